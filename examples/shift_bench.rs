@@ -0,0 +1,73 @@
+/*
+ * Compare the in place Image::shift against the previous version that allocated
+ * a new buffer for every shift.
+ *
+ *   cargo run --release --example shift_bench
+ */
+
+use eframe::egui::Color32;
+use egui_image::{EdgeMode, Image};
+use std::time::Instant;
+
+/// The original implementation
+fn shift_copy(image: &mut Image, mut shift_x: i32, mut shift_y: i32) {
+    let mut shifted = vec![Color32::BLUE; image.pixels.len()];
+
+    let width = image.size.0;
+    while shift_x < 0 {
+        shift_x += width as i32;
+    }
+    let shift_x = shift_x as usize;
+
+    let height = image.size.1;
+    while shift_y < 0 {
+        shift_y += height as i32;
+    }
+    let shift_y = shift_y as usize;
+
+    for y in 0..height {
+        for x in 0..width {
+            let dst_x = (x + shift_x) % width;
+            let dst_y = (y + shift_y) % height;
+            let dst_ind = dst_y * width + dst_x;
+            let ind = y * width + x;
+            shifted[dst_ind] = image.pixels[ind];
+        }
+    }
+    image.pixels = shifted;
+}
+
+fn bench(name: &str, iterations: u32, mut f: impl FnMut()) {
+    let t0 = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    println!("{:<24} {:?} per shift", name, t0.elapsed() / iterations);
+}
+
+fn main() {
+    let size = (1920, 1080);
+    let pixels = (0..size.0 * size.1)
+        .map(|i| Color32::from_gray((i % 251) as u8))
+        .collect();
+    let mut image = Image { size, pixels };
+    let iterations = 50;
+
+    let mut reference = image.clone();
+    shift_copy(&mut reference, -7, 3);
+    let mut shifted = image.clone();
+    shifted.shift(-7, 3);
+    assert!(reference.pixels == shifted.pixels);
+
+    bench("copy", iterations, || shift_copy(&mut image, 1, 1));
+    bench("in place wrap", iterations, || image.shift(1, 1));
+    bench("in place clamp", iterations, || {
+        image.shift_edge(1, 1, EdgeMode::Clamp)
+    });
+    bench("in place fill", iterations, || {
+        image.shift_edge(1, 1, EdgeMode::Fill(Color32::BLUE))
+    });
+    bench("sub-pixel wrap", iterations, || {
+        image.shift_subpixel(0.5, 0.25, EdgeMode::Wrap)
+    });
+}
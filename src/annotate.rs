@@ -0,0 +1,667 @@
+/*
+ * Annotation layer for labelling images: bounding boxes, keypoints and polygons
+ * with class labels, kept in image pixel coordinates so they stay in place as the
+ * view is zoomed. They are saved next to the image as json, or as a coco style
+ * dataset with a single image.
+ *
+ * The mouse handling follows the egui Painting example, like painting.rs.
+ */
+
+use eframe::egui::{self, Color32, Pos2, Rect, Vec2};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnnotationShape {
+    Box { min: [f32; 2], max: [f32; 2] },
+    Keypoint { pos: [f32; 2] },
+    Polygon { points: Vec<[f32; 2]> },
+}
+
+impl AnnotationShape {
+    /// Smallest rectangle containing the shape as min and max corners
+    pub fn bounds(&self) -> ([f32; 2], [f32; 2]) {
+        match self {
+            AnnotationShape::Box { min, max } => (*min, *max),
+            AnnotationShape::Keypoint { pos } => (*pos, *pos),
+            AnnotationShape::Polygon { points } => points.iter().fold(
+                ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]),
+                |(min, max), p| {
+                    (
+                        [min[0].min(p[0]), min[1].min(p[1])],
+                        [max[0].max(p[0]), max[1].max(p[1])],
+                    )
+                },
+            ),
+        }
+    }
+
+    /// Whether the image position is on the shape, within tolerance pixels for keypoints
+    pub fn contains(&self, pos: [f32; 2], tolerance: f32) -> bool {
+        match self {
+            AnnotationShape::Box { min, max } => {
+                pos[0] >= min[0] && pos[0] <= max[0] && pos[1] >= min[1] && pos[1] <= max[1]
+            }
+            AnnotationShape::Keypoint { pos: p } => {
+                (pos[0] - p[0]).hypot(pos[1] - p[1]) <= tolerance
+            }
+            AnnotationShape::Polygon { points } => {
+                // even-odd rule
+                let mut inside = false;
+                for (ind, p0) in points.iter().enumerate() {
+                    let p1 = points[(ind + 1) % points.len()];
+                    if (p0[1] <= pos[1]) != (p1[1] <= pos[1]) {
+                        let x = p0[0] + (pos[1] - p0[1]) / (p1[1] - p0[1]) * (p1[0] - p0[0]);
+                        if pos[0] < x {
+                            inside = !inside;
+                        }
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    pub fn translate(&mut self, delta: [f32; 2]) {
+        let shift = |p: &mut [f32; 2]| {
+            p[0] += delta[0];
+            p[1] += delta[1];
+        };
+        match self {
+            AnnotationShape::Box { min, max } => {
+                shift(min);
+                shift(max);
+            }
+            AnnotationShape::Keypoint { pos } => shift(pos),
+            AnnotationShape::Polygon { points } => points.iter_mut().for_each(shift),
+        }
+    }
+
+    /// Area in square pixels, zero for keypoints
+    pub fn area(&self) -> f32 {
+        match self {
+            AnnotationShape::Box { min, max } => (max[0] - min[0]) * (max[1] - min[1]),
+            AnnotationShape::Keypoint { .. } => 0.0,
+            AnnotationShape::Polygon { points } => {
+                let twice: f32 = points
+                    .iter()
+                    .zip(points.iter().cycle().skip(1))
+                    .map(|(p0, p1)| p0[0] * p1[1] - p1[0] * p0[1])
+                    .sum();
+                twice.abs() / 2.0
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub label: String,
+    pub shape: AnnotationShape,
+}
+
+/// All of the annotations for one image
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotations {
+    pub image: String,
+    pub size: (usize, usize),
+    pub annotations: Vec<Annotation>,
+}
+
+impl Annotations {
+    pub fn save_json(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename)
+            .map_err(|why| format!("couldn't create {}: {}", filename, why))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)
+            .map_err(|why| format!("couldn't write {}: {}", filename, why))
+    }
+
+    pub fn load_json(filename: &str) -> Result<Self, String> {
+        let file =
+            File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|why| format!("couldn't parse {}: {}", filename, why))
+    }
+
+    /// The labels in order of first use, their index + 1 is the coco category id
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = Vec::new();
+        for annotation in self.annotations.iter() {
+            if !labels.contains(&annotation.label) {
+                labels.push(annotation.label.clone());
+            }
+        }
+        labels
+    }
+
+    /// A coco style dataset with this as the only image, boxes have a bbox only,
+    /// polygons a segmentation and keypoints a single visible keypoint.
+    pub fn to_coco(&self) -> serde_json::Value {
+        let labels = self.labels();
+        let categories: Vec<_> = labels
+            .iter()
+            .enumerate()
+            .map(|(ind, name)| json!({ "id": ind + 1, "name": name }))
+            .collect();
+        let annotations: Vec<_> = self
+            .annotations
+            .iter()
+            .enumerate()
+            .map(|(ind, annotation)| {
+                let (min, max) = annotation.shape.bounds();
+                let category_id = labels.iter().position(|l| *l == annotation.label).unwrap() + 1;
+                let mut value = json!({
+                    "id": ind + 1,
+                    "image_id": 1,
+                    "category_id": category_id,
+                    "bbox": [min[0], min[1], max[0] - min[0], max[1] - min[1]],
+                    "area": annotation.shape.area(),
+                    "iscrowd": 0,
+                });
+                match &annotation.shape {
+                    AnnotationShape::Box { .. } => {}
+                    AnnotationShape::Keypoint { pos } => {
+                        value["keypoints"] = json!([pos[0], pos[1], 2]);
+                        value["num_keypoints"] = json!(1);
+                    }
+                    AnnotationShape::Polygon { points } => {
+                        let flat: Vec<f32> =
+                            points.iter().flat_map(|p| p.iter().copied()).collect();
+                        value["segmentation"] = json!([flat]);
+                    }
+                }
+                value
+            })
+            .collect();
+        json!({
+            "images": [{
+                "id": 1,
+                "file_name": self.image,
+                "width": self.size.0,
+                "height": self.size.1,
+            }],
+            "categories": categories,
+            "annotations": annotations,
+        })
+    }
+
+    /// Read the annotations of the first image of a coco style dataset
+    pub fn from_coco(coco: &serde_json::Value) -> Result<Self, String> {
+        let image = coco["images"]
+            .get(0)
+            .ok_or_else(|| "coco dataset has no images".to_string())?;
+        let image_id = &image["id"];
+        let as_usize = |v: &serde_json::Value| v.as_u64().unwrap_or(0) as usize;
+        // a missing array is empty, but one with something other than numbers is an error
+        let floats = |v: &serde_json::Value| -> Result<Vec<f32>, String> {
+            v.as_array()
+                .into_iter()
+                .flatten()
+                .map(|f| {
+                    f.as_f64()
+                        .map(|f| f as f32)
+                        .ok_or_else(|| format!("{} isn't a number", f))
+                })
+                .collect()
+        };
+
+        let mut annotations = Vec::new();
+        for value in coco["annotations"].as_array().into_iter().flatten() {
+            if &value["image_id"] != image_id {
+                continue;
+            }
+            let label = coco["categories"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|c| c["id"] == value["category_id"])
+                .and_then(|c| c["name"].as_str())
+                .unwrap_or("unknown")
+                .to_string();
+            let segmentation = floats(&value["segmentation"][0])?;
+            let keypoints = floats(&value["keypoints"])?;
+            let bbox = floats(&value["bbox"])?;
+            if segmentation.len() % 2 != 0 {
+                return Err(format!(
+                    "annotation {} has an odd number of segmentation values",
+                    value["id"]
+                ));
+            }
+            let shape = if segmentation.len() >= 6 {
+                AnnotationShape::Polygon {
+                    points: segmentation.chunks(2).map(|p| [p[0], p[1]]).collect(),
+                }
+            } else if keypoints.len() >= 2 {
+                AnnotationShape::Keypoint {
+                    pos: [keypoints[0], keypoints[1]],
+                }
+            } else if bbox.len() == 4 {
+                AnnotationShape::Box {
+                    min: [bbox[0], bbox[1]],
+                    max: [bbox[0] + bbox[2], bbox[1] + bbox[3]],
+                }
+            } else {
+                return Err(format!("annotation {} has no shape", value["id"]));
+            };
+            annotations.push(Annotation { label, shape });
+        }
+
+        Ok(Self {
+            image: image["file_name"].as_str().unwrap_or_default().to_string(),
+            size: (as_usize(&image["width"]), as_usize(&image["height"])),
+            annotations,
+        })
+    }
+
+    pub fn save_coco(&self, filename: &str) -> Result<(), String> {
+        let file = File::create(filename)
+            .map_err(|why| format!("couldn't create {}: {}", filename, why))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self.to_coco())
+            .map_err(|why| format!("couldn't write {}: {}", filename, why))
+    }
+
+    pub fn load_coco(filename: &str) -> Result<Self, String> {
+        let file =
+            File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+        let coco: serde_json::Value = serde_json::from_reader(BufReader::new(file))
+            .map_err(|why| format!("couldn't parse {}: {}", filename, why))?;
+        Self::from_coco(&coco)
+    }
+}
+
+/// Stable color for a label
+fn label_color(label: &str) -> Color32 {
+    const PALETTE: [Color32; 6] = [
+        Color32::from_rgb(255, 80, 80),
+        Color32::from_rgb(80, 220, 80),
+        Color32::from_rgb(80, 160, 255),
+        Color32::from_rgb(255, 200, 0),
+        Color32::from_rgb(230, 80, 230),
+        Color32::from_rgb(0, 220, 220),
+    ];
+    let hash = label.bytes().fold(0usize, |acc, b| {
+        acc.wrapping_mul(31).wrapping_add(b as usize)
+    });
+    PALETTE[hash % PALETTE.len()]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnnotateMode {
+    Select,
+    Box,
+    Keypoint,
+    Polygon,
+}
+
+pub struct AnnotationTool {
+    pub enabled: bool,
+    pub mode: AnnotateMode,
+    /// label given to new annotations
+    label: String,
+    pub annotations: Annotations,
+    selected: Option<usize>,
+    /// image position where the current box drag started
+    drag_start: Option<[f32; 2]>,
+    /// vertices of the polygon being drawn
+    polygon: Vec<[f32; 2]>,
+    status: String,
+}
+
+impl Default for AnnotationTool {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: AnnotateMode::Box,
+            label: "object".to_string(),
+            annotations: Default::default(),
+            selected: None,
+            drag_start: None,
+            polygon: Vec::new(),
+            status: String::new(),
+        }
+    }
+}
+
+impl AnnotationTool {
+    fn json_filename(&self) -> String {
+        format!("{}.json", self.annotations.image)
+    }
+
+    fn coco_filename(&self) -> String {
+        format!("{}.coco.json", self.annotations.image)
+    }
+
+    /// Start on another image, loading the annotations saved next to it if there are any
+    pub fn open_image(&mut self, filename: &str, size: (usize, usize)) {
+        self.selected = None;
+        self.drag_start = None;
+        self.polygon.clear();
+        let json_filename = format!("{}.json", filename);
+        self.annotations = if Path::new(&json_filename).exists() {
+            match Annotations::load_json(&json_filename) {
+                Ok(annotations) => {
+                    self.status = format!("loaded {}", json_filename);
+                    annotations
+                }
+                Err(why) => {
+                    self.status = why;
+                    Default::default()
+                }
+            }
+        } else {
+            self.status.clear();
+            Default::default()
+        };
+        self.set_image(filename, size);
+    }
+
+    /// Keep the annotations associated with the image being viewed
+    pub fn set_image(&mut self, filename: &str, size: (usize, usize)) {
+        self.annotations.image = filename.to_string();
+        self.annotations.size = size;
+    }
+
+    /// Mode, label and file controls and the list of annotations
+    pub fn ui_control(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "annotate");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, AnnotateMode::Select, "select");
+            ui.radio_value(&mut self.mode, AnnotateMode::Box, "box");
+            ui.radio_value(&mut self.mode, AnnotateMode::Keypoint, "keypoint");
+            ui.radio_value(&mut self.mode, AnnotateMode::Polygon, "polygon");
+        });
+        ui.horizontal(|ui| {
+            ui.label("label");
+            ui.text_edit_singleline(&mut self.label);
+        });
+        if self.mode == AnnotateMode::Polygon {
+            ui.horizontal(|ui| {
+                ui.label(format!("{} points", self.polygon.len()));
+                if ui.button("finish").clicked {
+                    self.finish_polygon();
+                }
+                if ui.button("cancel").clicked {
+                    self.polygon.clear();
+                }
+            });
+        }
+
+        ui.separator();
+        let mut delete = None;
+        let selected = &mut self.selected;
+        for (ind, annotation) in self.annotations.annotations.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let kind = match annotation.shape {
+                    AnnotationShape::Box { .. } => "box",
+                    AnnotationShape::Keypoint { .. } => "keypoint",
+                    AnnotationShape::Polygon { .. } => "polygon",
+                };
+                if ui
+                    .selectable_label(*selected == Some(ind), format!("{} {}", ind, kind))
+                    .clicked
+                {
+                    *selected = Some(ind);
+                }
+                ui.text_edit_singleline(&mut annotation.label);
+                if ui.button("delete").clicked {
+                    delete = Some(ind);
+                }
+            });
+        }
+        if let Some(ind) = delete {
+            self.delete(ind);
+        }
+
+        ui.separator();
+        ui.label(format!("file {}", self.json_filename()));
+        ui.horizontal(|ui| {
+            if ui.button("save json").clicked {
+                self.status = match self.annotations.save_json(&self.json_filename()) {
+                    Ok(()) => format!("saved {}", self.json_filename()),
+                    Err(why) => why,
+                };
+            }
+            if ui.button("load json").clicked {
+                self.status = match Annotations::load_json(&self.json_filename()) {
+                    Ok(annotations) => {
+                        self.annotations = annotations;
+                        format!("loaded {}", self.json_filename())
+                    }
+                    Err(why) => why,
+                };
+            }
+            if ui.button("save coco").clicked {
+                self.status = match self.annotations.save_coco(&self.coco_filename()) {
+                    Ok(()) => format!("saved {}", self.coco_filename()),
+                    Err(why) => why,
+                };
+            }
+            if ui.button("load coco").clicked {
+                self.status = match Annotations::load_coco(&self.coco_filename()) {
+                    Ok(annotations) => {
+                        self.annotations = annotations;
+                        format!("loaded {}", self.coco_filename())
+                    }
+                    Err(why) => why,
+                };
+            }
+        });
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+    }
+
+    fn delete(&mut self, ind: usize) {
+        self.annotations.annotations.remove(ind);
+        self.selected = None;
+    }
+
+    fn finish_polygon(&mut self) {
+        if self.polygon.len() >= 3 {
+            let points = std::mem::take(&mut self.polygon);
+            self.add(AnnotationShape::Polygon { points });
+        }
+    }
+
+    fn add(&mut self, shape: AnnotationShape) {
+        self.annotations.annotations.push(Annotation {
+            label: self.label.clone(),
+            shape,
+        });
+        self.selected = Some(self.annotations.annotations.len() - 1);
+    }
+
+    /// Handle the mouse over the image and draw the annotations on top of it,
+    /// rect is where the image is on screen and scale the screen points per image pixel.
+    pub fn ui_content(
+        &mut self,
+        ui: &mut egui::Ui,
+        response: &egui::Response,
+        rect: Rect,
+        scale: Vec2,
+    ) {
+        let to_image = |pos: Pos2| {
+            let p = pos - rect.min;
+            [p.x / scale.x, p.y / scale.y]
+        };
+        let to_screen = |p: [f32; 2]| rect.min + Vec2::new(p[0] * scale.x, p[1] * scale.y);
+
+        if self.enabled {
+            let input = ui.input();
+            let mouse = input.mouse.pos.map(to_image);
+            let tolerance = 6.0 / scale.x.min(scale.y);
+            match self.mode {
+                AnnotateMode::Select => {
+                    if let (true, Some(pos)) = (input.mouse.pressed && response.hovered, mouse) {
+                        self.selected = self
+                            .annotations
+                            .annotations
+                            .iter()
+                            .rposition(|a| a.shape.contains(pos, tolerance));
+                    }
+                    if response.active {
+                        if let Some(ind) = self.selected {
+                            let delta = input.mouse.delta;
+                            self.annotations.annotations[ind]
+                                .shape
+                                .translate([delta.x / scale.x, delta.y / scale.y]);
+                        }
+                    }
+                }
+                AnnotateMode::Box => {
+                    if input.mouse.pressed && response.hovered {
+                        self.drag_start = mouse;
+                    }
+                    if input.mouse.released {
+                        if let (Some(start), Some(end)) = (self.drag_start.take(), mouse) {
+                            let min = [start[0].min(end[0]), start[1].min(end[1])];
+                            let max = [start[0].max(end[0]), start[1].max(end[1])];
+                            if max[0] > min[0] && max[1] > min[1] {
+                                self.add(AnnotationShape::Box { min, max });
+                            }
+                        }
+                    }
+                }
+                AnnotateMode::Keypoint => {
+                    if let (true, Some(pos)) = (response.clicked, mouse) {
+                        self.add(AnnotationShape::Keypoint { pos });
+                    }
+                }
+                AnnotateMode::Polygon => {
+                    if response.double_clicked {
+                        self.finish_polygon();
+                    } else if let (true, Some(pos)) = (response.clicked, mouse) {
+                        // the first click of a double click has already been added
+                        let repeated = self.polygon.last().is_some_and(|last| {
+                            (pos[0] - last[0]).hypot(pos[1] - last[1]) <= tolerance
+                        });
+                        if !repeated {
+                            self.polygon.push(pos);
+                        }
+                    }
+                    if input.key_pressed(egui::Key::Enter) {
+                        self.finish_polygon();
+                    }
+                    if input.key_pressed(egui::Key::Escape) {
+                        self.polygon.clear();
+                    }
+                }
+            }
+            if response.hovered && input.key_pressed(egui::Key::Delete) {
+                if let Some(ind) = self.selected {
+                    self.delete(ind);
+                }
+            }
+
+            // the box being dragged out
+            if let (Some(start), Some(end)) = (self.drag_start, mouse) {
+                let (start, end) = (to_screen(start), to_screen(end));
+                let drag = Rect::from_min_max(
+                    Pos2::new(start.x.min(end.x), start.y.min(end.y)),
+                    Pos2::new(start.x.max(end.x), start.y.max(end.y)),
+                );
+                ui.painter().rect_stroke(drag, 0.0, (1.0, Color32::WHITE));
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        for (ind, annotation) in self.annotations.annotations.iter().enumerate() {
+            let color = label_color(&annotation.label);
+            let width = if self.selected == Some(ind) { 3.0 } else { 1.5 };
+            match &annotation.shape {
+                AnnotationShape::Box { min, max } => {
+                    let screen = Rect::from_min_max(to_screen(*min), to_screen(*max));
+                    painter.rect_stroke(screen, 0.0, (width, color));
+                }
+                AnnotationShape::Keypoint { pos } => {
+                    painter.circle_filled(to_screen(*pos), 2.0 * width, color);
+                }
+                AnnotationShape::Polygon { points } => {
+                    for (p0, p1) in points.iter().zip(points.iter().cycle().skip(1)) {
+                        painter.line_segment([to_screen(*p0), to_screen(*p1)], (width, color));
+                    }
+                }
+            }
+            let (min, _) = annotation.shape.bounds();
+            painter.text(
+                to_screen(min),
+                egui::Align2::LEFT_BOTTOM,
+                &annotation.label,
+                egui::TextStyle::Small,
+                color,
+            );
+        }
+        for pair in self.polygon.windows(2) {
+            painter.line_segment(
+                [to_screen(pair[0]), to_screen(pair[1])],
+                (1.0, Color32::WHITE),
+            );
+        }
+        for p in self.polygon.iter() {
+            painter.circle_filled(to_screen(*p), 2.0, Color32::WHITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations() -> Annotations {
+        let annotation = |label: &str, shape| Annotation {
+            label: label.to_string(),
+            shape,
+        };
+        Annotations {
+            image: "street.png".to_string(),
+            size: (640, 480),
+            annotations: vec![
+                annotation(
+                    "car",
+                    AnnotationShape::Box {
+                        min: [10.0, 20.0],
+                        max: [110.0, 70.0],
+                    },
+                ),
+                annotation(
+                    "person",
+                    AnnotationShape::Keypoint {
+                        pos: [300.5, 200.0],
+                    },
+                ),
+                annotation(
+                    "car",
+                    AnnotationShape::Polygon {
+                        points: vec![[0.0, 0.0], [40.0, 0.0], [40.0, 30.0], [0.0, 30.0]],
+                    },
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn coco_round_trip() {
+        let annotations = annotations();
+        let coco = annotations.to_coco();
+        assert_eq!(coco["categories"][1]["name"], "person");
+        assert_eq!(coco["annotations"][2]["category_id"], 1);
+        assert_eq!(coco["annotations"][2]["area"], 1200.0);
+        assert_eq!(Annotations::from_coco(&coco).unwrap(), annotations);
+    }
+
+    #[test]
+    fn bad_segmentations() {
+        let mut coco = annotations().to_coco();
+        coco["annotations"][2]["segmentation"] = json!([[0, 0, 40, 0, 40, 30, 0]]);
+        assert!(Annotations::from_coco(&coco).is_err());
+        // a string among the values is an error rather than being skipped
+        coco["annotations"][2]["segmentation"] = json!([[0, 0, 40, 0, 40, 30, 0, "30"]]);
+        assert!(Annotations::from_coco(&coco).is_err());
+        coco["annotations"][2]["segmentation"] = json!([[0, 0, 40, 0, 40, 30, 0, 30]]);
+        assert!(Annotations::from_coco(&coco).is_ok());
+    }
+}
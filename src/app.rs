@@ -1,6 +1,23 @@
 use eframe::{egui, epi};
-use image::GenericImageView;
 // use std::fs::File;
+use crate::annotate::AnnotationTool;
+use crate::blend::CombineTool;
+use crate::browser::FolderBrowser;
+use crate::compare::CompareView;
+use crate::edge::EdgeMode;
+use crate::filter::FilterTool;
+use crate::geometry::GeometryTool;
+use crate::history::History;
+use crate::image_io::ImagePicker;
+use crate::job::Job;
+use crate::morphology::MorphologyTool;
+use crate::painting::Painting;
+use crate::raw::{describe_pixel, load_native, LevelsTool, Loaded, RawImage, ToneMap};
+use crate::raw_import::RawImportTool;
+use crate::record::Recorder;
+use crate::roi::RoiTool;
+use crate::sequence::SequencePlayer;
+use crate::shift::RunningShift;
 use crate::utility::{Image, TexMngr};
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -11,28 +28,79 @@ pub struct ImageApp {
     x_scale: f32,
     y_scale: f32,
     y_ind: usize,
-    // filename: String,
+    /// Pixels to shift the image by each frame
+    shift: (f32, f32),
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    shift_edge: EdgeMode,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    running_shift: RunningShift,
+    filename: String,
     #[cfg_attr(feature = "persistence", serde(skip))]
     image: Image,
+    /// Counts the changes to image, so tools can tell when what they made from it is stale
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    generation: u64,
+    /// The file being decoded in the background, it replaces filename and image when done
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    loading: Option<(String, Job<Loaded>)>,
+    /// Native values of a loaded image deeper than 8 bits, image is drawn from them through levels
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    raw: Option<RawImage>,
+    /// generation of the image when it was last drawn from raw, the native values
+    /// only line up with the image until it is edited or shifted
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    raw_generation: u64,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    levels: LevelsTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    raw_import: RawImportTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    browser: FolderBrowser,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    sequence: SequencePlayer,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    geometry: GeometryTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    filter: FilterTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    morphology: MorphologyTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    combine: CombineTool,
+    /// Image to combine or compare with the current one
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    second: ImagePicker,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    compare: CompareView,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    annotate: AnnotationTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    painting: Painting,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    roi: RoiTool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    history: History,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    recorder: Recorder,
     #[cfg_attr(feature = "persistence", serde(skip))]
     tex_mngr: TexMngr,
 }
 
+/// Decode the image file in the background
+fn load_job(filename: &str) -> Job<Loaded> {
+    let filename = filename.to_string();
+    Job::spawn(format!("loading {}", filename), move |progress| {
+        load_native(&filename, progress)
+    })
+}
+
 impl Default for ImageApp {
     fn default() -> Self {
-        // Decode the jpeg using image::GenericImageView, then paint into the screen
+        // Decode the jpeg in the background, then paint into the screen
         // following egui url image loading example in egui/egui_demo_lib/src/app/http_app.rs
         let filename = "data/gradient_rect.jpg";
-        let image = image::open(filename).unwrap();
-        let image_buffer = image.to_rgba8();
-        let size = (image.width() as usize, image.height() as usize);
-        println!("{} {:?}", filename, size);
-        let pixels = image_buffer.into_vec();
-        assert_eq!(size.0 * size.1 * 4, pixels.len());
-        let pixels = pixels
-            .chunks(4)
-            .map(|p| egui::Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]))
-            .collect();
+        // shown until the real image has loaded
+        let size = (256, 256);
+        let pixels = vec![egui::Color32::BLACK; size.0 * size.1];
 
         Self {
             // Example stuff:
@@ -40,14 +108,61 @@ impl Default for ImageApp {
             x_scale: 4.0,
             y_scale: 4.0,
             y_ind: 30,
-            // filename: (&filename).to_string(),
+            shift: (1.0, 0.0),
+            shift_edge: EdgeMode::Wrap,
+            running_shift: Default::default(),
+            filename: filename.to_string(),
             image: Image { size, pixels },
+            generation: 0,
+            loading: Some((filename.to_string(), load_job(filename))),
+            raw: None,
+            raw_generation: 0,
+            levels: Default::default(),
+            raw_import: Default::default(),
+            browser: Default::default(),
+            sequence: Default::default(),
+            geometry: Default::default(),
+            filter: Default::default(),
+            morphology: Default::default(),
+            combine: Default::default(),
+            second: Default::default(),
+            compare: Default::default(),
+            annotate: Default::default(),
+            painting: Default::default(),
+            roi: Default::default(),
+            history: Default::default(),
+            recorder: Default::default(),
             tex_mngr: Default::default(),
         }
     }
 }
 
 impl ImageApp {
+    /// Native values of the image, only while it is still as it was drawn from them
+    fn native(&self) -> Option<&RawImage> {
+        self.raw
+            .as_ref()
+            .filter(|_| self.generation == self.raw_generation)
+    }
+
+    /// Move the image on by the shift per frame, unless it has to hold still: while
+    /// annotating, painting, measuring, previewing or comparing it, while a mask made
+    /// from it is overlaid, or while its native values line up with it
+    fn shift_frame(&mut self) {
+        let hold = self.annotate.enabled
+            || self.painting.enabled
+            || self.roi.enabled
+            || self.filter.preview
+            || self.morphology.is_overlaid(&self.image)
+            || (self.compare.open && self.second.image.is_some())
+            || self.native().is_some();
+        if !hold && self.shift != (0.0, 0.0) {
+            let (shift, edge) = (self.shift, self.shift_edge);
+            self.running_shift
+                .step(&mut self.image, &mut self.generation, shift, edge);
+        }
+    }
+
     // TODO(lucasw) trying to copy demo code for dancing strings to get a regular timer update
     // even if window isn't active, but this isn't getting called by anything, there is special
     // demo code infrastructure involved there.
@@ -78,16 +193,102 @@ impl epi::App for ImageApp {
     /// Called each time the UI needs repainting, which may be many times per second.
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::CtxRef, frame: &mut epi::Frame<'_>) {
+        // TODO(lucsw) this is only happening when there is a mouse motion or other change
+        // over the window- as noted above the repaint needs to be triggered.
+        // update the image pixels
+        self.shift_frame();
+
         let ImageApp {
             label,
             x_scale,
             y_scale,
             y_ind,
-            // filename,
+            shift,
+            shift_edge,
+            running_shift: _,
+            filename,
             image,
+            generation,
+            loading,
+            raw,
+            raw_generation,
+            levels,
+            raw_import,
+            browser,
+            sequence,
+            geometry,
+            filter,
+            morphology,
+            combine,
+            second,
+            compare,
+            annotate,
+            painting,
+            roi,
+            history,
+            recorder,
             tex_mngr,
         } = self;
 
+        // the filename, image and native values of a newly opened file, they only replace
+        // the current ones once the file has loaded
+        let mut opened = None;
+        let finished = loading
+            .as_ref()
+            .and_then(|(path, job)| job.poll().map(|result| (path.clone(), result)));
+        if let Some((path, result)) = finished {
+            match result {
+                Ok(Loaded::Image(loaded)) => opened = Some((path, loaded, None)),
+                Ok(Loaded::Raw(loaded)) => {
+                    opened = Some((path, levels.reset(&loaded), Some(loaded)))
+                }
+                Err(why) => println!("{}", why),
+            }
+            *loading = None;
+        }
+
+        if history.handle_keys(ctx, image) {
+            *generation += 1;
+        }
+
+        let mut chosen = browser.handle_keys(ctx);
+        egui::Window::new("Folder").show(ctx, |ui| {
+            if let Some(path) = browser.ui(ui, frame) {
+                chosen = Some(path);
+            }
+        });
+        egui::Window::new("Playback").show(ctx, |ui| {
+            if let Some((path, frame)) = sequence.ui(ui) {
+                opened = Some((path, frame, None));
+            }
+        });
+
+        egui::Window::new("Raw import").show(ctx, |ui| {
+            if let Some((path, imported)) = raw_import.ui(ui) {
+                opened = Some((path, imported, None));
+            }
+        });
+
+        if let Some(path) = chosen {
+            if let Some((_, job)) = loading {
+                job.cancel();
+            }
+            *loading = Some((path.clone(), load_job(&path)));
+        }
+
+        // edits, regions and annotations of the previous image don't carry over
+        if let Some((path, opened_image, opened_raw)) = opened {
+            *filename = path;
+            *image = opened_image;
+            *raw = opened_raw;
+            *generation += 1;
+            *raw_generation = *generation;
+            history.clear();
+            roi.reset();
+            morphology.reset();
+            annotate.open_image(filename, image.size);
+        }
+
         // Examples of how to create different panels and windows.
         // Pick whichever suits you.
         // Tip: a good default choice is to just keep the `CentralPanel`.
@@ -102,10 +303,6 @@ impl epi::App for ImageApp {
                     ui.text_edit_singleline(label);
                 });
 
-                if ui.button("Increment").clicked {
-                    *x_scale += 1.0;
-                }
-
                 ui.with_layout(egui::Layout::bottom_up(egui::Align::Center), |ui| {
                     ui.add(
                         egui::Hyperlink::new("https://github.com/emilk/egui/")
@@ -126,6 +323,96 @@ impl epi::App for ImageApp {
             });
         }
 
+        egui::TopPanel::top("toolbar").show(ctx, |ui| {
+            if let Some((name, edited)) = geometry.ui(ui, image) {
+                println!("{}", name);
+                history.record(&name, image);
+                *image = edited;
+                *generation += 1;
+            }
+        });
+
+        egui::Window::new("Levels").show(ctx, |ui| match raw {
+            Some(raw) => {
+                if let Some((name, drawn)) = levels.ui(ui, raw) {
+                    println!("{}", name);
+                    history.record(&name, image);
+                    *image = drawn;
+                    *generation += 1;
+                    *raw_generation = *generation;
+                }
+            }
+            None => {
+                ui.label("the image is 8 bit, there are no deeper values to map");
+            }
+        });
+
+        egui::Window::new("Filter").show(ctx, |ui| {
+            if let Some((name, filtered)) = filter.ui(ui, image, *generation) {
+                println!("{}", name);
+                history.record(&name, image);
+                *image = filtered;
+                *generation += 1;
+            }
+        });
+
+        egui::Window::new("Threshold and morphology").show(ctx, |ui| {
+            if let Some((name, edited)) = morphology.ui(ui, image) {
+                println!("{}", name);
+                history.record(&name, image);
+                *image = edited;
+                *generation += 1;
+            }
+        });
+
+        egui::Window::new("Combine with...").show(ctx, |ui| {
+            second.ui(ui, image);
+            if second.image.is_some() {
+                ui.checkbox(&mut compare.open, "compare");
+            }
+            ui.separator();
+            if let Some((name, combined)) = combine.ui(ui, image, second.image.as_ref()) {
+                println!("{}", name);
+                history.record(&name, image);
+                *image = combined;
+                *generation += 1;
+            }
+        });
+
+        annotate.set_image(filename, image.size);
+        egui::Window::new("Annotations").show(ctx, |ui| {
+            annotate.ui_control(ui);
+        });
+
+        egui::Window::new("Painting").show(ctx, |ui| {
+            painting.ui_control(ui, image);
+        });
+
+        egui::Window::new("Region of interest").show(ctx, |ui| {
+            roi.ui_control(ui, image, *generation);
+        });
+
+        egui::Window::new("Record").show(ctx, |ui| {
+            recorder.ui(ui);
+        });
+
+        egui::Window::new("History").show(ctx, |ui| {
+            if history.ui(ui, image) {
+                *generation += 1;
+            }
+        });
+
+        if let Some(other) = &second.image {
+            // the window close button unchecks compare
+            let mut open = compare.open;
+            egui::Window::new("Compare")
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    compare.ui(ui, frame, image, *generation, other, second.generation);
+                });
+            compare.open = open;
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Egui Image");
             ui.hyperlink("https://github.com/lucasw/egui_image");
@@ -137,27 +424,72 @@ impl epi::App for ImageApp {
 
             ui.separator();
 
+            if let Some((_, job)) = loading {
+                job.ui(ui);
+            }
+
             ui.heading("Central Panel");
             ui.label("The central panel the region left after adding TopPanel's and SidePanel's");
             ui.label("It is often a great place for big things, like drawings:");
-            ui.add(egui::Slider::f32(x_scale, 0.1..=10.0).text("x scale"));
-            ui.add(egui::Slider::f32(y_scale, 0.1..=10.0).text("y scale"));
+            if raw.is_some() && levels.levels.tone != ToneMap::Levels {
+                // previewed until applied in the levels window, like the other levels
+                let exposure = &mut levels.levels.exposure;
+                ui.add(egui::Slider::f32(exposure, -10.0..=10.0).text("exposure"));
+            }
+            // x_scale and y_scale only zoom the displayed image
+            ui.add(egui::Slider::f32(x_scale, 0.1..=10.0).text("x zoom"));
+            ui.add(egui::Slider::f32(y_scale, 0.1..=10.0).text("y zoom"));
 
             ui.add(egui::Slider::usize(y_ind, 0..=(image.size.1 - 1)).text("y ind"));
 
+            ui.horizontal(|ui| {
+                ui.label("shift per frame");
+                ui.add(egui::DragValue::f32(&mut shift.0).speed(0.05).prefix("x "));
+                ui.add(egui::DragValue::f32(&mut shift.1).speed(0.05).prefix("y "));
+                shift_edge.ui(ui, "shift_edge");
+            });
+
             egui::ScrollArea::auto_sized().show(ui, |ui| {
-                // TODO(lucsw) this is only happening when there is a mouse motion or other change
-                // over the window- as noted above the repaint needs to be triggered.
-                // update the image pixels
-                image.shift(1, 0);
                 let update = true;
 
-                if let Some(texture_id) = tex_mngr.texture(frame, update, image) {
+                let shown = match raw.as_ref().and_then(|raw| levels.preview(raw)) {
+                    Some(preview) => preview,
+                    None => filter.preview(image, *generation).unwrap_or(image),
+                };
+                let overlaid = morphology.overlay(shown);
+                let shown = overlaid.as_ref().unwrap_or(shown);
+                recorder.capture(shown, ui.input().time);
+                if let Some(texture_id) = tex_mngr.texture(frame, update, shown) {
                     let size = egui::Vec2::new(
                         image.size.0 as f32 * *x_scale,
                         image.size.1 as f32 * *y_scale,
                     );
-                    ui.image(texture_id, size);
+                    let (rect, response) =
+                        ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+                    egui::Image::new(texture_id, size).paint_at(ui, rect);
+                    let scale = egui::Vec2::new(*x_scale, *y_scale);
+                    annotate.ui_content(ui, &response, rect, scale);
+                    roi.ui_content(ui, &response, rect, scale);
+                    if painting.enabled && response.active {
+                        // the stroke in progress paints into the image
+                        *generation += 1;
+                    }
+                    if let Some((name, before)) =
+                        painting.ui_content(ui, &response, rect, scale, image)
+                    {
+                        println!("{}", name);
+                        history.record(&name, &before);
+                    }
+                    let mouse = ui.input().mouse.pos.filter(|_| response.hovered);
+                    if let Some(pos) = mouse {
+                        let x = ((pos.x - rect.min.x) / *x_scale).floor();
+                        let y = ((pos.y - rect.min.y) / *y_scale).floor();
+                        if x >= 0.0 && y >= 0.0 {
+                            let native = raw.as_ref().filter(|_| *generation == *raw_generation);
+                            let text = describe_pixel(image, native, x as usize, y as usize);
+                            response.on_hover_text(text);
+                        }
+                    }
                 }
             });
         });
@@ -177,67 +509,43 @@ impl epi::App for ImageApp {
     }
 }
 
-// ----------------------------------------------------------------------------
-
-/*
-/// Example code for painting on a canvas with your mouse
-#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
-struct Painting {
-    lines: Vec<Vec<egui::Vec2>>,
-    stroke: egui::Stroke,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raw::Depth;
 
-impl Default for Painting {
-    fn default() -> Self {
-        Self {
-            lines: Default::default(),
-            stroke: egui::Stroke::new(1.0, egui::Color32::LIGHT_BLUE),
+    #[test]
+    fn native_values_show_after_load() {
+        let mut app = ImageApp::default();
+        if let Some((_, job)) = app.loading.take() {
+            job.cancel();
         }
-    }
-}
-
-impl Painting {
-    pub fn ui_control(&mut self, ui: &mut egui::Ui) -> egui::Response {
-        ui.horizontal(|ui| {
-            self.stroke.ui(ui, "Stroke");
-            ui.separator();
-            if ui.button("Clear Painting").clicked {
-                self.lines.clear();
-            }
-        })
-        .1
-    }
-
-    pub fn ui_content(&mut self, ui: &mut egui::Ui) -> egui::Response {
-        let (response, painter) =
-            ui.allocate_painter(ui.available_size_before_wrap_finite(), egui::Sense::drag());
-        let rect = response.rect;
-
-        if self.lines.is_empty() {
-            self.lines.push(vec![]);
+        // loaded as update does when the load job finishes
+        let raw = RawImage {
+            size: (4, 2),
+            channels: 1,
+            depth: Depth::U16,
+            data: (0..8).map(|v| v as f32 * 1000.0).collect(),
+            maxval: None,
+        };
+        app.image = app.levels.reset(&raw);
+        app.raw = Some(raw);
+        app.generation += 1;
+        app.raw_generation = app.generation;
+
+        // the default shift per frame doesn't move it away from its values
+        assert_ne!(app.shift, (0.0, 0.0));
+        for _ in 0..3 {
+            app.shift_frame();
         }
-
-        let current_line = self.lines.last_mut().unwrap();
-
-        if response.active {
-            if let Some(mouse_pos) = ui.input().mouse.pos {
-                let canvas_pos = mouse_pos - rect.min;
-                if current_line.last() != Some(&canvas_pos) {
-                    current_line.push(canvas_pos);
-                }
-            }
-        } else if !current_line.is_empty() {
-            self.lines.push(vec![]);
-        }
-
-        for line in &self.lines {
-            if line.len() >= 2 {
-                let points: Vec<egui::Pos2> = line.iter().map(|p| rect.min + *p).collect();
-                painter.add(egui::PaintCmd::line(points, self.stroke));
-            }
-        }
-
-        response
+        let text = describe_pixel(&app.image, app.native(), 1, 1);
+        assert!(text.contains("16 bit 5000"), "{}", text);
+
+        // once edited the values no longer line up, and it shifts again
+        app.generation += 1;
+        assert!(app.native().is_none());
+        let generation = app.generation;
+        app.shift_frame();
+        assert_eq!(app.generation, generation + 1);
     }
 }
-*/
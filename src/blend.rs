@@ -0,0 +1,276 @@
+/*
+ * Pixel by pixel arithmetic between two images of the same size, for things like
+ * subtracting a background frame or blending a before and after.
+ *
+ * The color channels are combined and the result keeps the larger alpha of the
+ * two pixels, so differences of opaque images stay opaque.
+ */
+
+use crate::utility::Image;
+use eframe::egui::{self, Color32};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Combine {
+    /// Saturating sum
+    Add,
+    /// Saturating self - other
+    Subtract,
+    /// Product scaled so white is one
+    Multiply,
+    AbsDiff,
+    Min,
+    Max,
+    /// Weight of the other image from 0.0 to 1.0
+    Blend(f32),
+}
+
+impl Combine {
+    fn apply(&self, a: u8, b: u8) -> u8 {
+        let (a32, b32) = (a as u32, b as u32);
+        match self {
+            Combine::Add => a.saturating_add(b),
+            Combine::Subtract => a.saturating_sub(b),
+            Combine::Multiply => ((a32 * b32 + 127) / 255) as u8,
+            Combine::AbsDiff => (a as i32 - b as i32).unsigned_abs() as u8,
+            Combine::Min => a.min(b),
+            Combine::Max => a.max(b),
+            Combine::Blend(fr) => {
+                let fr = fr.clamp(0.0, 1.0);
+                (a as f32 * (1.0 - fr) + b as f32 * fr).round() as u8
+            }
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Combine::Blend(fr) => format!("blend {:.2}", fr),
+            op => format!("{:?}", op).to_lowercase(),
+        }
+    }
+}
+
+/// Error for operations that need two images of the same size
+pub fn check_same_size(a: &Image, b: &Image) -> Result<(), String> {
+    if a.size != b.size {
+        return Err(format!(
+            "image sizes differ: {}x{} and {}x{}",
+            a.size.0, a.size.1, b.size.0, b.size.1
+        ));
+    }
+    Ok(())
+}
+
+impl Image {
+    pub fn combine(&self, other: &Image, op: Combine) -> Result<Image, String> {
+        check_same_size(self, other)?;
+        let pixels = self
+            .pixels
+            .iter()
+            .zip(other.pixels.iter())
+            .map(|(p, q)| {
+                let (p, q) = (p.to_array(), q.to_array());
+                let alpha = match op {
+                    Combine::Blend(_) => op.apply(p[3], q[3]),
+                    _ => p[3].max(q[3]),
+                };
+                let channel = |c: usize| op.apply(p[c], q[c]).min(alpha);
+                Color32::from_rgba_premultiplied(channel(0), channel(1), channel(2), alpha)
+            })
+            .collect();
+        Ok(Image {
+            size: self.size,
+            pixels,
+        })
+    }
+
+    pub fn add(&self, other: &Image) -> Result<Image, String> {
+        self.combine(other, Combine::Add)
+    }
+
+    pub fn subtract(&self, other: &Image) -> Result<Image, String> {
+        self.combine(other, Combine::Subtract)
+    }
+
+    pub fn multiply(&self, other: &Image) -> Result<Image, String> {
+        self.combine(other, Combine::Multiply)
+    }
+
+    pub fn abs_diff(&self, other: &Image) -> Result<Image, String> {
+        self.combine(other, Combine::AbsDiff)
+    }
+
+    pub fn min(&self, other: &Image) -> Result<Image, String> {
+        self.combine(other, Combine::Min)
+    }
+
+    pub fn max(&self, other: &Image) -> Result<Image, String> {
+        self.combine(other, Combine::Max)
+    }
+
+    pub fn blend(&self, other: &Image, fr: f32) -> Result<Image, String> {
+        self.combine(other, Combine::Blend(fr))
+    }
+}
+
+/// Actions combining the current image with the second image
+pub struct CombineTool {
+    blend: f32,
+    error: Option<String>,
+}
+
+impl Default for CombineTool {
+    fn default() -> Self {
+        Self {
+            blend: 0.5,
+            error: None,
+        }
+    }
+}
+
+impl CombineTool {
+    /// Returns the name of the operation and the combined image when one is applied
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        image: &Image,
+        other: Option<&Image>,
+    ) -> Option<(String, Image)> {
+        let other = match other {
+            Some(other) => other,
+            None => {
+                ui.label("load a second image to combine with");
+                return None;
+            }
+        };
+
+        let mut op = None;
+        ui.horizontal(|ui| {
+            for choice in [
+                Combine::Add,
+                Combine::Subtract,
+                Combine::Multiply,
+                Combine::AbsDiff,
+                Combine::Min,
+                Combine::Max,
+            ]
+            .iter()
+            {
+                if ui.button(choice.name()).clicked {
+                    op = Some(*choice);
+                }
+            }
+        });
+        let blend = &mut self.blend;
+        ui.horizontal(|ui| {
+            ui.add(egui::Slider::f32(blend, 0.0..=1.0).text("weight"));
+            if ui.button("blend").clicked {
+                op = Some(Combine::Blend(*blend));
+            }
+        });
+
+        let mut edit = None;
+        if let Some(op) = op {
+            match image.combine(other, op) {
+                Ok(combined) => {
+                    edit = Some((format!("{} with other", op.name()), combined));
+                    self.error = None;
+                }
+                Err(why) => self.error = Some(why),
+            }
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(rgba: [u8; 4]) -> Image {
+        Image {
+            size: (1, 1),
+            pixels: vec![Color32::from_rgba_premultiplied(
+                rgba[0], rgba[1], rgba[2], rgba[3],
+            )],
+        }
+    }
+
+    fn combined(a: [u8; 4], b: [u8; 4], op: Combine) -> [u8; 4] {
+        pixel(a).combine(&pixel(b), op).unwrap().pixels[0].to_array()
+    }
+
+    const A: [u8; 4] = [200, 100, 50, 255];
+    const B: [u8; 4] = [100, 150, 50, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    #[test]
+    fn opaque() {
+        assert_eq!(combined(A, B, Combine::Add), [255, 250, 100, 255]);
+        assert_eq!(combined(A, B, Combine::Subtract), [100, 0, 0, 255]);
+        assert_eq!(combined(A, B, Combine::Multiply), [78, 59, 10, 255]);
+        assert_eq!(combined(A, B, Combine::AbsDiff), [100, 50, 0, 255]);
+        assert_eq!(combined(A, B, Combine::Min), [100, 100, 50, 255]);
+        assert_eq!(combined(A, B, Combine::Max), [200, 150, 50, 255]);
+        assert_eq!(combined(A, B, Combine::Blend(0.5)), [150, 125, 50, 255]);
+        assert_eq!(combined(A, B, Combine::Blend(0.0)), A);
+        assert_eq!(combined(A, B, Combine::Blend(1.0)), B);
+        // weights outside 0..1 are clamped
+        assert_eq!(combined(A, B, Combine::Blend(2.0)), B);
+        assert_eq!(combined(A, A, Combine::Multiply), [157, 39, 10, 255]);
+    }
+
+    #[test]
+    fn transparent() {
+        assert_eq!(combined(A, CLEAR, Combine::Add), A);
+        assert_eq!(combined(A, CLEAR, Combine::Subtract), A);
+        assert_eq!(combined(CLEAR, A, Combine::Subtract), [0, 0, 0, 255]);
+        assert_eq!(combined(A, CLEAR, Combine::Multiply), [0, 0, 0, 255]);
+        assert_eq!(combined(A, CLEAR, Combine::AbsDiff), A);
+        assert_eq!(combined(A, CLEAR, Combine::Min), [0, 0, 0, 255]);
+        assert_eq!(combined(A, CLEAR, Combine::Max), A);
+        assert_eq!(combined(A, CLEAR, Combine::Blend(0.5)), [100, 50, 25, 128]);
+        assert_eq!(combined(A, CLEAR, Combine::Blend(1.0)), CLEAR);
+        for op in [
+            Combine::Add,
+            Combine::Subtract,
+            Combine::Multiply,
+            Combine::AbsDiff,
+            Combine::Min,
+            Combine::Max,
+            Combine::Blend(0.3),
+        ]
+        .iter()
+        {
+            assert_eq!(combined(CLEAR, CLEAR, *op), CLEAR, "{}", op.name());
+        }
+    }
+
+    #[test]
+    fn stays_premultiplied() {
+        // color channels never exceed the alpha of the result
+        let half = [100, 100, 100, 128];
+        assert_eq!(
+            combined(half, [100, 0, 0, 128], Combine::Add),
+            [128, 100, 100, 128]
+        );
+        assert_eq!(
+            combined(half, [20, 0, 0, 64], Combine::AbsDiff),
+            [80, 100, 100, 128]
+        );
+    }
+
+    #[test]
+    fn sizes_and_names() {
+        let wide = Image {
+            size: (2, 1),
+            pixels: vec![Color32::BLACK; 2],
+        };
+        assert!(pixel(A).add(&wide).is_err());
+        assert!(check_same_size(&wide, &wide.clone()).is_ok());
+        assert_eq!(Combine::AbsDiff.name(), "absdiff");
+        assert_eq!(Combine::Blend(0.25).name(), "blend 0.25");
+    }
+}
@@ -0,0 +1,488 @@
+/*
+ * Browsing the images in a folder. The files are listed in the background,
+ * the arrow keys step through them, and a strip of thumbnails around the current
+ * file is decoded on background threads as it comes into view, then kept as
+ * textures in a keyed texture manager.
+ */
+
+use crate::geometry::Filter;
+use crate::image_io::to_image;
+use crate::job::{Job, Progress};
+use crate::raw::{can_load, is_native, load_native};
+use crate::utility::{Image, KeyedTexMngr};
+use eframe::{egui, epi};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Largest width or height of a thumbnail in pixels
+pub const THUMBNAIL_SIZE: u32 = 96;
+/// Number of thumbnails shown in the strip
+const STRIP_LEN: usize = 9;
+/// Thumbnails decoded ahead of the strip either side so stepping doesn't wait
+const PREFETCH: usize = 4;
+/// Thumbnails decoded at the same time
+const MAX_JOBS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortBy {
+    Name,
+    Modified,
+}
+
+#[derive(Clone, Debug)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+/// Files in the directory load_native can open, going by their extensions
+pub fn list_images(dir: &str, progress: &Progress) -> Result<Vec<FileEntry>, String> {
+    let entries = fs::read_dir(dir).map_err(|why| format!("couldn't open {}: {}", dir, why))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        if progress.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        let entry = entry.map_err(|why| format!("couldn't list {}: {}", dir, why))?;
+        let path = entry.path();
+        if !path.is_file() || !can_load(&path) {
+            continue;
+        }
+        let modified = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        files.push(FileEntry { path, modified });
+        progress.set_rows(files.len() as u64);
+    }
+    Ok(files)
+}
+
+pub fn sort_files(files: &mut [FileEntry], sort: SortBy) {
+    match sort {
+        SortBy::Name => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        SortBy::Modified => {
+            files.sort_by(|a, b| a.modified.cmp(&b.modified).then(a.path.cmp(&b.path)))
+        }
+    }
+}
+
+/// Decode the image and shrink it to fit in THUMBNAIL_SIZE, files the image crate
+/// can't read are drawn with the auto levels the viewer opens them with
+pub fn load_thumbnail(path: &Path) -> Result<Image, String> {
+    if !is_native(path) {
+        let image = image::open(path)
+            .map_err(|why| format!("couldn't decode {}: {}", path.display(), why))?;
+        return Ok(to_image(&image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)));
+    }
+    let image = load_native(&path.to_string_lossy(), &Progress::default())?.into_image();
+    let (width, height) = image.size;
+    let scale = (THUMBNAIL_SIZE as f32 / width.max(height).max(1) as f32).min(1.0);
+    if scale == 1.0 {
+        return Ok(image);
+    }
+    let size = |len: usize| ((len as f32 * scale).round() as usize).max(1);
+    Ok(image.resize(size(width), size(height), Filter::Bilinear))
+}
+
+pub struct FolderBrowser {
+    dir: String,
+    sort: SortBy,
+    files: Vec<FileEntry>,
+    current: Option<usize>,
+    listing: Option<Job<Vec<FileEntry>>>,
+    thumbnails: KeyedTexMngr<PathBuf>,
+    /// sizes of the decoded thumbnails
+    sizes: HashMap<PathBuf, (usize, usize)>,
+    decoding: Vec<(PathBuf, Job<Image>)>,
+    /// thumbnails that couldn't be decoded, so they aren't tried again
+    failed: HashSet<PathBuf>,
+    error: Option<String>,
+}
+
+impl Default for FolderBrowser {
+    fn default() -> Self {
+        Self {
+            dir: "data".to_string(),
+            sort: SortBy::Name,
+            files: Vec::new(),
+            current: None,
+            listing: None,
+            thumbnails: KeyedTexMngr::new(64),
+            sizes: HashMap::new(),
+            decoding: Vec::new(),
+            failed: HashSet::new(),
+            error: None,
+        }
+    }
+}
+
+impl FolderBrowser {
+    fn open(&mut self) {
+        let dir = self.dir.clone();
+        self.listing = Some(Job::spawn(format!("listing {}", dir), move |progress| {
+            list_images(&dir, progress)
+        }));
+    }
+
+    fn filename(&self, ind: usize) -> String {
+        self.files[ind].path.to_string_lossy().into_owned()
+    }
+
+    /// Move through the files by delta, wrapping around at either end, returning the
+    /// filename to show if it changed
+    pub fn step(&mut self, delta: i64) -> Option<String> {
+        if self.files.is_empty() {
+            return None;
+        }
+        let len = self.files.len() as i64;
+        let ind = match self.current {
+            Some(current) => (current as i64 + delta).rem_euclid(len) as usize,
+            None => 0,
+        };
+        self.select(ind)
+    }
+
+    fn select(&mut self, ind: usize) -> Option<String> {
+        if self.current == Some(ind) || ind >= self.files.len() {
+            return None;
+        }
+        self.current = Some(ind);
+        Some(self.filename(ind))
+    }
+
+    /// Step with the left and right arrow keys, home and end, unless a text field has the keyboard
+    pub fn handle_keys(&mut self, ctx: &egui::CtxRef) -> Option<String> {
+        if ctx.wants_keyboard_input() || self.files.is_empty() {
+            return None;
+        }
+        let input = ctx.input();
+        if input.key_pressed(egui::Key::ArrowRight) {
+            self.step(1)
+        } else if input.key_pressed(egui::Key::ArrowLeft) {
+            self.step(-1)
+        } else if input.key_pressed(egui::Key::Home) {
+            self.select(0)
+        } else if input.key_pressed(egui::Key::End) {
+            self.select(self.files.len() - 1)
+        } else {
+            None
+        }
+    }
+
+    fn resort(&mut self) {
+        let current = self.current.map(|ind| self.files[ind].path.clone());
+        sort_files(&mut self.files, self.sort);
+        self.current = current.and_then(|path| self.files.iter().position(|f| f.path == path));
+    }
+
+    /// Indices of the files shown in the strip
+    fn strip(&self) -> std::ops::Range<usize> {
+        let center = self.current.unwrap_or(0);
+        let end = (center.saturating_sub(STRIP_LEN / 2) + STRIP_LEN).min(self.files.len());
+        end.saturating_sub(STRIP_LEN)..end
+    }
+
+    /// Upload finished thumbnails and start decoding the ones coming into view
+    fn update_thumbnails(&mut self, frame: &mut epi::Frame<'_>) {
+        let mut ind = 0;
+        while ind < self.decoding.len() {
+            let result = match self.decoding[ind].1.poll() {
+                Some(result) => result,
+                None => {
+                    ind += 1;
+                    continue;
+                }
+            };
+            let (path, _) = self.decoding.swap_remove(ind);
+            match result {
+                Ok(thumbnail) => {
+                    self.sizes.insert(path.clone(), thumbnail.size);
+                    self.thumbnails.insert(frame, path, &thumbnail);
+                }
+                Err(why) => {
+                    println!("{}", why);
+                    self.failed.insert(path);
+                }
+            }
+        }
+
+        let strip = self.strip();
+        let start = strip.start.saturating_sub(PREFETCH);
+        let end = (strip.end + PREFETCH).min(self.files.len());
+        // the strip first, then the files ahead of it
+        let wanted = strip
+            .clone()
+            .chain(strip.end..end)
+            .chain(start..strip.start);
+        for ind in wanted {
+            if self.decoding.len() >= MAX_JOBS {
+                break;
+            }
+            let path = &self.files[ind].path;
+            if self.thumbnails.contains(path)
+                || self.failed.contains(path)
+                || self.decoding.iter().any(|(p, _)| p == path)
+            {
+                continue;
+            }
+            let job_path = path.clone();
+            let job = Job::spawn(format!("thumbnail {}", path.display()), move |_| {
+                load_thumbnail(&job_path)
+            });
+            self.decoding.push((path.clone(), job));
+        }
+    }
+
+    /// Folder, sorting and navigation controls and the thumbnail strip, returns
+    /// the filename to show when a different file is chosen.
+    pub fn ui(&mut self, ui: &mut egui::Ui, frame: &mut epi::Frame<'_>) -> Option<String> {
+        let mut chosen = None;
+        if let Some(result) = self.listing.as_ref().and_then(|job| job.poll()) {
+            match result {
+                Ok(files) => {
+                    for (_, job) in self.decoding.drain(..) {
+                        job.cancel();
+                    }
+                    self.thumbnails.clear(frame);
+                    self.sizes.clear();
+                    self.failed.clear();
+                    self.files = files;
+                    self.current = None;
+                    self.error = None;
+                    sort_files(&mut self.files, self.sort);
+                    chosen = self.step(0);
+                }
+                Err(why) => self.error = Some(why),
+            }
+            self.listing = None;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("folder");
+            ui.text_edit_singleline(&mut self.dir);
+            if ui.button("open").clicked {
+                self.open();
+            }
+        });
+        ui.horizontal(|ui| {
+            let sort = self.sort;
+            ui.label("sort by");
+            ui.radio_value(&mut self.sort, SortBy::Name, "name");
+            ui.radio_value(&mut self.sort, SortBy::Modified, "modified");
+            if self.sort != sort {
+                self.resort();
+            }
+        });
+        if let Some(job) = &self.listing {
+            job.ui(ui);
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        if self.files.is_empty() {
+            ui.label("no images");
+            return chosen;
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("<").clicked {
+                chosen = self.step(-1);
+            }
+            if ui.button(">").clicked {
+                chosen = self.step(1);
+            }
+            if let Some(ind) = self.current {
+                let name = self.files[ind]
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                ui.label(format!("{} / {} {}", ind + 1, self.files.len(), name));
+            }
+        });
+
+        self.update_thumbnails(frame);
+        let box_size = egui::Vec2::splat(THUMBNAIL_SIZE as f32);
+        ui.horizontal(|ui| {
+            for ind in self.strip() {
+                let path = &self.files[ind].path;
+                let texture = self.thumbnails.get(path);
+                match (texture, self.sizes.get(path)) {
+                    (Some(texture_id), Some(size)) => {
+                        // fit the thumbnail in the box keeping its aspect ratio
+                        let fit = THUMBNAIL_SIZE as f32 / size.0.max(size.1) as f32;
+                        let size = egui::Vec2::new(size.0 as f32 * fit, size.1 as f32 * fit);
+                        let button = egui::ImageButton::new(texture_id, size)
+                            .selected(self.current == Some(ind));
+                        if ui.add(button).clicked {
+                            chosen = self.select(ind);
+                        }
+                    }
+                    _ => {
+                        let (rect, _) = ui.allocate_exact_size(box_size, egui::Sense::hover());
+                        let failed = self.failed.contains(path);
+                        ui.painter()
+                            .rect_stroke(rect, 2.0, (1.0, egui::Color32::GRAY));
+                        ui.painter().text(
+                            rect.center(),
+                            egui::Align2::CENTER_CENTER,
+                            if failed { "x" } else { "..." },
+                            egui::TextStyle::Body,
+                            egui::Color32::GRAY,
+                        );
+                    }
+                }
+            }
+        });
+        if !self.decoding.is_empty() {
+            // keep polling the thumbnails even if there is no input
+            ui.ctx().request_repaint();
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netpbm::write_pfm;
+    use crate::raw::{Depth, Loaded, RawImage};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("egui_image_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A 2 x 3 uint16 array
+    fn write_npy(path: &Path) {
+        let mut header = "{'descr': '<u2', 'fortran_order': False, 'shape': (2, 3), }".to_string();
+        let pad = 63 - (10 + header.len()) % 64;
+        header.push_str(&" ".repeat(pad));
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        for value in [0u16, 1000, 2000, 3000, 4000, 65535].iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        fs::write(path, &bytes).unwrap();
+    }
+
+    fn entries(names_and_times: &[(&str, u64)]) -> Vec<FileEntry> {
+        names_and_times
+            .iter()
+            .map(|(name, secs)| FileEntry {
+                path: PathBuf::from(name),
+                modified: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(*secs),
+            })
+            .collect()
+    }
+
+    fn names(files: &[FileEntry]) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| file.path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn sorting() {
+        let mut files = entries(&[("b.png", 5), ("c.png", 1), ("a.png", 5), ("a.npy", 9)]);
+        sort_files(&mut files, SortBy::Name);
+        assert_eq!(names(&files), ["a.npy", "a.png", "b.png", "c.png"]);
+        // files modified at the same time stay in name order
+        sort_files(&mut files, SortBy::Modified);
+        assert_eq!(names(&files), ["c.png", "a.png", "b.png", "a.npy"]);
+
+        // the selected file stays selected when the order changes
+        let mut browser = FolderBrowser {
+            files,
+            ..Default::default()
+        };
+        browser.select(2);
+        browser.sort = SortBy::Name;
+        browser.resort();
+        assert_eq!(browser.current, Some(2));
+        assert_eq!(browser.filename(2), "b.png");
+        browser.sort = SortBy::Modified;
+        browser.resort();
+        assert_eq!(browser.filename(browser.current.unwrap()), "b.png");
+    }
+
+    #[test]
+    fn stepping() {
+        let mut browser = FolderBrowser::default();
+        assert_eq!(browser.step(1), None);
+
+        browser.files = entries(&[("a", 0), ("b", 0), ("c", 0)]);
+        assert_eq!(browser.step(1).as_deref(), Some("a"));
+        assert_eq!(browser.step(0), None);
+        assert_eq!(browser.step(1).as_deref(), Some("b"));
+        assert_eq!(browser.step(1).as_deref(), Some("c"));
+        // around the end and back again
+        assert_eq!(browser.step(1).as_deref(), Some("a"));
+        assert_eq!(browser.step(-1).as_deref(), Some("c"));
+        assert_eq!(browser.step(-4).as_deref(), Some("b"));
+        assert_eq!(browser.step(3), None);
+
+        browser.files.truncate(1);
+        browser.current = Some(0);
+        assert_eq!(browser.step(1), None);
+        assert_eq!(browser.step(-1), None);
+    }
+
+    #[test]
+    fn lists_and_opens_npy() {
+        let dir = temp_dir("browser");
+        write_npy(&dir.join("frame.NPY"));
+        fs::write(dir.join("notes.txt"), "not an image").unwrap();
+
+        let files = list_images(&dir.to_string_lossy(), &Progress::default());
+        let paths: Vec<PathBuf> = files.unwrap().into_iter().map(|file| file.path).collect();
+        assert_eq!(paths, vec![dir.join("frame.NPY")]);
+        let loaded = load_native(&paths[0].to_string_lossy(), &Progress::default());
+        fs::remove_dir_all(&dir).unwrap();
+        match loaded.unwrap() {
+            Loaded::Raw(raw) => {
+                assert_eq!((raw.size, raw.channels, raw.depth), ((3, 2), 1, Depth::U16));
+                assert_eq!(raw.data[5], 65535.0);
+            }
+            Loaded::Image(_) => panic!("an npy array should keep its native values"),
+        }
+    }
+
+    #[test]
+    fn native_thumbnails() {
+        let dir = temp_dir("thumbnail");
+        write_npy(&dir.join("frame.npy"));
+        let wide = RawImage {
+            size: (200, 100),
+            channels: 1,
+            depth: Depth::F32,
+            data: (0..200 * 100)
+                .map(|ind| (ind % 200) as f32 / 199.0)
+                .collect(),
+            maxval: None,
+        };
+        write_pfm(fs::File::create(dir.join("wide.pfm")).unwrap(), &wide).unwrap();
+
+        let small = load_thumbnail(&dir.join("frame.npy"));
+        let shrunk = load_thumbnail(&dir.join("wide.pfm"));
+        fs::remove_dir_all(&dir).unwrap();
+        // drawn from black to white across the values like the viewer does
+        let small = small.unwrap();
+        assert_eq!(small.size, (3, 2));
+        assert_eq!(small.pixels[0], egui::Color32::BLACK);
+        assert_eq!(small.pixels[5], egui::Color32::WHITE);
+        let shrunk = shrunk.unwrap();
+        assert_eq!(
+            shrunk.size,
+            (THUMBNAIL_SIZE as usize, THUMBNAIL_SIZE as usize / 2)
+        );
+        assert!(shrunk.pixels[0].r() < 10 && shrunk.pixels[THUMBNAIL_SIZE as usize - 1].r() > 245);
+    }
+}
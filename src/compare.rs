@@ -0,0 +1,328 @@
+/*
+ * Compare two images: side by side, split by a swipe divider, flickering between
+ * them or as their difference. Both images share the zoom and pan, so the same
+ * region of each is in view, drag to pan and scroll to zoom.
+ */
+
+use crate::utility::{Image, TexMngr};
+use eframe::egui::{self, paint, Color32, Pos2, Rect, Vec2};
+use eframe::epi;
+
+const VIEW_SIZE: (f32, f32) = (640.0, 480.0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareMode {
+    SideBySide,
+    Swipe,
+    Flicker,
+    Difference,
+}
+
+pub struct CompareView {
+    /// Whether the compare window is shown
+    pub open: bool,
+    pub mode: CompareMode,
+    /// screen points per image pixel
+    zoom: f32,
+    /// image pixel at the top left of the view
+    offset: Vec2,
+    /// divider position as a fraction of the view width
+    swipe: f32,
+    dragging_swipe: bool,
+    /// flickers per second, 0 to switch only on click
+    flicker_rate: f32,
+    show_b: bool,
+    /// multiplier on the absolute difference
+    gain: f32,
+    tex_a: TexMngr,
+    tex_b: TexMngr,
+    tex_diff: TexMngr,
+    /// generations of the images in the textures, they are only uploaded again on changes
+    tex_a_generation: Option<u64>,
+    tex_b_generation: Option<u64>,
+    /// the gain and image generations the difference was made from
+    diff_key: Option<(f32, u64, u64)>,
+    diff: Result<Image, String>,
+}
+
+impl Default for CompareView {
+    fn default() -> Self {
+        Self {
+            open: false,
+            mode: CompareMode::SideBySide,
+            zoom: 1.0,
+            offset: Vec2::zero(),
+            swipe: 0.5,
+            dragging_swipe: false,
+            flicker_rate: 2.0,
+            show_b: false,
+            gain: 4.0,
+            tex_a: Default::default(),
+            tex_b: Default::default(),
+            tex_diff: Default::default(),
+            tex_a_generation: None,
+            tex_b_generation: None,
+            diff_key: None,
+            diff: Err("no difference yet".to_string()),
+        }
+    }
+}
+
+/// Absolute difference of the color channels times the gain, opaque
+pub fn amplified_difference(a: &Image, b: &Image, gain: f32) -> Result<Image, String> {
+    let diff = a.abs_diff(b)?;
+    let pixels = diff
+        .pixels
+        .iter()
+        .map(|p| {
+            let amp = |v: u8| (v as f32 * gain).round().min(255.0) as u8;
+            Color32::from_rgb(amp(p.r()), amp(p.g()), amp(p.b()))
+        })
+        .collect();
+    Ok(Image {
+        size: diff.size,
+        pixels,
+    })
+}
+
+/// Draw the whole texture at the rect, only the part within the clip rect is visible
+fn paint_image(ui: &egui::Ui, texture_id: egui::TextureId, rect: Rect, clip: Rect) {
+    let mut triangles = paint::Triangles::with_texture(texture_id);
+    let uv = Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(1.0, 1.0));
+    triangles.add_rect_with_uv(rect, uv, Color32::WHITE);
+    ui.painter_at(clip).add(egui::Shape::triangles(triangles));
+}
+
+impl CompareView {
+    /// Where the image lands on screen for a view with its top left at origin
+    fn image_rect(&self, origin: Pos2, image: &Image) -> Rect {
+        let min = origin - self.offset * self.zoom;
+        let size = Vec2::new(image.size.0 as f32, image.size.1 as f32) * self.zoom;
+        Rect::from_min_size(min, size)
+    }
+
+    /// The generations change whenever the images do, the textures and the difference
+    /// are only redone then
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        frame: &mut epi::Frame<'_>,
+        a: &Image,
+        a_generation: u64,
+        b: &Image,
+        b_generation: u64,
+    ) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, CompareMode::SideBySide, "side by side");
+            ui.radio_value(&mut self.mode, CompareMode::Swipe, "swipe");
+            ui.radio_value(&mut self.mode, CompareMode::Flicker, "flicker");
+            ui.radio_value(&mut self.mode, CompareMode::Difference, "difference");
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::Slider::f32(&mut self.zoom, 0.05..=32.0)
+                    .logarithmic(true)
+                    .text("zoom"),
+            );
+            if ui.button("reset").clicked {
+                self.zoom = 1.0;
+                self.offset = Vec2::zero();
+            }
+        });
+        match self.mode {
+            CompareMode::Flicker => {
+                ui.add(egui::Slider::f32(&mut self.flicker_rate, 0.0..=10.0).text("flickers/s"));
+            }
+            CompareMode::Difference => {
+                ui.add(
+                    egui::Slider::f32(&mut self.gain, 1.0..=64.0)
+                        .logarithmic(true)
+                        .text("gain"),
+                );
+            }
+            _ => {}
+        }
+
+        let (view, response) = ui.allocate_exact_size(
+            Vec2::new(VIEW_SIZE.0, VIEW_SIZE.1),
+            egui::Sense::click_and_drag(),
+        );
+        ui.painter().rect_filled(view, 0.0, Color32::from_gray(32));
+
+        // pan and zoom shared by both images
+        let input = ui.input();
+        let divider_x = view.min.x + view.width() * self.swipe;
+        if input.mouse.pressed {
+            self.dragging_swipe = self.mode == CompareMode::Swipe
+                && input
+                    .mouse
+                    .press_origin
+                    .is_some_and(|pos| view.contains(pos) && (pos.x - divider_x).abs() < 8.0);
+        }
+        if response.active {
+            if self.dragging_swipe {
+                if let Some(pos) = input.mouse.pos {
+                    self.swipe = ((pos.x - view.min.x) / view.width()).clamp(0.0, 1.0);
+                }
+            } else {
+                self.offset -= input.mouse.delta / self.zoom;
+            }
+        }
+        if response.hovered && input.scroll_delta.y != 0.0 {
+            // keep the pixel under the mouse in place
+            let pos = input.mouse.pos.unwrap_or_else(|| view.center());
+            let under = self.offset + (pos - view.min) / self.zoom;
+            self.zoom = (self.zoom * (input.scroll_delta.y / 200.0).exp()).clamp(0.05, 32.0);
+            self.offset = under - (pos - view.min) / self.zoom;
+        }
+        let time = input.time;
+        if response.clicked && self.mode == CompareMode::Flicker {
+            self.show_b = !self.show_b;
+        }
+
+        let update_a = self.tex_a_generation != Some(a_generation);
+        let update_b = self.tex_b_generation != Some(b_generation);
+        let tex_a = self.tex_a.texture(frame, update_a, a);
+        let tex_b = self.tex_b.texture(frame, update_b, b);
+        let (tex_a, tex_b) = match (tex_a, tex_b) {
+            (Some(tex_a), Some(tex_b)) => (tex_a, tex_b),
+            _ => return,
+        };
+        self.tex_a_generation = Some(a_generation);
+        self.tex_b_generation = Some(b_generation);
+
+        match self.mode {
+            CompareMode::SideBySide => {
+                let half = Vec2::new(view.width() / 2.0, view.height());
+                let left = Rect::from_min_size(view.min, half);
+                let right = Rect::from_min_size(view.min + Vec2::new(half.x, 0.0), half);
+                paint_image(ui, tex_a, self.image_rect(left.min, a), left);
+                paint_image(ui, tex_b, self.image_rect(right.min, b), right);
+                let stroke = (1.0, ui.style().visuals.text_color());
+                ui.painter()
+                    .line_segment([right.left_top(), right.left_bottom()], stroke);
+            }
+            CompareMode::Swipe => {
+                let mut left = view;
+                left.max.x = divider_x;
+                let mut right = view;
+                right.min.x = divider_x;
+                paint_image(ui, tex_a, self.image_rect(view.min, a), left);
+                paint_image(ui, tex_b, self.image_rect(view.min, b), right);
+                ui.painter().line_segment(
+                    [right.left_top(), right.left_bottom()],
+                    (2.0, Color32::WHITE),
+                );
+            }
+            CompareMode::Flicker => {
+                let show_b = if self.flicker_rate > 0.0 {
+                    ui.ctx().request_repaint();
+                    (time * self.flicker_rate as f64) as i64 % 2 == 1
+                } else {
+                    self.show_b
+                };
+                let (texture_id, image) = if show_b { (tex_b, b) } else { (tex_a, a) };
+                paint_image(ui, texture_id, self.image_rect(view.min, image), view);
+                ui.label(if show_b { "showing b" } else { "showing a" });
+            }
+            CompareMode::Difference => {
+                let key = Some((self.gain, a_generation, b_generation));
+                let update = self.diff_key != key;
+                if update {
+                    self.diff = amplified_difference(a, b, self.gain);
+                    self.diff_key = key;
+                }
+                match &self.diff {
+                    Ok(diff) => {
+                        if let Some(texture_id) = self.tex_diff.texture(frame, update, diff) {
+                            paint_image(ui, texture_id, self.image_rect(view.min, diff), view);
+                        }
+                    }
+                    Err(why) => {
+                        ui.colored_label(Color32::RED, why);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(pixels: Vec<Color32>) -> Image {
+        Image {
+            size: (pixels.len(), 1),
+            pixels,
+        }
+    }
+
+    #[test]
+    fn difference() {
+        let a = image(vec![
+            Color32::from_rgb(10, 20, 30),
+            Color32::from_rgb(200, 0, 100),
+            Color32::TRANSPARENT,
+        ]);
+        let same = amplified_difference(&a, &a.clone(), 8.0).unwrap();
+        assert_eq!(same.size, a.size);
+        assert_eq!(same.pixels, vec![Color32::BLACK; 3]);
+
+        let b = image(vec![
+            Color32::from_rgb(12, 20, 25),
+            Color32::from_rgb(100, 0, 110),
+            Color32::from_rgba_premultiplied(3, 0, 0, 3),
+        ]);
+        // the gain scales the difference until it saturates, either way around
+        let diff = amplified_difference(&a, &b, 4.0).unwrap();
+        assert_eq!(
+            diff.pixels,
+            vec![
+                Color32::from_rgb(8, 0, 20),
+                Color32::from_rgb(255, 0, 40),
+                Color32::from_rgb(12, 0, 0),
+            ]
+        );
+        assert_eq!(
+            amplified_difference(&b, &a, 4.0).unwrap().pixels,
+            diff.pixels
+        );
+        assert_eq!(
+            amplified_difference(&a, &b, 1.0).unwrap().pixels[1],
+            Color32::from_rgb(100, 0, 10)
+        );
+    }
+
+    #[test]
+    fn sizes_differ() {
+        let a = image(vec![Color32::WHITE; 2]);
+        let b = image(vec![Color32::WHITE; 3]);
+        match amplified_difference(&a, &b, 1.0) {
+            Err(why) => assert_eq!(why, "image sizes differ: 2x1 and 3x1"),
+            Ok(_) => panic!("different sizes were compared"),
+        }
+    }
+
+    #[test]
+    fn shared_view() {
+        let mut view = CompareView::default();
+        let a = image(vec![Color32::WHITE; 4]);
+        let b = Image {
+            size: (8, 2),
+            pixels: vec![Color32::WHITE; 16],
+        };
+        let origin = Pos2::new(100.0, 50.0);
+        assert_eq!(
+            view.image_rect(origin, &a),
+            Rect::from_min_size(origin, Vec2::new(4.0, 1.0))
+        );
+        // both images move and scale together so the same pixel is at the same place
+        view.zoom = 2.0;
+        view.offset = Vec2::new(1.0, 0.5);
+        let (ra, rb) = (view.image_rect(origin, &a), view.image_rect(origin, &b));
+        assert_eq!(ra.min, Pos2::new(98.0, 49.0));
+        assert_eq!(rb.min, ra.min);
+        assert_eq!(rb.size(), Vec2::new(16.0, 4.0));
+    }
+}
@@ -15,10 +15,15 @@
 use std::env;
 use std::error::Error;
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 // use std::process;
 
-pub fn load_csv(csv_file: File) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
+/// Parse the csv into columns, on_row is called with the number of rows parsed so far
+pub fn load_csv(
+    csv_file: impl Read,
+    mut on_row: impl FnMut(usize),
+) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
     // println!("loading '{}'", csv_file);
 
     let mut data: Vec<Vec<f64>> = Vec::new();
@@ -31,22 +36,39 @@ pub fn load_csv(csv_file: File) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
             data.resize(record.len(), Vec::new());
             println!("processing {} columns", data.len());
         }
-        if data.len() != record.len() {
-            println!("unexpected record len {} {}", data.len(), record.len());
-            continue;
-        }
         // println!("{:?}", record);
-        for (j, val_str) in record.iter().enumerate() {
-            // print!(" '{}' -> ", val_str);
-            let val = val_str.trim_start_matches(' ').parse::<f64>().unwrap();
-            // print!(" {},", val);
-            data[j].push(val);
+        let fields: Vec<&str> = record.iter().collect();
+        if let Some(row) = parse_row(&fields, data.len())? {
+            push_row(&mut data, &row);
         }
+        on_row(i + 1);
         // println!("");
     }
     Ok(data)
 }
 
+/// Convert the fields of one csv record into a row of values, returns None if the
+/// number of fields doesn't match the expected number of columns.
+pub fn parse_row(fields: &[&str], num_columns: usize) -> Result<Option<Vec<f64>>, Box<dyn Error>> {
+    if num_columns != fields.len() {
+        println!("unexpected record len {} {}", num_columns, fields.len());
+        return Ok(None);
+    }
+    let mut row = Vec::with_capacity(num_columns);
+    for val_str in fields {
+        // print!(" '{}' -> ", val_str);
+        row.push(val_str.trim().parse::<f64>()?);
+    }
+    Ok(Some(row))
+}
+
+/// Append a row of values to the end of each column
+pub fn push_row(data: &mut [Vec<f64>], row: &[f64]) {
+    for (column, val) in data.iter_mut().zip(row.iter()) {
+        column.push(*val);
+    }
+}
+
 pub fn get_filename() -> String {
     let args: Vec<String> = env::args().collect();
     // println!("args {:?}", args);
@@ -70,7 +92,7 @@ fn demo_load_csv() {
         Ok(csv_file) => csv_file,
     };
 
-    let columns = load_csv(csv_file).unwrap();
+    let columns = load_csv(csv_file, |_| {}).unwrap();
 
     for (i, column) in columns.iter().enumerate() {
         println!("{} {:?}", i, column);
@@ -0,0 +1,132 @@
+/*
+ * Column storage for continuously appended plot data, old samples are dropped
+ * according to the retention so the columns don't grow without bound.
+ */
+
+use crate::csv_plot::push_row;
+use crate::decimate::MinMaxPyramid;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Retention {
+    /// Keep the last N samples
+    Samples(usize),
+    /// Keep the samples within T seconds of the latest value of the x (first) column
+    Seconds(f64),
+}
+
+#[derive(Clone)]
+pub struct DataStore {
+    columns: Vec<Vec<f64>>,
+    /// Min/max level of detail for each column, extended as rows are appended
+    pyramids: Vec<MinMaxPyramid>,
+    retention: Retention,
+    /// Samples dropped from the front since the data started, so sample positions
+    /// counted from the start stay the same as old samples are dropped
+    dropped: usize,
+}
+
+impl DataStore {
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            columns: Vec::new(),
+            pyramids: Vec::new(),
+            retention,
+            dropped: 0,
+        }
+    }
+
+    pub fn columns(&self) -> &[Vec<f64>] {
+        &self.columns
+    }
+
+    /// The min and max of a column over a range of samples
+    pub fn min_max(&self, col_ind: usize, start: usize, end: usize) -> Option<(f64, f64)> {
+        self.pyramids[col_ind].min_max(&self.columns[col_ind], start, end)
+    }
+
+    /// Number of samples in each column
+    pub fn num_samples(&self) -> usize {
+        self.columns.first().map_or(0, |column| column.len())
+    }
+
+    /// Position of the first retained sample counted from the start of the data
+    pub fn first_sample(&self) -> usize {
+        self.dropped
+    }
+
+    /// Position one past the last sample counted from the start of the data
+    pub fn end_sample(&self) -> usize {
+        self.dropped + self.num_samples()
+    }
+
+    pub fn set_retention(&mut self, retention: Retention) {
+        if self.retention != retention {
+            self.retention = retention;
+            self.drop_old();
+        }
+    }
+
+    /// Replace all the data, e.g. when a file is reloaded
+    pub fn set_columns(&mut self, columns: Vec<Vec<f64>>) {
+        self.columns = columns;
+        self.dropped = 0;
+        self.pyramids = self
+            .columns
+            .iter()
+            .map(|column| MinMaxPyramid::new(column))
+            .collect();
+        self.drop_old();
+    }
+
+    pub fn append_rows(&mut self, rows: Vec<Vec<f64>>) {
+        if rows.is_empty() {
+            return;
+        }
+        let mut old_len = self.num_samples();
+        for row in rows {
+            if self.columns.len() != row.len() {
+                if !self.columns.is_empty() {
+                    println!(
+                        "column count changed {} -> {}",
+                        self.columns.len(),
+                        row.len()
+                    );
+                }
+                self.columns = vec![Vec::new(); row.len()];
+                self.pyramids = vec![MinMaxPyramid::default(); row.len()];
+                self.dropped = 0;
+                old_len = 0;
+            }
+            push_row(&mut self.columns, &row);
+        }
+        for (column, pyramid) in self.columns.iter().zip(self.pyramids.iter_mut()) {
+            pyramid.push(&column[old_len..]);
+        }
+        self.drop_old();
+    }
+
+    /// Drop the oldest samples that fall outside of the retention
+    fn drop_old(&mut self) {
+        let len = self.num_samples();
+        let first = match self.retention {
+            Retention::Samples(max_samples) => len.saturating_sub(max_samples),
+            Retention::Seconds(max_seconds) => match self.columns.first() {
+                Some(x) if !x.is_empty() => {
+                    let oldest = x[len - 1] - max_seconds;
+                    x.partition_point(|t| *t < oldest)
+                }
+                _ => 0,
+            },
+        };
+        if first == 0 {
+            return;
+        }
+        for column in self.columns.iter_mut() {
+            column.drain(..first);
+        }
+        self.dropped += first;
+        for pyramid in self.pyramids.iter_mut() {
+            pyramid.drop_front(self.dropped);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+/*
+ * Level of detail for plotting long series: the min and max of blocks of samples are
+ * cached at successively doubled block sizes, so the envelope of any range of samples
+ * can be found in O(log n) without visiting every sample and without losing spikes.
+ *
+ * Blocks are indexed from the start of the series including samples that have since been
+ * dropped from the front, so appending and dropping samples only touches the blocks at the
+ * ends of each level instead of rebuilding the pyramid.
+ */
+
+use std::collections::VecDeque;
+
+/// Samples covered by each entry of the finest level of the pyramid,
+/// ranges smaller than this are scanned directly.
+const BASE: usize = 8;
+
+#[derive(Clone, Default)]
+pub struct MinMaxPyramid {
+    /// levels[k] holds the min and max of each block of BASE * 2^k samples
+    levels: Vec<VecDeque<(f64, f64)>>,
+    /// Number of blocks dropped from the front of each level
+    dropped_blocks: Vec<usize>,
+    /// Position of the first retained sample
+    first: usize,
+    /// Position one past the last sample
+    end: usize,
+}
+
+fn combine(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0.min(b.0), a.1.max(b.1))
+}
+
+fn scan(data: &[f64]) -> (f64, f64) {
+    data.iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |acc, val| {
+            combine(acc, (*val, *val))
+        })
+}
+
+/// Replace the block at position ind of the level, or append it if it is one past the end
+fn set_block(level: &mut VecDeque<(f64, f64)>, dropped: usize, ind: usize, val: (f64, f64)) {
+    let ind = ind - dropped;
+    if ind < level.len() {
+        level[ind] = val;
+    } else {
+        level.push_back(val);
+    }
+}
+
+impl MinMaxPyramid {
+    pub fn new(data: &[f64]) -> Self {
+        let mut pyramid = Self::default();
+        pyramid.push(data);
+        pyramid
+    }
+
+    /// Extend the pyramid with samples appended to the end of the series,
+    /// only the blocks the new samples fall in are updated.
+    pub fn push(&mut self, values: &[f64]) {
+        if values.is_empty() {
+            return;
+        }
+        if self.levels.is_empty() {
+            self.levels.push(VecDeque::new());
+            self.dropped_blocks.push(self.first / BASE);
+        }
+
+        // the finest level, the last block may already be partly filled
+        let mut pos = self.end;
+        let mut remaining = values;
+        while !remaining.is_empty() {
+            let block = pos / BASE;
+            let take = (BASE - pos % BASE).min(remaining.len());
+            let mut val = scan(&remaining[..take]);
+            let ind = block - self.dropped_blocks[0];
+            if let Some(existing) = self.levels[0].get(ind) {
+                val = combine(*existing, val);
+            }
+            set_block(&mut self.levels[0], self.dropped_blocks[0], block, val);
+            pos += take;
+            remaining = &remaining[take..];
+        }
+        let mut changed = (self.end / BASE, (pos - 1) / BASE + 1);
+        self.end = pos;
+
+        // recompute the parents of the changed blocks, adding levels until one block covers all
+        let mut k = 0;
+        while self.dropped_blocks[k] + self.levels[k].len() > 1 {
+            if self.levels.len() == k + 1 {
+                // a new level is filled from every block of the level below
+                let dropped = self.dropped_blocks[k] / 2;
+                self.levels.push(VecDeque::new());
+                self.dropped_blocks.push(dropped);
+                changed.0 = self.dropped_blocks[k];
+            }
+            let (lower, upper) = self.levels.split_at_mut(k + 1);
+            let child = &lower[k];
+            let child_dropped = self.dropped_blocks[k];
+            let parents = (changed.0 / 2, (changed.1 - 1) / 2 + 1);
+            for parent in parents.0..parents.1 {
+                // the first child may have been dropped while the parent is still kept
+                let lo = (parent * 2).max(child_dropped) - child_dropped;
+                let hi = (parent * 2 + 2 - child_dropped).min(child.len());
+                let val = child
+                    .range(lo..hi)
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |acc, val| {
+                        combine(acc, *val)
+                    });
+                set_block(&mut upper[0], self.dropped_blocks[k + 1], parent, val);
+            }
+            changed = parents;
+            k += 1;
+        }
+    }
+
+    /// Forget the blocks that end before the first retained sample,
+    /// first is counted from the start of the series like the blocks.
+    pub fn drop_front(&mut self, first: usize) {
+        self.first = first;
+        for (k, level) in self.levels.iter_mut().enumerate() {
+            let keep_from = first / (BASE << k);
+            let dropped = &mut self.dropped_blocks[k];
+            while *dropped < keep_from && level.pop_front().is_some() {
+                *dropped += 1;
+            }
+            if level.is_empty() {
+                *dropped = keep_from;
+            }
+        }
+    }
+
+    /// The min and max of data[start..end], data must be the retained part of the series
+    /// the pyramid was built from
+    pub fn min_max(&self, data: &[f64], start: usize, end: usize) -> Option<(f64, f64)> {
+        let end = end.min(data.len());
+        if start >= end {
+            return None;
+        }
+        // blocks are indexed from the start of the series
+        let first = self.first;
+        let (start, end) = (start + first, end + first);
+
+        // whole blocks of the finest level, these never include dropped samples
+        let mut lo = start.div_ceil(BASE);
+        let mut hi = end / BASE;
+        if lo >= hi {
+            return Some(scan(&data[start - first..end - first]));
+        }
+        let mut acc = combine(
+            scan(&data[start - first..lo * BASE - first]),
+            scan(&data[hi * BASE - first..end - first]),
+        );
+
+        // take the unpaired blocks at either end of the range then move up a level
+        for (level, dropped) in self.levels.iter().zip(self.dropped_blocks.iter()) {
+            if lo >= hi {
+                break;
+            }
+            if lo % 2 == 1 {
+                acc = combine(acc, level[lo - dropped]);
+                lo += 1;
+            }
+            if hi % 2 == 1 {
+                hi -= 1;
+                acc = combine(acc, level[hi - dropped]);
+            }
+            lo /= 2;
+            hi /= 2;
+        }
+        Some(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental_matches_scan() {
+        let series: Vec<f64> = (0..1000).map(|i| ((i * 37) % 101) as f64 - 50.0).collect();
+        let mut pyramid = MinMaxPyramid::default();
+        let mut first = 0;
+        let mut end = 0;
+        // append uneven batches and drop from the front like a retention limited store
+        for batch in [1, 7, 30, 3, 200, 64, 5, 300, 390].iter() {
+            pyramid.push(&series[end..end + batch]);
+            end += batch;
+            first = first.max(end.saturating_sub(250));
+            pyramid.drop_front(first);
+            let data = &series[first..end];
+            for start in (0..data.len()).step_by(3) {
+                for stop in (start..=data.len()).step_by(11) {
+                    let expected = if start < stop {
+                        Some(scan(&data[start..stop]))
+                    } else {
+                        None
+                    };
+                    assert_eq!(pyramid.min_max(data, start, stop), expected);
+                }
+            }
+        }
+    }
+}
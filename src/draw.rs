@@ -0,0 +1,465 @@
+/*
+ * Drawing into the pixels of an Image: lines, rectangles, ellipses, polylines,
+ * filled polygons and bitmap text.
+ *
+ * Positions are in pixels with pixel (x, y) covering x..x + 1 and y..y + 1.
+ * Colors are blended over the existing pixels using their alpha, and each shape
+ * touches each pixel only once so translucent colors blend evenly. Anything
+ * outside of the image is clipped.
+ */
+
+use crate::font::{glyph, ADVANCE, GLYPH_HEIGHT, LINE_HEIGHT};
+use crate::utility::Image;
+use eframe::egui::Color32;
+
+/// Premultiplied source over destination
+pub fn blend_over(dst: Color32, src: Color32) -> Color32 {
+    let src = src.to_array();
+    if src[3] == 255 {
+        return Color32::from_rgba_premultiplied(src[0], src[1], src[2], src[3]);
+    }
+    let dst = dst.to_array();
+    let inv = 255 - src[3] as u32;
+    let over = |c: usize| (src[c] as u32 + (dst[c] as u32 * inv + 127) / 255).min(255) as u8;
+    Color32::from_rgba_premultiplied(over(0), over(1), over(2), over(3))
+}
+
+/// Size in pixels of the text as drawn by draw_text
+pub fn text_size(text: &str, scale: usize) -> (usize, usize) {
+    let lines = text.lines().count().max(1);
+    let chars = text
+        .lines()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = (chars * ADVANCE).saturating_sub(1);
+    let height = (lines - 1) * LINE_HEIGHT + GLYPH_HEIGHT;
+    (width * scale, height * scale)
+}
+
+/// Clip the segment from a to b to the rectangle from (0, 0) to max with Cohen-Sutherland,
+/// None if the segment is entirely outside
+fn clip_segment(
+    mut a: (f64, f64),
+    mut b: (f64, f64),
+    max: (f64, f64),
+) -> Option<((f64, f64), (f64, f64))> {
+    let outcode = |p: (f64, f64)| {
+        let mut code = 0;
+        if p.0 < 0.0 {
+            code |= 1;
+        } else if p.0 > max.0 {
+            code |= 2;
+        }
+        if p.1 < 0.0 {
+            code |= 4;
+        } else if p.1 > max.1 {
+            code |= 8;
+        }
+        code
+    };
+    let (mut code_a, mut code_b) = (outcode(a), outcode(b));
+    // each step moves an end onto an edge, so four are enough
+    for _ in 0..4 {
+        if code_a | code_b == 0 {
+            break;
+        }
+        if code_a & code_b != 0 {
+            return None;
+        }
+        let out = if code_a != 0 { code_a } else { code_b };
+        let ((x0, y0), (x1, y1)) = (a, b);
+        let p = if out & 8 != 0 {
+            (x0 + (x1 - x0) * (max.1 - y0) / (y1 - y0), max.1)
+        } else if out & 4 != 0 {
+            (x0 + (x1 - x0) * -y0 / (y1 - y0), 0.0)
+        } else if out & 2 != 0 {
+            (max.0, y0 + (y1 - y0) * (max.0 - x0) / (x1 - x0))
+        } else {
+            (0.0, y0 + (y1 - y0) * -x0 / (x1 - x0))
+        };
+        if out == code_a {
+            a = p;
+            code_a = outcode(a);
+        } else {
+            b = p;
+            code_b = outcode(b);
+        }
+    }
+    if code_a & code_b != 0 {
+        return None;
+    }
+    // rounding can leave an end a hair outside
+    let clamp = |p: (f64, f64)| (p.0.max(0.0).min(max.0), p.1.max(0.0).min(max.1));
+    Some((clamp(a), clamp(b)))
+}
+
+/// Pixels on the line from p0 to p1 including both ends, clipped to an image of the size
+/// so far away ends don't make long lines of pixels that are never drawn
+fn line_pixels(p0: (f32, f32), p1: (f32, f32), size: (usize, usize)) -> Vec<(i64, i64)> {
+    if size.0 == 0 || size.1 == 0 {
+        return Vec::new();
+    }
+    let max = ((size.0 - 1) as f64, (size.1 - 1) as f64);
+    let start = (p0.0.floor() as f64, p0.1.floor() as f64);
+    let end = (p1.0.floor() as f64, p1.1.floor() as f64);
+    let (start, end) = match clip_segment(start, end, max) {
+        Some(clipped) => clipped,
+        None => return Vec::new(),
+    };
+    let (mut x, mut y) = (start.0.round() as i64, start.1.round() as i64);
+    let (x1, y1) = (end.0.round() as i64, end.1.round() as i64);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let sx = if x < x1 { 1 } else { -1 };
+    let sy = if y < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    let mut pixels = Vec::with_capacity((dx - dy) as usize + 1);
+    loop {
+        pixels.push((x, y));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    pixels
+}
+
+impl Image {
+    /// Blend the color over the pixel, positions outside of the image are ignored
+    pub fn blend_pixel(&mut self, x: i64, y: i64, color: Color32) {
+        if x < 0 || y < 0 || x >= self.size.0 as i64 || y >= self.size.1 as i64 {
+            return;
+        }
+        let ind = y as usize * self.size.0 + x as usize;
+        self.pixels[ind] = blend_over(self.pixels[ind], color);
+    }
+
+    /// Blend the color over the pixels from x0 up to but not including x1 in row y
+    fn blend_span(&mut self, x0: i64, x1: i64, y: i64, color: Color32) {
+        if y < 0 || y >= self.size.1 as i64 {
+            return;
+        }
+        let x0 = x0.max(0) as usize;
+        let x1 = x1.min(self.size.0 as i64);
+        if x1 <= x0 as i64 {
+            return;
+        }
+        let ind = y as usize * self.size.0;
+        for pixel in self.pixels[ind + x0..ind + x1 as usize].iter_mut() {
+            *pixel = blend_over(*pixel, color);
+        }
+    }
+
+    pub fn draw_line(&mut self, p0: (f32, f32), p1: (f32, f32), color: Color32) {
+        for (x, y) in line_pixels(p0, p1, self.size) {
+            self.blend_pixel(x, y, color);
+        }
+    }
+
+    /// Connected line segments, closed joins the last point back to the first
+    pub fn draw_polyline(&mut self, points: &[(f32, f32)], closed: bool, color: Color32) {
+        let size = self.size;
+        let mut pixels: Vec<(i64, i64)> = Vec::new();
+        let mut segment = |p0, p1| {
+            let line = line_pixels(p0, p1, size);
+            // the joint pixel was already drawn with the previous segment
+            let skip = if pixels.last() == line.first() { 1 } else { 0 };
+            pixels.extend(line.into_iter().skip(skip));
+        };
+        for pair in points.windows(2) {
+            segment(pair[0], pair[1]);
+        }
+        if closed && points.len() > 2 {
+            segment(points[points.len() - 1], points[0]);
+            if pixels.len() > 1 && pixels.first() == pixels.last() {
+                pixels.pop();
+            }
+        }
+        if points.len() == 1 {
+            pixels = line_pixels(points[0], points[0], size);
+        }
+        for (x, y) in pixels {
+            self.blend_pixel(x, y, color);
+        }
+    }
+
+    /// Fill the pixels from min up to but not including max
+    pub fn fill_rect(&mut self, min: (f32, f32), max: (f32, f32), color: Color32) {
+        let (x0, x1) = (min.0.floor() as i64, max.0.floor() as i64);
+        for y in min.1.floor() as i64..max.1.floor() as i64 {
+            self.blend_span(x0, x1, y, color);
+        }
+    }
+
+    /// One pixel wide outline just inside the rectangle filled by fill_rect
+    pub fn draw_rect(&mut self, min: (f32, f32), max: (f32, f32), color: Color32) {
+        let (x0, y0) = (min.0.floor() as i64, min.1.floor() as i64);
+        let (x1, y1) = (max.0.floor() as i64 - 1, max.1.floor() as i64 - 1);
+        if x1 < x0 || y1 < y0 {
+            return;
+        }
+        self.blend_span(x0, x1 + 1, y0, color);
+        if y1 > y0 {
+            self.blend_span(x0, x1 + 1, y1, color);
+        }
+        for y in y0 + 1..y1 {
+            self.blend_pixel(x0, y, color);
+            if x1 > x0 {
+                self.blend_pixel(x1, y, color);
+            }
+        }
+    }
+
+    /// Blend the color over the pixels around the ellipse that inside accepts, given
+    /// their normalized distance from the center (1.0 is on the ellipse) and the smaller radius.
+    fn ellipse_pixels(
+        &mut self,
+        center: (f32, f32),
+        radii: (f32, f32),
+        color: Color32,
+        inside: impl Fn(f32, f32) -> bool,
+    ) {
+        let rx = radii.0.abs().max(0.5);
+        let ry = radii.1.abs().max(0.5);
+        let y0 = (center.1 - ry - 1.0).floor() as i64;
+        let y1 = (center.1 + ry + 1.0).ceil() as i64;
+        let x0 = (center.0 - rx - 1.0).floor() as i64;
+        let x1 = (center.0 + rx + 1.0).ceil() as i64;
+        for y in y0.max(0)..y1.min(self.size.1 as i64) {
+            for x in x0.max(0)..x1.min(self.size.0 as i64) {
+                let dx = (x as f32 + 0.5 - center.0) / rx;
+                let dy = (y as f32 + 0.5 - center.1) / ry;
+                let dist = (dx * dx + dy * dy).sqrt();
+                if inside(dist, rx.min(ry)) {
+                    self.blend_pixel(x, y, color);
+                }
+            }
+        }
+    }
+
+    pub fn fill_ellipse(&mut self, center: (f32, f32), radii: (f32, f32), color: Color32) {
+        self.ellipse_pixels(center, radii, color, |dist, _| dist <= 1.0);
+    }
+
+    /// About one pixel wide outline of the ellipse
+    pub fn draw_ellipse(&mut self, center: (f32, f32), radii: (f32, f32), color: Color32) {
+        self.ellipse_pixels(center, radii, color, |dist, r| {
+            ((dist - 1.0) * r).abs() <= 0.5
+        });
+    }
+
+    pub fn fill_circle(&mut self, center: (f32, f32), radius: f32, color: Color32) {
+        self.fill_ellipse(center, (radius, radius), color);
+    }
+
+    pub fn draw_circle(&mut self, center: (f32, f32), radius: f32, color: Color32) {
+        self.draw_ellipse(center, (radius, radius), color);
+    }
+
+    /// Scanline fill with the even-odd rule, pixels are filled where their centers
+    /// are inside, so polygons sharing an edge don't overlap.
+    pub fn fill_polygon(&mut self, points: &[(f32, f32)], color: Color32) {
+        if points.len() < 3 {
+            return;
+        }
+        let min_y = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+        let max_y = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+        let y0 = (min_y.floor() as i64).max(0);
+        let y1 = (max_y.ceil() as i64).min(self.size.1 as i64);
+        let mut crossings = Vec::new();
+        for y in y0..y1 {
+            let yc = y as f32 + 0.5;
+            crossings.clear();
+            for (ind, p0) in points.iter().enumerate() {
+                let p1 = points[(ind + 1) % points.len()];
+                // half open so a vertex on the scanline is only counted once
+                if (p0.1 <= yc) != (p1.1 <= yc) {
+                    crossings.push(p0.0 + (yc - p0.1) / (p1.1 - p0.1) * (p1.0 - p0.0));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for pair in crossings.chunks(2) {
+                if let [xa, xb] = pair {
+                    // pixels with centers in xa..xb
+                    let x0 = (xa - 0.5).ceil() as i64;
+                    let x1 = (xb - 0.5).ceil() as i64;
+                    self.blend_span(x0, x1, y, color);
+                }
+            }
+        }
+    }
+
+    /// Text with the top left at pos, each font pixel drawn as a scale x scale block
+    pub fn draw_text(&mut self, pos: (f32, f32), text: &str, scale: usize, color: Color32) {
+        let scale = scale.max(1);
+        let x0 = pos.0.floor() as i64;
+        let mut y = pos.1.floor() as i64;
+        for line in text.lines() {
+            let mut x = x0;
+            for ch in line.chars() {
+                for (col, bits) in glyph(ch).iter().enumerate() {
+                    for row in 0..GLYPH_HEIGHT {
+                        if bits & (1 << row) == 0 {
+                            continue;
+                        }
+                        let px = x + (col * scale) as i64;
+                        let py = y + (row * scale) as i64;
+                        for sy in 0..scale as i64 {
+                            self.blend_span(px, px + scale as i64, py + sy, color);
+                        }
+                    }
+                }
+                x += (ADVANCE * scale) as i64;
+            }
+            y += (LINE_HEIGHT * scale) as i64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank(width: usize, height: usize) -> Image {
+        Image {
+            size: (width, height),
+            pixels: vec![Color32::TRANSPARENT; width * height],
+        }
+    }
+
+    /// '#' for the pixels drawn on, a row per line
+    fn drawn(image: &Image) -> String {
+        image
+            .pixels
+            .chunks(image.size.0)
+            .map(|row| {
+                row.iter()
+                    .map(|p| if p.a() > 0 { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn blending() {
+        let half = Color32::from_rgba_premultiplied(100, 0, 0, 128);
+        assert_eq!(blend_over(Color32::BLUE, Color32::RED), Color32::RED);
+        assert_eq!(
+            blend_over(Color32::BLACK, half),
+            Color32::from_rgba_premultiplied(100, 0, 0, 255)
+        );
+        assert_eq!(
+            blend_over(Color32::from_rgb(0, 0, 200), half),
+            Color32::from_rgba_premultiplied(100, 0, 100, 255)
+        );
+        assert_eq!(blend_over(Color32::TRANSPARENT, half), half);
+    }
+
+    #[test]
+    fn lines() {
+        let mut image = blank(6, 3);
+        image.draw_line((1.0, 1.0), (4.9, 1.5), Color32::WHITE);
+        assert_eq!(drawn(&image), "......\n.####.\n......");
+
+        let mut image = blank(4, 4);
+        image.draw_line((3.5, 0.0), (0.0, 3.5), Color32::WHITE);
+        assert_eq!(drawn(&image), "...#\n..#.\n.#..\n#...");
+    }
+
+    #[test]
+    fn clipped_lines() {
+        let mut image = blank(5, 3);
+        image.draw_line((-10.0, 1.5), (20.0, 1.5), Color32::WHITE);
+        assert_eq!(drawn(&image), ".....\n#####\n.....");
+
+        let mut image = blank(4, 4);
+        image.draw_line((-2.0, -2.0), (1e6, 1e6), Color32::WHITE);
+        assert_eq!(drawn(&image), "#...\n.#..\n..#.\n...#");
+
+        let mut image = blank(4, 4);
+        image.draw_line((-5.0, -1.0), (10.0, -1.0), Color32::WHITE);
+        image.draw_line((4.0, 0.0), (4.0, 3.0), Color32::WHITE);
+        assert_eq!(drawn(&image), "....\n....\n....\n....");
+
+        let mut image = blank(0, 0);
+        image.draw_line((0.0, 0.0), (1.0, 1.0), Color32::WHITE);
+        assert!(image.pixels.is_empty());
+    }
+
+    #[test]
+    fn joints_blend_once() {
+        let mut image = blank(4, 4);
+        let half = Color32::from_rgba_premultiplied(0, 0, 128, 128);
+        let square = [(0.0, 0.0), (3.0, 0.0), (3.0, 3.0), (0.0, 3.0)];
+        image.draw_polyline(&square, true, half);
+        assert_eq!(drawn(&image), "####\n#..#\n#..#\n####");
+        assert!(image.pixels.iter().all(|p| *p == half || p.a() == 0));
+    }
+
+    #[test]
+    fn rects() {
+        let mut image = blank(6, 5);
+        image.draw_rect((1.0, 1.0), (5.0, 4.0), Color32::WHITE);
+        assert_eq!(drawn(&image), "......\n.####.\n.#..#.\n.####.\n......");
+
+        let mut image = blank(6, 5);
+        image.fill_rect((1.0, 1.0), (5.0, 4.0), Color32::WHITE);
+        assert_eq!(drawn(&image), "......\n.####.\n.####.\n.####.\n......");
+
+        // the sides outside of the image are left out
+        let mut image = blank(4, 4);
+        image.draw_rect((-2.0, -2.0), (3.0, 3.0), Color32::WHITE);
+        assert_eq!(drawn(&image), "..#.\n..#.\n###.\n....");
+
+        let mut image = blank(3, 3);
+        image.fill_rect((-1.0, -1.0), (10.0, 2.0), Color32::WHITE);
+        image.fill_rect((2.0, 2.0), (2.0, 5.0), Color32::WHITE);
+        assert_eq!(drawn(&image), "###\n###\n...");
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(text_size("", 1), (0, GLYPH_HEIGHT));
+        assert_eq!(text_size("ab\ncde", 2), (34, 32));
+
+        let mut image = blank(5, 7);
+        image.draw_text((0.0, 0.0), "|", 1, Color32::WHITE);
+        assert_eq!(drawn(&image), ["..#.."; 7].join("\n"));
+
+        let mut image = blank(10, 14);
+        image.draw_text((0.0, 0.0), "|", 2, Color32::WHITE);
+        assert_eq!(drawn(&image), ["....##...."; 14].join("\n"));
+
+        // everything drawn is within the text size
+        let text = "Hi!\n@#";
+        let size = text_size(text, 1);
+        let mut image = blank(size.0 + 4, size.1 + 4);
+        image.draw_text((0.0, 0.0), text, 1, Color32::WHITE);
+        for (ind, p) in image.pixels.iter().enumerate() {
+            let (x, y) = (ind % image.size.0, ind / image.size.0);
+            assert!(p.a() == 0 || (x < size.0 && y < size.1), "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn clipped_text() {
+        let mut image = blank(3, 3);
+        image.draw_text((-2.0, -3.0), "|", 1, Color32::WHITE);
+        assert_eq!(drawn(&image), "#..\n#..\n#..");
+
+        let mut image = blank(3, 3);
+        image.draw_text((3.0, 0.0), "|||", 1, Color32::WHITE);
+        image.draw_text((0.0, -20.0), "|", 2, Color32::WHITE);
+        assert_eq!(drawn(&image), "...\n...\n...");
+    }
+}
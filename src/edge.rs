@@ -0,0 +1,51 @@
+use eframe::egui::{self, Color32};
+
+/// What to use for pixels that come from outside of the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeMode {
+    /// Take the pixel from the opposite side of the image
+    Wrap,
+    /// Repeat the nearest edge pixel
+    Clamp,
+    Fill(Color32),
+}
+
+impl EdgeMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Wrap => "wrap",
+            EdgeMode::Clamp => "clamp",
+            EdgeMode::Fill(_) => "fill",
+        }
+    }
+
+    /// Index of the pixel to use for position i along an axis of length len,
+    /// None where the fill color should be used instead.
+    pub fn index(&self, i: i64, len: usize) -> Option<usize> {
+        if i >= 0 && (i as usize) < len {
+            return Some(i as usize);
+        }
+        match self {
+            EdgeMode::Wrap => Some(i.rem_euclid(len as i64) as usize),
+            EdgeMode::Clamp => Some(i.clamp(0, len as i64 - 1) as usize),
+            EdgeMode::Fill(_) => None,
+        }
+    }
+
+    /// Combo box to select the edge mode, with a color picker for the fill
+    pub fn ui(&mut self, ui: &mut egui::Ui, id_source: &str) {
+        let id = ui.make_persistent_id(id_source);
+        egui::combo_box(ui, id, self.name(), |ui| {
+            ui.selectable_value(self, EdgeMode::Wrap, "wrap");
+            ui.selectable_value(self, EdgeMode::Clamp, "clamp");
+            let fill = match self {
+                EdgeMode::Fill(color) => *color,
+                _ => Color32::BLUE,
+            };
+            ui.selectable_value(self, EdgeMode::Fill(fill), "fill");
+        });
+        if let EdgeMode::Fill(color) = self {
+            ui.color_edit_button_srgba(color);
+        }
+    }
+}
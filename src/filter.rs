@@ -0,0 +1,562 @@
+/*
+ * Neighborhood filters on Image: blurs, median, gradients, sharpening and
+ * convolution with user defined kernels.
+ *
+ * Pixels beyond the image borders are taken from the EdgeMode. The filters work
+ * on the premultiplied channels as floats, so blurs don't bleed the color of
+ * transparent pixels. Gradient and Laplacian outputs are opaque magnitudes.
+ */
+
+use crate::edge::EdgeMode;
+use crate::pixel::{add, from_f32, scale, to_f32};
+use crate::utility::Image;
+use eframe::egui;
+
+type Buffer = Vec<[f32; 4]>;
+
+/// Weights for a convolution, applied centered on each pixel without flipping
+#[derive(Clone, Debug, PartialEq)]
+pub struct Kernel {
+    pub size: (usize, usize),
+    pub weights: Vec<f32>,
+}
+
+impl Kernel {
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Result<Self, String> {
+        if width.is_multiple_of(2) || height.is_multiple_of(2) {
+            return Err(format!("kernel size {}x{} must be odd", width, height));
+        }
+        if weights.len() != width * height {
+            return Err(format!(
+                "kernel {}x{} needs {} weights not {}",
+                width,
+                height,
+                width * height,
+                weights.len()
+            ));
+        }
+        Ok(Self {
+            size: (width, height),
+            weights,
+        })
+    }
+
+    /// Scale the weights to sum to one, unless they sum to zero as for edge detectors
+    pub fn normalized(mut self) -> Self {
+        let total: f32 = self.weights.iter().sum();
+        if total.abs() > f32::EPSILON {
+            for weight in self.weights.iter_mut() {
+                *weight /= total;
+            }
+        }
+        self
+    }
+}
+
+/// Normalized 1D gaussian out to three sigma
+fn gaussian_weights(sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil().max(0.0) as i64;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+fn to_buffer(image: &Image) -> Buffer {
+    image.pixels.iter().map(|p| to_f32(*p)).collect()
+}
+
+fn from_buffer(size: (usize, usize), buffer: &[[f32; 4]]) -> Image {
+    Image {
+        size,
+        pixels: buffer.iter().map(|p| from_f32(*p)).collect(),
+    }
+}
+
+/// Pixel at a position that may be outside of the buffer
+fn get(buffer: &[[f32; 4]], size: (usize, usize), x: i64, y: i64, edge: EdgeMode) -> [f32; 4] {
+    match (edge.index(x, size.0), edge.index(y, size.1)) {
+        (Some(x), Some(y)) => buffer[y * size.0 + x],
+        _ => match edge {
+            EdgeMode::Fill(color) => to_f32(color),
+            _ => [0.0; 4],
+        },
+    }
+}
+
+/// Correlate with a full 2D kernel
+fn correlate(buffer: &[[f32; 4]], size: (usize, usize), kernel: &Kernel, edge: EdgeMode) -> Buffer {
+    let (kw, kh) = kernel.size;
+    let (rx, ry) = ((kw / 2) as i64, (kh / 2) as i64);
+    let mut out = Vec::with_capacity(buffer.len());
+    for y in 0..size.1 as i64 {
+        for x in 0..size.0 as i64 {
+            let mut sum = [0.0; 4];
+            for (ky, row) in kernel.weights.chunks(kw).enumerate() {
+                for (kx, w) in row.iter().enumerate() {
+                    if *w == 0.0 {
+                        continue;
+                    }
+                    let val = get(buffer, size, x + kx as i64 - rx, y + ky as i64 - ry, edge);
+                    sum = add(sum, scale(val, *w));
+                }
+            }
+            out.push(sum);
+        }
+    }
+    out
+}
+
+/// Correlate with odd length kernels along x then along y
+fn correlate_separable(
+    buffer: &[[f32; 4]],
+    size: (usize, usize),
+    x_weights: &[f32],
+    y_weights: &[f32],
+    edge: EdgeMode,
+) -> Buffer {
+    let pass = |src: &[[f32; 4]], weights: &[f32], horizontal: bool| {
+        let r = (weights.len() / 2) as i64;
+        let mut out = Vec::with_capacity(src.len());
+        for y in 0..size.1 as i64 {
+            for x in 0..size.0 as i64 {
+                let mut sum = [0.0; 4];
+                for (i, w) in weights.iter().enumerate() {
+                    let d = i as i64 - r;
+                    let val = if horizontal {
+                        get(src, size, x + d, y, edge)
+                    } else {
+                        get(src, size, x, y + d, edge)
+                    };
+                    sum = add(sum, scale(val, *w));
+                }
+                out.push(sum);
+            }
+        }
+        out
+    };
+    let tmp = pass(buffer, x_weights, true);
+    pass(&tmp, y_weights, false)
+}
+
+/// Opaque magnitude of each color channel
+fn magnitude(gx: &[[f32; 4]], gy: &[[f32; 4]], sc: f32) -> Buffer {
+    gx.iter()
+        .zip(gy.iter())
+        .map(|(a, b)| {
+            let mag = |c: usize| (a[c] * a[c] + b[c] * b[c]).sqrt() * sc;
+            [mag(0), mag(1), mag(2), 255.0]
+        })
+        .collect()
+}
+
+impl Image {
+    pub fn convolve(&self, kernel: &Kernel, edge: EdgeMode) -> Image {
+        from_buffer(
+            self.size,
+            &correlate(&to_buffer(self), self.size, kernel, edge),
+        )
+    }
+
+    pub fn convolve_separable(
+        &self,
+        x_weights: &[f32],
+        y_weights: &[f32],
+        edge: EdgeMode,
+    ) -> Image {
+        let out = correlate_separable(&to_buffer(self), self.size, x_weights, y_weights, edge);
+        from_buffer(self.size, &out)
+    }
+
+    pub fn gaussian_blur(&self, sigma: f32, edge: EdgeMode) -> Image {
+        if sigma <= 0.0 {
+            return self.clone();
+        }
+        let weights = gaussian_weights(sigma);
+        self.convolve_separable(&weights, &weights, edge)
+    }
+
+    /// Mean of the (2 * radius + 1) square around each pixel
+    pub fn box_blur(&self, radius: usize, edge: EdgeMode) -> Image {
+        let len = 2 * radius + 1;
+        let weights = vec![1.0 / len as f32; len];
+        self.convolve_separable(&weights, &weights, edge)
+    }
+
+    /// Median of each channel over the (2 * radius + 1) square around each pixel
+    pub fn median(&self, radius: usize, edge: EdgeMode) -> Image {
+        let buffer = to_buffer(self);
+        let r = radius as i64;
+        let mut window: Vec<[f32; 4]> = Vec::with_capacity((2 * radius + 1).pow(2));
+        let mut channel = Vec::with_capacity(window.capacity());
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.size.1 as i64 {
+            for x in 0..self.size.0 as i64 {
+                window.clear();
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        window.push(get(&buffer, self.size, x + dx, y + dy, edge));
+                    }
+                }
+                let mut med = [0.0; 4];
+                for (c, val) in med.iter_mut().enumerate() {
+                    channel.clear();
+                    channel.extend(window.iter().map(|p| p[c]));
+                    let mid = channel.len() / 2;
+                    *val = *channel
+                        .select_nth_unstable_by(mid, |a: &f32, b: &f32| a.total_cmp(b))
+                        .1;
+                }
+                pixels.push(from_f32(med));
+            }
+        }
+        Image {
+            size: self.size,
+            pixels,
+        }
+    }
+
+    /// Gradient magnitude with the 3x3 Sobel operator, scaled so a full step is 255
+    pub fn sobel(&self, edge: EdgeMode) -> Image {
+        self.gradient(&[1.0, 2.0, 1.0], 1.0 / 4.0, edge)
+    }
+
+    /// Gradient magnitude with the more rotationally symmetric 3x3 Scharr operator
+    pub fn scharr(&self, edge: EdgeMode) -> Image {
+        self.gradient(&[3.0, 10.0, 3.0], 1.0 / 16.0, edge)
+    }
+
+    fn gradient(&self, smooth: &[f32], sc: f32, edge: EdgeMode) -> Image {
+        let diff = [-1.0, 0.0, 1.0];
+        let buffer = to_buffer(self);
+        let gx = correlate_separable(&buffer, self.size, &diff, smooth, edge);
+        let gy = correlate_separable(&buffer, self.size, smooth, &diff, edge);
+        from_buffer(self.size, &magnitude(&gx, &gy, sc))
+    }
+
+    /// Absolute value of the 4-neighbor Laplacian
+    pub fn laplacian(&self, edge: EdgeMode) -> Image {
+        let kernel = Kernel {
+            size: (3, 3),
+            weights: vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0],
+        };
+        let out: Buffer = correlate(&to_buffer(self), self.size, &kernel, edge)
+            .iter()
+            .map(|p| [p[0].abs(), p[1].abs(), p[2].abs(), 255.0])
+            .collect();
+        from_buffer(self.size, &out)
+    }
+
+    /// Sharpen by adding back amount times the difference from a gaussian blur
+    pub fn unsharp_mask(&self, sigma: f32, amount: f32, edge: EdgeMode) -> Image {
+        let blurred = self.gaussian_blur(sigma, edge);
+        let pixels = self
+            .pixels
+            .iter()
+            .zip(blurred.pixels.iter())
+            .map(|(p, b)| {
+                let p = to_f32(*p);
+                let b = to_f32(*b);
+                let sharp = |c: usize| p[c] + amount * (p[c] - b[c]);
+                from_f32([sharp(0), sharp(1), sharp(2), p[3]])
+            })
+            .collect();
+        Image {
+            size: self.size,
+            pixels,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    GaussianBlur,
+    BoxBlur,
+    Median,
+    Sobel,
+    Scharr,
+    Laplacian,
+    UnsharpMask,
+    Custom,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 8] = [
+        FilterKind::GaussianBlur,
+        FilterKind::BoxBlur,
+        FilterKind::Median,
+        FilterKind::Sobel,
+        FilterKind::Scharr,
+        FilterKind::Laplacian,
+        FilterKind::UnsharpMask,
+        FilterKind::Custom,
+    ];
+}
+
+#[derive(Clone, PartialEq)]
+struct FilterParams {
+    kind: FilterKind,
+    sigma: f32,
+    radius: usize,
+    amount: f32,
+    /// 3x3 user kernel
+    custom: [f32; 9],
+    normalize: bool,
+    edge: EdgeMode,
+}
+
+impl FilterParams {
+    fn apply(&self, image: &Image) -> Image {
+        match self.kind {
+            FilterKind::GaussianBlur => image.gaussian_blur(self.sigma, self.edge),
+            FilterKind::BoxBlur => image.box_blur(self.radius, self.edge),
+            FilterKind::Median => image.median(self.radius, self.edge),
+            FilterKind::Sobel => image.sobel(self.edge),
+            FilterKind::Scharr => image.scharr(self.edge),
+            FilterKind::Laplacian => image.laplacian(self.edge),
+            FilterKind::UnsharpMask => image.unsharp_mask(self.sigma, self.amount, self.edge),
+            FilterKind::Custom => {
+                let mut kernel = Kernel {
+                    size: (3, 3),
+                    weights: self.custom.to_vec(),
+                };
+                if self.normalize {
+                    kernel = kernel.normalized();
+                }
+                image.convolve(&kernel, self.edge)
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        match self.kind {
+            FilterKind::GaussianBlur => format!("gaussian blur {:.1}", self.sigma),
+            FilterKind::BoxBlur => format!("box blur {}", self.radius),
+            FilterKind::Median => format!("median {}", self.radius),
+            FilterKind::UnsharpMask => {
+                format!("unsharp mask {:.1} {:.1}", self.sigma, self.amount)
+            }
+            kind => format!("{:?}", kind).to_lowercase(),
+        }
+    }
+}
+
+/// Panel for choosing a filter, previewing it on the current image and applying it
+pub struct FilterTool {
+    params: FilterParams,
+    pub preview: bool,
+    /// the params and image generation the preview was made from, so it is only redone on changes
+    cache: Option<(FilterParams, u64, Image)>,
+}
+
+impl Default for FilterTool {
+    fn default() -> Self {
+        Self {
+            params: FilterParams {
+                kind: FilterKind::GaussianBlur,
+                sigma: 2.0,
+                radius: 1,
+                amount: 1.0,
+                custom: [0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0],
+                normalize: false,
+                edge: EdgeMode::Clamp,
+            },
+            preview: false,
+            cache: None,
+        }
+    }
+}
+
+impl FilterTool {
+    /// The filtered image to display in place of the source while previewing,
+    /// generation changes whenever the image does
+    pub fn preview(&mut self, image: &Image, generation: u64) -> Option<&Image> {
+        if !self.preview {
+            self.cache = None;
+            return None;
+        }
+        let stale = match &self.cache {
+            Some((params, cached, _)) => *params != self.params || *cached != generation,
+            None => true,
+        };
+        if stale {
+            let filtered = self.params.apply(image);
+            self.cache = Some((self.params.clone(), generation, filtered));
+        }
+        self.cache.as_ref().map(|(_, _, filtered)| filtered)
+    }
+
+    /// Returns the name of the filter and the filtered image when it is applied
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        image: &Image,
+        generation: u64,
+    ) -> Option<(String, Image)> {
+        let params = &mut self.params;
+        let id = ui.make_persistent_id("filter_kind");
+        egui::combo_box(ui, id, format!("{:?}", params.kind), |ui| {
+            for kind in FilterKind::ALL.iter() {
+                ui.selectable_value(&mut params.kind, *kind, format!("{:?}", kind));
+            }
+        });
+
+        match params.kind {
+            FilterKind::GaussianBlur | FilterKind::UnsharpMask => {
+                ui.add(egui::Slider::f32(&mut params.sigma, 0.1..=20.0).text("sigma"));
+            }
+            FilterKind::BoxBlur | FilterKind::Median => {
+                ui.add(egui::Slider::usize(&mut params.radius, 0..=10).text("radius"));
+            }
+            FilterKind::Custom => {
+                for row in params.custom.chunks_mut(3) {
+                    ui.horizontal(|ui| {
+                        for weight in row.iter_mut() {
+                            ui.add(egui::DragValue::f32(weight).speed(0.1));
+                        }
+                    });
+                }
+                ui.checkbox(&mut params.normalize, "normalize");
+            }
+            _ => {}
+        }
+        if params.kind == FilterKind::UnsharpMask {
+            ui.add(egui::Slider::f32(&mut params.amount, 0.0..=5.0).text("amount"));
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("border");
+            params.edge.ui(ui, "filter_edge");
+        });
+
+        let mut edit = None;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.preview, "preview");
+            if ui.button("apply").clicked {
+                let filtered = match self.cache.take() {
+                    Some((params, cached, filtered))
+                        if params == self.params && cached == generation =>
+                    {
+                        filtered
+                    }
+                    _ => self.params.apply(image),
+                };
+                edit = Some((self.params.name(), filtered));
+            }
+        });
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::egui::Color32;
+
+    fn gray(values: &[u8], width: usize) -> Image {
+        Image {
+            size: (width, values.len() / width),
+            pixels: values.iter().map(|v| Color32::from_gray(*v)).collect(),
+        }
+    }
+
+    fn values(image: &Image) -> Vec<u8> {
+        image.pixels.iter().map(|p| p.r()).collect()
+    }
+
+    const EDGES: [EdgeMode; 3] = [
+        EdgeMode::Wrap,
+        EdgeMode::Clamp,
+        EdgeMode::Fill(Color32::BLACK),
+    ];
+
+    #[test]
+    fn kernels() {
+        assert!(Kernel::new(2, 3, vec![0.0; 6]).is_err());
+        assert!(Kernel::new(3, 3, vec![0.0; 8]).is_err());
+        let kernel = Kernel::new(3, 1, vec![1.0, 2.0, 1.0]).unwrap().normalized();
+        assert_eq!(kernel.weights, vec![0.25, 0.5, 0.25]);
+        // zero sum kernels are left as they are
+        let kernel = Kernel::new(3, 1, vec![-1.0, 0.0, 1.0])
+            .unwrap()
+            .normalized();
+        assert_eq!(kernel.weights, vec![-1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn identity() {
+        let image = gray(&[0, 10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110], 4);
+        let mut weights = vec![0.0; 9];
+        weights[4] = 1.0;
+        let kernel = Kernel::new(3, 3, weights).unwrap();
+        for edge in EDGES.iter() {
+            assert_eq!(values(&image.convolve(&kernel, *edge)), values(&image));
+            assert_eq!(
+                values(&image.convolve_separable(&[1.0], &[1.0], *edge)),
+                values(&image)
+            );
+            assert_eq!(values(&image.gaussian_blur(0.0, *edge)), values(&image));
+        }
+    }
+
+    #[test]
+    fn box_blur_edges() {
+        let flat = gray(&[90; 9], 3);
+        assert_eq!(values(&flat.box_blur(1, EdgeMode::Wrap)), vec![90; 9]);
+        assert_eq!(values(&flat.box_blur(1, EdgeMode::Clamp)), vec![90; 9]);
+        // black from beyond the borders darkens the edges and more so the corners
+        let filled = flat.box_blur(1, EdgeMode::Fill(Color32::BLACK));
+        assert_eq!(values(&filled), vec![40, 60, 40, 60, 90, 60, 40, 60, 40]);
+        // and transparent fill makes them translucent too
+        let filled = flat.box_blur(1, EdgeMode::Fill(Color32::TRANSPARENT));
+        assert_eq!(filled.pixels[0].a(), 113);
+
+        let dot = gray(&[0, 0, 0, 0, 0, 0, 0, 180, 0, 0, 0, 0, 0, 0, 0], 5);
+        let blurred = dot.box_blur(1, EdgeMode::Clamp);
+        assert_eq!(
+            values(&blurred),
+            vec![0, 20, 20, 20, 0, 0, 20, 20, 20, 0, 0, 20, 20, 20, 0]
+        );
+    }
+
+    #[test]
+    fn sharpen() {
+        let kernel = Kernel::new(3, 3, vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0]);
+        let kernel = kernel.unwrap();
+        let flat = gray(&[77; 6], 3);
+        assert_eq!(
+            values(&flat.convolve(&kernel, EdgeMode::Clamp)),
+            vec![77; 6]
+        );
+        // overshoots either side of a step, saturating at the top
+        let step = gray(&[50, 50, 200, 200], 4);
+        assert_eq!(
+            values(&step.convolve(&kernel, EdgeMode::Clamp)),
+            vec![50, 0, 255, 200]
+        );
+        let sharpened = step.unsharp_mask(1.0, 1.0, EdgeMode::Clamp);
+        let sharpened = values(&sharpened);
+        assert!(sharpened[1] < 50 && sharpened[2] > 200, "{:?}", sharpened);
+        assert_eq!(
+            values(&flat.unsharp_mask(1.0, 2.0, EdgeMode::Wrap)),
+            vec![77; 6]
+        );
+    }
+
+    #[test]
+    fn median_and_gradients() {
+        let speck = gray(&[10, 10, 10, 10, 250, 10, 10, 10, 10], 3);
+        assert_eq!(values(&speck.median(1, EdgeMode::Clamp)), vec![10; 9]);
+        let flat = gray(&[60; 9], 3);
+        assert_eq!(values(&flat.sobel(EdgeMode::Clamp)), vec![0; 9]);
+        assert_eq!(values(&flat.laplacian(EdgeMode::Clamp)), vec![0; 9]);
+        // a full step is 255 on both sides of it
+        let step = gray(&[0, 0, 255, 255, 0, 0, 255, 255], 4);
+        assert_eq!(
+            values(&step.sobel(EdgeMode::Clamp)),
+            vec![0, 255, 255, 0, 0, 255, 255, 0]
+        );
+        assert_eq!(values(&step.scharr(EdgeMode::Clamp))[1], 255);
+    }
+}
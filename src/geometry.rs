@@ -0,0 +1,473 @@
+/*
+ * Geometry operations on Image: crop, flip, rotate and resize.
+ */
+
+use crate::pixel::{add, from_f32, lerp, scale, to_f32};
+use crate::utility::Image;
+use eframe::egui::{self, Color32};
+use std::f32::consts::PI;
+
+/// Resampling filter used when resizing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos3,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 4] = [
+        Filter::Nearest,
+        Filter::Bilinear,
+        Filter::Bicubic,
+        Filter::Lanczos3,
+    ];
+
+    /// Half width of the filter kernel in source pixels
+    fn support(&self) -> f32 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Bicubic => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::Bicubic => {
+                // Catmull-Rom
+                let a = -0.5;
+                if x < 1.0 {
+                    (a + 2.0) * x * x * x - (a + 3.0) * x * x + 1.0
+                } else if x < 2.0 {
+                    a * x * x * x - 5.0 * a * x * x + 8.0 * a * x - 4.0 * a
+                } else {
+                    0.0
+                }
+            }
+            Filter::Lanczos3 => {
+                if x < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// For each destination index the source indices and their normalized weights
+fn resample_weights(src_len: usize, dst_len: usize, filter: Filter) -> Vec<Vec<(usize, f32)>> {
+    let sc = dst_len as f32 / src_len as f32;
+    // widen the kernel when shrinking so every source pixel contributes
+    let filter_scale = (1.0 / sc).max(1.0);
+    let support = filter.support() * filter_scale;
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) / sc;
+            if filter == Filter::Nearest {
+                return vec![((center as usize).min(src_len - 1), 1.0)];
+            }
+            let left = (center - support).floor() as i64;
+            let right = (center + support).ceil() as i64;
+            let mut weights: Vec<(usize, f32)> = (left..=right)
+                .map(|j| {
+                    let w = filter.weight((j as f32 + 0.5 - center) / filter_scale);
+                    (j.max(0).min(src_len as i64 - 1) as usize, w)
+                })
+                .filter(|(_, w)| *w != 0.0)
+                .collect();
+            let total: f32 = weights.iter().map(|(_, w)| w).sum();
+            for (_, w) in weights.iter_mut() {
+                *w /= total;
+            }
+            weights
+        })
+        .collect()
+}
+
+impl Image {
+    /// The part of the image within the rectangle, clipped to the image bounds
+    pub fn crop(&self, x0: usize, y0: usize, width: usize, height: usize) -> Image {
+        let x0 = x0.min(self.size.0);
+        let y0 = y0.min(self.size.1);
+        let width = width.min(self.size.0 - x0);
+        let height = height.min(self.size.1 - y0);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in y0..(y0 + height) {
+            let ind = y * self.size.0 + x0;
+            pixels.extend_from_slice(&self.pixels[ind..ind + width]);
+        }
+        Image {
+            size: (width, height),
+            pixels,
+        }
+    }
+
+    pub fn flip_horizontal(&mut self) {
+        if self.size.0 == 0 || self.size.1 == 0 {
+            return;
+        }
+        for row in self.pixels.chunks_mut(self.size.0) {
+            row.reverse();
+        }
+    }
+
+    pub fn flip_vertical(&mut self) {
+        let (width, height) = self.size;
+        for y in 0..height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    /// Rotate a quarter turn clockwise
+    pub fn rotate90(&self) -> Image {
+        let (width, height) = self.size;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..width {
+            for x in 0..height {
+                pixels.push(self.pixels[(height - 1 - x) * width + y]);
+            }
+        }
+        Image {
+            size: (height, width),
+            pixels,
+        }
+    }
+
+    pub fn rotate180(&mut self) {
+        self.pixels.reverse();
+    }
+
+    /// Rotate a quarter turn counterclockwise
+    pub fn rotate270(&self) -> Image {
+        let (width, height) = self.size;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..width {
+            for x in 0..height {
+                pixels.push(self.pixels[x * width + (width - 1 - y)]);
+            }
+        }
+        Image {
+            size: (height, width),
+            pixels,
+        }
+    }
+
+    /// Bilinear interpolation at a position where pixel centers are at +0.5,
+    /// pixels outside of the image are taken to be the fill color.
+    pub fn sample_bilinear(&self, x: f32, y: f32, fill: Color32) -> [f32; 4] {
+        let x = x - 0.5;
+        let y = y - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+        let get = |xi: f32, yi: f32| {
+            if xi < 0.0 || yi < 0.0 || xi as usize >= self.size.0 || yi as usize >= self.size.1 {
+                to_f32(fill)
+            } else {
+                to_f32(self.pixels[yi as usize * self.size.0 + xi as usize])
+            }
+        };
+        let top = lerp(get(x0, y0), get(x0 + 1.0, y0), fx);
+        let bottom = lerp(get(x0, y0 + 1.0), get(x0 + 1.0, y0 + 1.0), fx);
+        lerp(top, bottom, fy)
+    }
+
+    /// Rotate counterclockwise about the center by an arbitrary angle in radians,
+    /// keeping the same size and filling the uncovered corners.
+    pub fn rotate(&self, angle: f32, fill: Color32) -> Image {
+        let (width, height) = self.size;
+        let cx = width as f32 / 2.0;
+        let cy = height as f32 / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..height {
+            for x in 0..width {
+                let dx = x as f32 + 0.5 - cx;
+                let dy = y as f32 + 0.5 - cy;
+                let src_x = cx + dx * cos - dy * sin;
+                let src_y = cy + dx * sin + dy * cos;
+                pixels.push(from_f32(self.sample_bilinear(src_x, src_y, fill)));
+            }
+        }
+        Image {
+            size: self.size,
+            pixels,
+        }
+    }
+
+    pub fn resize(&self, width: usize, height: usize, filter: Filter) -> Image {
+        let (src_width, src_height) = self.size;
+        if width == 0 || height == 0 || src_width == 0 || src_height == 0 {
+            return Image {
+                size: (width, height),
+                pixels: vec![Color32::TRANSPARENT; width * height],
+            };
+        }
+
+        // horizontal then vertical pass
+        let x_weights = resample_weights(src_width, width, filter);
+        let mut tmp = Vec::with_capacity(width * src_height);
+        for row in self.pixels.chunks(src_width) {
+            for weights in x_weights.iter() {
+                let mut sum = [0.0; 4];
+                for (ind, w) in weights {
+                    sum = add(sum, scale(to_f32(row[*ind]), *w));
+                }
+                tmp.push(sum);
+            }
+        }
+
+        let y_weights = resample_weights(src_height, height, filter);
+        let mut pixels = Vec::with_capacity(width * height);
+        for weights in y_weights.iter() {
+            for x in 0..width {
+                let mut sum = [0.0; 4];
+                for (ind, w) in weights {
+                    sum = add(sum, scale(tmp[ind * width + x], *w));
+                }
+                pixels.push(from_f32(sum));
+            }
+        }
+        Image {
+            size: (width, height),
+            pixels,
+        }
+    }
+}
+
+/// Toolbar for applying geometry operations in the viewer
+pub struct GeometryTool {
+    crop_min: (i32, i32),
+    crop_size: (i32, i32),
+    /// degrees counterclockwise
+    angle: f32,
+    resize: (i32, i32),
+    filter: Filter,
+}
+
+impl Default for GeometryTool {
+    fn default() -> Self {
+        Self {
+            crop_min: (0, 0),
+            crop_size: (128, 128),
+            angle: 15.0,
+            resize: (512, 512),
+            filter: Filter::Bilinear,
+        }
+    }
+}
+
+impl GeometryTool {
+    /// Returns the name of the operation and the resulting image when one is applied
+    pub fn ui(&mut self, ui: &mut egui::Ui, image: &Image) -> Option<(String, Image)> {
+        let mut edit = None;
+        ui.horizontal(|ui| {
+            if ui.button("flip h").clicked {
+                let mut flipped = image.clone();
+                flipped.flip_horizontal();
+                edit = Some(("flip horizontal".to_string(), flipped));
+            }
+            if ui.button("flip v").clicked {
+                let mut flipped = image.clone();
+                flipped.flip_vertical();
+                edit = Some(("flip vertical".to_string(), flipped));
+            }
+            if ui.button("rotate 90").clicked {
+                edit = Some(("rotate 90".to_string(), image.rotate90()));
+            }
+            if ui.button("rotate 180").clicked {
+                let mut rotated = image.clone();
+                rotated.rotate180();
+                edit = Some(("rotate 180".to_string(), rotated));
+            }
+            if ui.button("rotate 270").clicked {
+                edit = Some(("rotate 270".to_string(), image.rotate270()));
+            }
+
+            ui.separator();
+            ui.add(egui::DragValue::f32(&mut self.angle).suffix("°"));
+            if ui.button("rotate").clicked {
+                let rotated = image.rotate(self.angle.to_radians(), Color32::TRANSPARENT);
+                edit = Some((format!("rotate {}°", self.angle), rotated));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::i32(&mut self.crop_min.0).prefix("x "));
+            ui.add(egui::DragValue::i32(&mut self.crop_min.1).prefix("y "));
+            ui.add(egui::DragValue::i32(&mut self.crop_size.0).prefix("w "));
+            ui.add(egui::DragValue::i32(&mut self.crop_size.1).prefix("h "));
+            if ui.button("crop").clicked {
+                let cropped = image.crop(
+                    self.crop_min.0.max(0) as usize,
+                    self.crop_min.1.max(0) as usize,
+                    self.crop_size.0.max(0) as usize,
+                    self.crop_size.1.max(0) as usize,
+                );
+                if cropped.size.0 > 0 && cropped.size.1 > 0 {
+                    edit = Some(("crop".to_string(), cropped));
+                }
+            }
+
+            ui.separator();
+            ui.add(egui::DragValue::i32(&mut self.resize.0).prefix("w "));
+            ui.add(egui::DragValue::i32(&mut self.resize.1).prefix("h "));
+            let id = ui.make_persistent_id("resize_filter");
+            egui::combo_box(ui, id, format!("{:?}", self.filter), |ui| {
+                for filter in Filter::ALL.iter() {
+                    ui.selectable_value(&mut self.filter, *filter, format!("{:?}", filter));
+                }
+            });
+            if ui.button("resize").clicked && self.resize.0 > 0 && self.resize.1 > 0 {
+                let resized =
+                    image.resize(self.resize.0 as usize, self.resize.1 as usize, self.filter);
+                edit = Some((format!("resize {:?}", self.filter), resized));
+            }
+        });
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An opaque image whose red channel counts up from 0 in row major order
+    fn counting(width: usize, height: usize) -> Image {
+        Image {
+            size: (width, height),
+            pixels: (0..width * height)
+                .map(|ind| Color32::from_rgb(ind as u8, 0, 0))
+                .collect(),
+        }
+    }
+
+    fn reds(image: &Image) -> Vec<u8> {
+        image.pixels.iter().map(|p| p.r()).collect()
+    }
+
+    #[test]
+    fn crop() {
+        let image = counting(4, 3);
+        let cropped = image.crop(1, 1, 2, 2);
+        assert_eq!(cropped.size, (2, 2));
+        assert_eq!(reds(&cropped), vec![5, 6, 9, 10]);
+        // clipped to the image
+        let clipped = image.crop(3, 1, 5, 5);
+        assert_eq!(clipped.size, (1, 2));
+        assert_eq!(reds(&clipped), vec![7, 11]);
+        assert_eq!(image.crop(9, 9, 2, 2).size, (0, 0));
+    }
+
+    #[test]
+    fn flips() {
+        let mut image = counting(3, 3);
+        image.flip_horizontal();
+        assert_eq!(reds(&image), vec![2, 1, 0, 5, 4, 3, 8, 7, 6]);
+        let mut image = counting(2, 3);
+        image.flip_vertical();
+        assert_eq!(reds(&image), vec![4, 5, 2, 3, 0, 1]);
+        let mut empty = counting(0, 3);
+        empty.flip_horizontal();
+        empty.flip_vertical();
+        assert!(empty.pixels.is_empty());
+    }
+
+    #[test]
+    fn quarter_turns() {
+        // 0 1 2
+        // 3 4 5
+        let image = counting(3, 2);
+        let clockwise = image.rotate90();
+        assert_eq!(clockwise.size, (2, 3));
+        assert_eq!(reds(&clockwise), vec![3, 0, 4, 1, 5, 2]);
+        let counterclockwise = image.rotate270();
+        assert_eq!(counterclockwise.size, (2, 3));
+        assert_eq!(reds(&counterclockwise), vec![2, 5, 1, 4, 0, 3]);
+        assert_eq!(reds(&clockwise.rotate270()), reds(&image));
+
+        let mut half = image.clone();
+        half.rotate180();
+        let mut flipped = image.clone();
+        flipped.flip_horizontal();
+        flipped.flip_vertical();
+        assert_eq!(reds(&half), reds(&flipped));
+        assert_eq!(reds(&clockwise.rotate90()), reds(&half));
+    }
+
+    #[test]
+    fn rotate() {
+        let image = counting(5, 3);
+        assert_eq!(reds(&image.rotate(0.0, Color32::BLACK)), reds(&image));
+        // half a turn matches rotate180 to within rounding
+        let mut half = image.clone();
+        half.rotate180();
+        let rotated = image.rotate(PI, Color32::BLACK);
+        for (a, b) in reds(&rotated).iter().zip(reds(&half).iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 1, "{} {}", a, b);
+        }
+        // the corners of a square turned by 45 degrees come from outside
+        let turned = counting(8, 8).rotate(PI / 4.0, Color32::TRANSPARENT);
+        assert_eq!(turned.size, (8, 8));
+        assert_eq!(turned.pixels[0], Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn resize() {
+        let color = Color32::from_rgba_premultiplied(40, 80, 120, 200);
+        let flat = Image {
+            size: (5, 3),
+            pixels: vec![color; 15],
+        };
+        // every filter keeps a flat image flat, growing and shrinking odd sizes
+        for filter in Filter::ALL.iter() {
+            for (width, height) in [(2, 7), (11, 1), (5, 3)].iter() {
+                let resized = flat.resize(*width, *height, *filter);
+                assert_eq!(resized.size, (*width, *height));
+                for p in resized.pixels.iter() {
+                    let diff = to_f32(*p)
+                        .iter()
+                        .zip(to_f32(color).iter())
+                        .map(|(a, b)| (a - b).abs())
+                        .fold(0.0, f32::max);
+                    assert!(diff <= 1.0, "{:?} {:?}", filter, p);
+                }
+            }
+        }
+
+        let image = counting(3, 2);
+        let doubled = image.resize(6, 4, Filter::Nearest);
+        assert_eq!(&reds(&doubled)[..6], &[0, 0, 1, 1, 2, 2]);
+        assert_eq!(&reds(&doubled)[18..], &[3, 3, 4, 4, 5, 5]);
+        assert_eq!(reds(&image.resize(3, 2, Filter::Bilinear)), reds(&image));
+        // a row shrunk to one pixel is its mean
+        assert_eq!(
+            reds(&counting(4, 1).resize(1, 1, Filter::Bilinear)),
+            vec![2]
+        );
+        assert!(image.resize(0, 4, Filter::Bicubic).pixels.is_empty());
+    }
+}
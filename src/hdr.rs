@@ -0,0 +1,498 @@
+/*
+ * High dynamic range images: radiance .hdr through the image crate and OpenEXR
+ * through a reader of its own, since the image crate doesn't read exr.
+ *
+ * Both are decoded to linear float rgb(a) RawImages and shown through the tone
+ * mapping in the levels. The exr reader handles single part scanline files that
+ * are uncompressed or compressed with RLE, ZIPS or ZIP, with half, float or
+ * uint channels. Tiled and multipart files and the lossy and wavelet
+ * compressions (PIZ, PXR24, B44, DWA) aren't supported.
+ *
+ * Sizes and offsets in an exr header are checked before they are used, and the
+ * image can't be larger than its file could decompress to, so a broken file is
+ * an error rather than a panic or a huge allocation.
+ */
+
+use crate::raw::{Depth, RawImage};
+use std::io::BufRead;
+
+/// Most a block can grow when it's decompressed, the limit of deflate
+const MAX_RATIO: usize = 1032;
+
+/// Decode a radiance hdr into float rgb
+pub fn read_hdr(reader: impl BufRead) -> Result<RawImage, String> {
+    let decoder = image::codecs::hdr::HdrDecoder::new(reader).map_err(|why| why.to_string())?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr().map_err(|why| why.to_string())?;
+    Ok(RawImage {
+        size: (metadata.width as usize, metadata.height as usize),
+        channels: 3,
+        depth: Depth::F32,
+        data: pixels
+            .iter()
+            .flat_map(|pixel| pixel.0.iter().copied())
+            .collect(),
+        maxval: None,
+    })
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * (2.0f32).powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * (2.0f32).powi(exponent - 15),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SampleType {
+    Uint,
+    Half,
+    Float,
+}
+
+impl SampleType {
+    fn bytes(&self) -> usize {
+        match self {
+            SampleType::Half => 2,
+            _ => 4,
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    sample_type: SampleType,
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.at.checked_add(len).ok_or("the file is cut short")?;
+        let taken = self
+            .bytes
+            .get(self.at..end)
+            .ok_or("the file is cut short")?;
+        self.at = end;
+        Ok(taken)
+    }
+
+    /// A byte count, which can't be negative
+    fn size(&mut self) -> Result<usize, String> {
+        let size = self.i32()?;
+        if size < 0 {
+            return Err(format!("size {} is negative", size));
+        }
+        Ok(size as usize)
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        let b = self.take(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let b = self.take(8)?;
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(b);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// A null terminated string
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.bytes[self.at.min(self.bytes.len())..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or("unterminated string")?;
+        let text = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.at += len + 1;
+        Ok(text)
+    }
+}
+
+fn parse_channels(value: &[u8]) -> Result<Vec<Channel>, String> {
+    let mut cursor = Cursor {
+        bytes: value,
+        at: 0,
+    };
+    let mut channels = Vec::new();
+    loop {
+        let name = cursor.string()?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        let sample_type = match cursor.i32()? {
+            0 => SampleType::Uint,
+            1 => SampleType::Half,
+            2 => SampleType::Float,
+            other => return Err(format!("channel type {} isn't known", other)),
+        };
+        // linear flag and reserved bytes
+        cursor.take(4)?;
+        let (x_sampling, y_sampling) = (cursor.i32()?, cursor.i32()?);
+        if x_sampling != 1 || y_sampling != 1 {
+            return Err(format!("subsampled channel {} isn't supported", name));
+        }
+        channels.push(Channel { name, sample_type });
+    }
+}
+
+/// Undo the byte delta predictor and the split of even and odd bytes that
+/// come before RLE and ZIP compression.
+fn unpredict(data: &mut Vec<u8>) {
+    for ind in 1..data.len() {
+        data[ind] = data[ind - 1].wrapping_add(data[ind]).wrapping_sub(128);
+    }
+    let half = data.len().div_ceil(2);
+    let (first, second) = data.split_at(half);
+    let mut interleaved = Vec::with_capacity(data.len());
+    for (ind, byte) in first.iter().enumerate() {
+        interleaved.push(*byte);
+        if let Some(byte) = second.get(ind) {
+            interleaved.push(*byte);
+        }
+    }
+    *data = interleaved;
+}
+
+fn decode_rle(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    let mut at = 0;
+    while at < data.len() {
+        let count = data[at] as i8;
+        at += 1;
+        if count < 0 {
+            let len = (-(count as i32)) as usize;
+            decoded.extend_from_slice(data.get(at..at + len).ok_or("bad rle data")?);
+            at += len;
+        } else {
+            let byte = *data.get(at).ok_or("bad rle data")?;
+            decoded.extend(std::iter::repeat_n(byte, count as usize + 1));
+            at += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+/// Decode a scanline OpenEXR into float rgb, rgba or gray from its R G B A or Y channels
+pub fn read_exr(bytes: &[u8]) -> Result<RawImage, String> {
+    let mut cursor = Cursor { bytes, at: 0 };
+    if cursor.take(4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return Err("not an exr file".to_string());
+    }
+    let version = cursor.i32()?;
+    if version & 0x200 != 0 {
+        return Err("tiled exr isn't supported".to_string());
+    }
+    if version & 0x1800 != 0 {
+        return Err("multipart and deep exr aren't supported".to_string());
+    }
+
+    let mut channels = None;
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = cursor.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _type_name = cursor.string()?;
+        let size = cursor.size()?;
+        let value = cursor.take(size)?;
+        match name.as_str() {
+            "channels" => channels = Some(parse_channels(value)?),
+            "compression" => compression = value.first().copied(),
+            "dataWindow" => {
+                let mut window = Cursor {
+                    bytes: value,
+                    at: 0,
+                };
+                data_window = Some([window.i32()?, window.i32()?, window.i32()?, window.i32()?]);
+            }
+            _ => (),
+        }
+    }
+    let channels = channels
+        .filter(|channels| !channels.is_empty())
+        .ok_or("no channels in the header")?;
+    let [x_min, y_min, x_max, y_max] = data_window.ok_or("no dataWindow in the header")?;
+    let width = (x_max as i64 - x_min as i64 + 1).max(0) as usize;
+    let height = (y_max as i64 - y_min as i64 + 1).max(0) as usize;
+    let lines_per_chunk = match compression.ok_or("no compression in the header")? {
+        0..=2 => 1,
+        3 => 16,
+        4 => return Err("PIZ compression isn't supported".to_string()),
+        other => return Err(format!("compression {} isn't supported", other)),
+    };
+
+    // which output channel each file channel goes to, channels are sorted by name
+    let find = |name: &str| channels.iter().position(|channel| channel.name == name);
+    let outputs: Vec<usize> = if find("R").is_some() || find("G").is_some() || find("B").is_some() {
+        ["R", "G", "B"]
+            .iter()
+            .filter_map(|name| find(name))
+            .collect()
+    } else {
+        vec![find("Y").unwrap_or(0)]
+    };
+    let alpha = find("A");
+    let out_channels = if outputs.len() == 1 { 1 } else { 3 } + alpha.is_some() as usize;
+    let out_of = |ind: usize| {
+        if Some(ind) == alpha {
+            Some(out_channels - 1)
+        } else if outputs.len() == 1 {
+            (outputs[0] == ind).then_some(0)
+        } else {
+            ["R", "G", "B"]
+                .iter()
+                .position(|name| channels[ind].name == *name)
+        }
+    };
+
+    let chunks = height.div_ceil(lines_per_chunk);
+    let offsets = (0..chunks)
+        .map(|_| cursor.u64())
+        .collect::<Result<Vec<_>, _>>()?;
+    let pixel_bytes: usize = channels
+        .iter()
+        .map(|channel| channel.sample_type.bytes())
+        .sum();
+    let line_bytes = width
+        .checked_mul(pixel_bytes)
+        .ok_or("the dataWindow is too large")?;
+    let max_ratio = if compression == Some(0) { 1 } else { MAX_RATIO };
+    let fits = line_bytes
+        .checked_mul(height)
+        .is_some_and(|image_bytes| image_bytes / max_ratio <= bytes.len());
+    if !fits {
+        return Err(format!(
+            "a {} x {} dataWindow is too large for the file",
+            width, height
+        ));
+    }
+    // at most 6 bytes of floats for each byte of samples, so this fits too
+    let mut data = vec![0.0f32; width * height * out_channels];
+    // missing rgb channels stay black, missing alpha doesn't exist
+    for offset in offsets {
+        let mut chunk = Cursor {
+            bytes,
+            at: offset as usize,
+        };
+        let first_line = (chunk.i32()? as i64 - y_min as i64).max(0) as usize;
+        let size = chunk.size()?;
+        let packed = chunk.take(size)?;
+        let lines = lines_per_chunk.min(height.saturating_sub(first_line));
+        let expected = line_bytes * lines;
+        let block = if packed.len() >= expected {
+            // stored uncompressed when compression wouldn't make it smaller
+            packed.to_vec()
+        } else {
+            let mut unpacked = match compression {
+                Some(1) => decode_rle(packed)?,
+                // the output buffer doubles as it grows, and stops with an error
+                // rather than growing past the limit even when the block would fit
+                _ => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+                    packed,
+                    expected.saturating_mul(2),
+                )
+                .map_err(|why| format!("couldn't inflate a block: {:?}", why))?,
+            };
+            unpredict(&mut unpacked);
+            unpacked
+        };
+        if block.len() < expected {
+            return Err("a block is cut short".to_string());
+        }
+
+        let mut at = 0;
+        for line in 0..lines {
+            let y = first_line + line;
+            for (ind, channel) in channels.iter().enumerate() {
+                let sample_bytes = channel.sample_type.bytes();
+                let samples = &block[at..at + sample_bytes * width];
+                at += sample_bytes * width;
+                let out = match out_of(ind) {
+                    Some(out) => out,
+                    None => continue,
+                };
+                for (x, b) in samples.chunks(sample_bytes).enumerate() {
+                    let value = match channel.sample_type {
+                        SampleType::Half => half_to_f32(u16::from_le_bytes([b[0], b[1]])),
+                        SampleType::Float => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                        SampleType::Uint => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+                    };
+                    data[(y * width + x) * out_channels + out] = value;
+                }
+            }
+        }
+    }
+    Ok(RawImage {
+        size: (width, height),
+        channels: out_channels,
+        depth: Depth::F32,
+        data,
+        maxval: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The byte reordering and delta predictor unpredict undoes
+    fn predict(data: &[u8]) -> Vec<u8> {
+        let mut split: Vec<u8> = data.iter().step_by(2).copied().collect();
+        split.extend(data.iter().skip(1).step_by(2));
+        let mut predicted = split.clone();
+        for ind in 1..split.len() {
+            predicted[ind] = split[ind].wrapping_sub(split[ind - 1]).wrapping_add(128);
+        }
+        predicted
+    }
+
+    /// A scanline exr of half R G B channels, each line its own chunk
+    fn exr_bytes(width: usize, lines: &[Vec<[u16; 3]>], compression: u8) -> Vec<u8> {
+        let attribute = |bytes: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]| {
+            for text in [name, type_name].iter() {
+                bytes.extend_from_slice(text.as_bytes());
+                bytes.push(0);
+            }
+            bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(value);
+        };
+        let mut bytes = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"].iter() {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            for value in [1i32, 0, 1, 1].iter() {
+                channels.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        channels.push(0);
+        attribute(&mut bytes, "channels", "chlist", &channels);
+        attribute(&mut bytes, "compression", "compression", &[compression]);
+        let window: Vec<u8> = [0, 10, width as i32 - 1, 10 + lines.len() as i32 - 1]
+            .iter()
+            .flat_map(|v: &i32| v.to_le_bytes().to_vec())
+            .collect();
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        let chunks: Vec<Vec<u8>> = lines
+            .iter()
+            .map(|line| {
+                // the channels of a line one after another, in the sorted B G R order
+                let mut samples = Vec::new();
+                for channel in (0..3).rev() {
+                    for pixel in line {
+                        samples.extend_from_slice(&pixel[channel].to_le_bytes());
+                    }
+                }
+                match compression {
+                    2 => miniz_oxide::deflate::compress_to_vec_zlib(&predict(&samples), 6),
+                    _ => samples,
+                }
+            })
+            .collect();
+        let mut offset = bytes.len() + 8 * chunks.len();
+        for chunk in chunks.iter() {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += 8 + chunk.len();
+        }
+        for (y, chunk) in chunks.iter().enumerate() {
+            bytes.extend_from_slice(&(10 + y as i32).to_le_bytes());
+            bytes.extend_from_slice(&(chunk.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+
+    #[test]
+    fn halves() {
+        assert_eq!(half_to_f32(0x3c00), 1.0);
+        assert_eq!(half_to_f32(0x3800), 0.5);
+        assert_eq!(half_to_f32(0xc000), -2.0);
+        assert_eq!(half_to_f32(0x7bff), 65504.0);
+        assert_eq!(half_to_f32(0x0001), 2.0f32.powi(-24));
+        assert_eq!(half_to_f32(0x0000), 0.0);
+        assert_eq!(half_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(half_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn rle() {
+        // a run of three literal bytes, then 9 repeated three times
+        assert_eq!(
+            decode_rle(&[0xfd, 1, 2, 3, 2, 9]).unwrap(),
+            vec![1, 2, 3, 9, 9, 9]
+        );
+        assert!(decode_rle(&[0xfd, 1, 2]).is_err());
+        assert!(decode_rle(&[4]).is_err());
+    }
+
+    #[test]
+    fn unpredicts() {
+        let data: Vec<u8> = (0..11u8).map(|v| v.wrapping_mul(37)).collect();
+        let mut predicted = predict(&data);
+        unpredict(&mut predicted);
+        assert_eq!(predicted, data);
+        let mut predicted = vec![1, 129, 129, 129];
+        unpredict(&mut predicted);
+        assert_eq!(predicted, vec![1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn scanline_exr() {
+        let (one, half, two) = (0x3c00, 0x3800, 0x4000);
+        let lines = vec![
+            vec![[one, half, 0], [0, 0, two]],
+            vec![[two, two, two], [half, 0, one]],
+        ];
+        let image = read_exr(&exr_bytes(2, &lines, 0)).unwrap();
+        assert_eq!((image.size, image.channels), ((2, 2), 3));
+        assert_eq!(
+            image.data,
+            vec![1.0, 0.5, 0.0, 0.0, 0.0, 2.0, 2.0, 2.0, 2.0, 0.5, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn zip_exr() {
+        // wide enough that the lines compress
+        let line: Vec<[u16; 3]> = (0..64).map(|x| [0x3c00, x as u16, 0x4000]).collect();
+        let image = read_exr(&exr_bytes(64, &[line.clone(), line], 2)).unwrap();
+        assert_eq!(image.size, (64, 2));
+        assert_eq!(image.pixel(5, 1), &[1.0, half_to_f32(5), 2.0]);
+    }
+
+    #[test]
+    fn broken_headers() {
+        let bytes = exr_bytes(2, &[vec![[0, 0, 0]; 2]], 0);
+        // the size of the channels attribute made negative
+        let mut negative = bytes.clone();
+        let at = 8 + "channels\0chlist\0".len();
+        negative[at..at + 4].copy_from_slice(&(-8i32).to_le_bytes());
+        assert!(read_exr(&negative).is_err());
+        // a dataWindow far larger than the file
+        let mut huge = bytes.clone();
+        let window = bytes
+            .windows(6)
+            .position(|name| name == b"box2i\0")
+            .unwrap()
+            + 10;
+        huge[window + 8..window + 12].copy_from_slice(&i32::MAX.to_le_bytes());
+        huge[window + 12..window + 16].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(read_exr(&huge).is_err());
+        assert!(read_exr(&bytes[..bytes.len() - 3]).is_err());
+    }
+}
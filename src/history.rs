@@ -0,0 +1,361 @@
+/*
+ * Undo and redo for edits to the image.
+ *
+ * Each edit is recorded with its name and a deflate compressed snapshot of the
+ * image from before the edit. Undoing swaps the current image with the snapshot,
+ * saving the current image on the redo stack. The oldest snapshots are dropped
+ * once the compressed total goes over the memory cap.
+ */
+
+use crate::utility::Image;
+use eframe::egui::{self, Color32};
+use std::collections::VecDeque;
+
+struct Snapshot {
+    size: (usize, usize),
+    /// compressed premultiplied rgba bytes
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    fn new(image: &Image) -> Self {
+        let mut bytes = Vec::with_capacity(image.pixels.len() * 4);
+        for pixel in image.pixels.iter() {
+            bytes.extend_from_slice(&pixel.to_array());
+        }
+        Self {
+            size: image.size,
+            // fast compression, big images are recorded while the ui waits
+            data: miniz_oxide::deflate::compress_to_vec(&bytes, 1),
+        }
+    }
+
+    fn image(&self) -> Result<Image, String> {
+        let bytes = miniz_oxide::inflate::decompress_to_vec(&self.data)
+            .map_err(|why| format!("couldn't decompress snapshot: {:?}", why))?;
+        if bytes.len() != self.size.0 * self.size.1 * 4 {
+            return Err("snapshot is the wrong size".to_string());
+        }
+        let pixels = bytes
+            .chunks(4)
+            .map(|p| Color32::from_rgba_premultiplied(p[0], p[1], p[2], p[3]))
+            .collect();
+        Ok(Image {
+            size: self.size,
+            pixels,
+        })
+    }
+}
+
+struct Entry {
+    name: String,
+    snapshot: Snapshot,
+}
+
+impl Entry {
+    fn bytes(&self) -> usize {
+        self.snapshot.data.len()
+    }
+}
+
+pub struct History {
+    /// oldest first, each holding the image from before the named edit
+    undo: VecDeque<Entry>,
+    /// next to redo last, each holding the image from after the named edit
+    redo: VecDeque<Entry>,
+    /// bytes of all the compressed snapshots held
+    bytes: usize,
+    /// bytes of compressed snapshots to keep
+    pub max_bytes: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: VecDeque::new(),
+            bytes: 0,
+            max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+impl History {
+    /// Call before applying an edit with the image as it is before the edit
+    pub fn record(&mut self, name: &str, before: &Image) {
+        self.bytes -= self.redo.iter().map(Entry::bytes).sum::<usize>();
+        self.redo.clear();
+        self.push_undo(Entry {
+            name: name.to_string(),
+            snapshot: Snapshot::new(before),
+        });
+        self.enforce_cap();
+    }
+
+    /// Forget all the edits, e.g. when another image is loaded
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.bytes = 0;
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn push_undo(&mut self, entry: Entry) {
+        self.bytes += entry.bytes();
+        self.undo.push_back(entry);
+    }
+
+    fn push_redo(&mut self, entry: Entry) {
+        self.bytes += entry.bytes();
+        self.redo.push_back(entry);
+    }
+
+    fn enforce_cap(&mut self) {
+        // always keep the latest edit so it can be undone, and the latest undone
+        // edit so it can be redone
+        while self.undo.len() > 1 && self.bytes > self.max_bytes {
+            if let Some(entry) = self.undo.pop_front() {
+                self.bytes -= entry.bytes();
+            }
+        }
+        while self.redo.len() > 1 && self.bytes > self.max_bytes {
+            if let Some(entry) = self.redo.pop_front() {
+                self.bytes -= entry.bytes();
+            }
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Restore the image from before the last edit, returns the name of the undone edit
+    pub fn undo(&mut self, image: &mut Image) -> Result<Option<String>, String> {
+        // decoded before it is taken off, so a snapshot that can't be decoded stays
+        let restored = match self.undo.back() {
+            Some(entry) => entry.snapshot.image()?,
+            None => return Ok(None),
+        };
+        let entry = match self.undo.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.bytes -= entry.bytes();
+        self.push_redo(Entry {
+            name: entry.name.clone(),
+            snapshot: Snapshot::new(image),
+        });
+        self.enforce_cap();
+        *image = restored;
+        Ok(Some(entry.name))
+    }
+
+    /// Reapply the last undone edit, returns its name
+    pub fn redo(&mut self, image: &mut Image) -> Result<Option<String>, String> {
+        let restored = match self.redo.back() {
+            Some(entry) => entry.snapshot.image()?,
+            None => return Ok(None),
+        };
+        let entry = match self.redo.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.bytes -= entry.bytes();
+        self.push_undo(Entry {
+            name: entry.name.clone(),
+            snapshot: Snapshot::new(image),
+        });
+        self.enforce_cap();
+        *image = restored;
+        Ok(Some(entry.name))
+    }
+
+    /// Handle ctrl+z and ctrl+shift+z, unless a text field has the keyboard,
+    /// returns true if the image was changed
+    pub fn handle_keys(&mut self, ctx: &egui::CtxRef, image: &mut Image) -> bool {
+        if ctx.wants_keyboard_input() {
+            return false;
+        }
+        let input = ctx.input();
+        if !input.modifiers.command || !input.key_pressed(egui::Key::Z) {
+            return false;
+        }
+        let result = if input.modifiers.shift {
+            self.redo(image)
+        } else {
+            self.undo(image)
+        };
+        match result {
+            Ok(name) => name.is_some(),
+            Err(why) => {
+                println!("{}", why);
+                false
+            }
+        }
+    }
+
+    /// List of the edits, clicking one undoes or redoes up to it,
+    /// returns true if the image was changed
+    pub fn ui(&mut self, ui: &mut egui::Ui, image: &mut Image) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let undo = egui::Button::new("undo").enabled(self.can_undo());
+            if ui.add(undo).clicked {
+                match self.undo(image) {
+                    Ok(name) => changed |= name.is_some(),
+                    Err(why) => println!("{}", why),
+                }
+            }
+            let redo = egui::Button::new("redo").enabled(self.can_redo());
+            if ui.add(redo).clicked {
+                match self.redo(image) {
+                    Ok(name) => changed |= name.is_some(),
+                    Err(why) => println!("{}", why),
+                }
+            }
+        });
+        let mut max_mb = self.max_bytes / (1024 * 1024);
+        ui.add(egui::Slider::usize(&mut max_mb, 1..=4096).text("memory cap MB"));
+        if max_mb * 1024 * 1024 != self.max_bytes {
+            self.max_bytes = max_mb * 1024 * 1024;
+            self.enforce_cap();
+        }
+        ui.label(format!(
+            "{:.1} MB used",
+            self.bytes() as f64 / (1024.0 * 1024.0)
+        ));
+        ui.separator();
+
+        // negative steps undo and positive redo
+        let mut steps: i64 = 0;
+        let num_undo = self.undo.len() as i64;
+        if ui.selectable_label(num_undo == 0, "original").clicked {
+            steps = -num_undo;
+        }
+        for (ind, entry) in self.undo.iter().enumerate() {
+            let current = ind as i64 == num_undo - 1;
+            if ui.selectable_label(current, &entry.name).clicked {
+                steps = ind as i64 + 1 - num_undo;
+            }
+        }
+        if self.can_redo() {
+            ui.label("undone:");
+        }
+        for (ind, entry) in self.redo.iter().rev().enumerate() {
+            if ui.selectable_label(false, &entry.name).clicked {
+                steps = ind as i64 + 1;
+            }
+        }
+
+        for _ in 0..steps.abs() {
+            let result = if steps < 0 {
+                self.undo(image)
+            } else {
+                self.redo(image)
+            };
+            match result {
+                Ok(name) => changed |= name.is_some(),
+                Err(why) => {
+                    println!("{}", why);
+                    break;
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(value: u8) -> Image {
+        Image {
+            size: (4, 3),
+            pixels: vec![Color32::from_gray(value); 12],
+        }
+    }
+
+    /// Record edits that set the image to each of the values in turn
+    fn edit(history: &mut History, image: &mut Image, values: &[u8]) {
+        for value in values {
+            history.record(&format!("set {}", value), image);
+            *image = self::image(*value);
+        }
+    }
+
+    fn held_bytes(history: &History) -> usize {
+        history
+            .undo
+            .iter()
+            .chain(history.redo.iter())
+            .map(Entry::bytes)
+            .sum()
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::default();
+        let mut current = image(0);
+        edit(&mut history, &mut current, &[1, 2]);
+        assert_eq!(history.undo(&mut current), Ok(Some("set 2".to_string())));
+        assert_eq!(current.pixels, image(1).pixels);
+        assert_eq!(history.undo(&mut current), Ok(Some("set 1".to_string())));
+        assert_eq!(current.pixels, image(0).pixels);
+        assert_eq!(history.undo(&mut current), Ok(None));
+        assert_eq!(history.redo(&mut current), Ok(Some("set 1".to_string())));
+        assert_eq!(current.pixels, image(1).pixels);
+        assert_eq!(history.bytes(), held_bytes(&history));
+
+        // a new edit drops what was undone
+        edit(&mut history, &mut current, &[3]);
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(&mut current), Ok(None));
+        assert_eq!(history.undo(&mut current), Ok(Some("set 3".to_string())));
+        assert_eq!(current.pixels, image(1).pixels);
+        assert_eq!(history.bytes(), held_bytes(&history));
+    }
+
+    #[test]
+    fn cap() {
+        let mut history = History::default();
+        let mut current = image(0);
+        edit(&mut history, &mut current, &[1]);
+        let entry_bytes = history.bytes();
+        // room for two snapshots, their sizes differ by a few bytes
+        history.max_bytes = entry_bytes * 5 / 2;
+        edit(&mut history, &mut current, &[2, 3, 4]);
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(history.bytes(), held_bytes(&history));
+        assert!(history.bytes() <= history.max_bytes);
+
+        // undoing and redoing stays under it too
+        history.undo(&mut current).unwrap();
+        history.undo(&mut current).unwrap();
+        assert!(history.bytes() <= history.max_bytes);
+        history.redo(&mut current).unwrap();
+        assert!(history.bytes() <= history.max_bytes);
+        assert_eq!(history.bytes(), held_bytes(&history));
+        assert_eq!(current.pixels, image(3).pixels);
+    }
+
+    #[test]
+    fn bad_snapshot_stays() {
+        let mut history = History::default();
+        let mut current = image(0);
+        edit(&mut history, &mut current, &[1]);
+        history.undo[0].snapshot.data = vec![0xff; 4];
+        let bytes = history.bytes();
+        assert!(history.undo(&mut current).is_err());
+        assert!(history.can_undo());
+        assert_eq!(history.bytes(), bytes);
+        assert_eq!(current.pixels, image(1).pixels);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::job::{Job, Progress, ProgressReader};
+use crate::netpbm::{is_netpbm, save_netpbm};
+use crate::pixel::unmultiply;
+use crate::raw::RawImage;
+use crate::utility::Image;
+use eframe::egui;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, GenericImageView};
+use std::fs::File;
+use std::io::BufReader;
+
+/// Convert a decoded image into the pixels that get displayed
+pub fn to_image(image: &image::DynamicImage) -> Image {
+    let image_buffer = image.to_rgba8();
+    let size = (image.width() as usize, image.height() as usize);
+    let pixels = image_buffer.into_vec();
+    assert_eq!(size.0 * size.1 * 4, pixels.len());
+    let pixels = pixels
+        .chunks(4)
+        .map(|p| egui::Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]))
+        .collect();
+    Image { size, pixels }
+}
+
+/// Decode an image file, reporting the bytes read so far to the progress
+pub fn load_image(filename: &str, progress: &Progress) -> Result<Image, String> {
+    let file =
+        File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+    if let Ok(metadata) = file.metadata() {
+        progress.set_total_bytes(metadata.len());
+    }
+    let reader = BufReader::new(ProgressReader::new(file, progress));
+    let image = image::io::Reader::new(reader)
+        .with_guessed_format()
+        .map_err(|why| format!("couldn't read {}: {}", filename, why))?
+        .decode()
+        .map_err(|why| format!("couldn't decode {}: {}", filename, why))?;
+    println!("{} {:?}", filename, image.dimensions());
+    Ok(to_image(&image))
+}
+
+/// A frame of an animation and how long it is shown for in seconds
+#[derive(Clone)]
+pub struct AnimationFrame {
+    pub image: Image,
+    pub delay: f64,
+}
+
+/// Decode all the frames of an animated gif or png, a png that isn't animated
+/// gives a single frame.
+pub fn load_animation(filename: &str, progress: &Progress) -> Result<Vec<AnimationFrame>, String> {
+    let decode_error = |why: image::ImageError| format!("couldn't decode {}: {}", filename, why);
+    let file =
+        File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+    if let Ok(metadata) = file.metadata() {
+        progress.set_total_bytes(metadata.len());
+    }
+    let reader = BufReader::new(ProgressReader::new(file, progress));
+    let frames = match image::ImageFormat::from_path(filename) {
+        Ok(image::ImageFormat::Gif) => GifDecoder::new(reader).map_err(decode_error)?.into_frames(),
+        Ok(image::ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader).map_err(decode_error)?;
+            if !decoder.is_apng() {
+                let image = image::DynamicImage::from_decoder(decoder).map_err(decode_error)?;
+                return Ok(vec![AnimationFrame {
+                    image: to_image(&image),
+                    delay: 0.1,
+                }]);
+            }
+            decoder.apng().into_frames()
+        }
+        _ => return Err(format!("{} isn't a gif or png", filename)),
+    };
+    let frames = frames.collect_frames().map_err(decode_error)?;
+    println!("{} {} frames", filename, frames.len());
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay = numer as f64 / denom.max(1) as f64 / 1000.0;
+            let image = image::DynamicImage::ImageRgba8(frame.into_buffer());
+            AnimationFrame {
+                image: to_image(&image),
+                // browsers show frames without a delay for a tenth of a second
+                delay: if delay < 0.01 { 0.1 } else { delay },
+            }
+        })
+        .collect())
+}
+
+/// Write the image in the format given by the extension of the filename
+pub fn save_image(image: &Image, filename: &str) -> Result<(), String> {
+    if is_netpbm(filename) {
+        return save_netpbm(&RawImage::from_image(image), filename, false);
+    }
+    let mut bytes = Vec::with_capacity(image.pixels.len() * 4);
+    for pixel in image.pixels.iter() {
+        // undo the premultiplication done by from_rgba_unmultiplied in to_image
+        bytes.extend_from_slice(&unmultiply(*pixel));
+    }
+    image::save_buffer(
+        filename,
+        &bytes,
+        image.size.0 as u32,
+        image.size.1 as u32,
+        image::ColorType::Rgba8,
+    )
+    .map_err(|why| format!("couldn't save {}: {}", filename, why))
+}
+
+/// A second image for tools that work on two images, loaded from a file in the
+/// background or copied from the current image.
+pub struct ImagePicker {
+    filename: String,
+    pub image: Option<Image>,
+    /// Counts the changes to image, so views of it can tell when to redo their work
+    pub generation: u64,
+    loading: Option<Job<Image>>,
+    error: Option<String>,
+}
+
+impl Default for ImagePicker {
+    fn default() -> Self {
+        Self {
+            filename: "data/gradient_rect.jpg".to_string(),
+            image: None,
+            generation: 0,
+            loading: None,
+            error: None,
+        }
+    }
+}
+
+impl ImagePicker {
+    pub fn ui(&mut self, ui: &mut egui::Ui, current: &Image) {
+        if let Some(result) = self.loading.as_ref().and_then(|job| job.poll()) {
+            match result {
+                Ok(loaded) => {
+                    self.image = Some(loaded);
+                    self.generation += 1;
+                    self.error = None;
+                }
+                Err(why) => self.error = Some(why),
+            }
+            self.loading = None;
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.filename);
+            if ui.button("load").clicked {
+                let filename = self.filename.clone();
+                self.loading = Some(Job::spawn(
+                    format!("loading {}", filename),
+                    move |progress| load_image(&filename, progress),
+                ));
+            }
+            if ui.button("use current").clicked {
+                self.image = Some(current.clone());
+                self.generation += 1;
+                self.error = None;
+            }
+        });
+        if let Some(job) = &self.loading {
+            job.ui(ui);
+        }
+        match (&self.image, &self.error) {
+            (_, Some(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            (Some(image), None) => {
+                ui.label(format!("{}x{}", image.size.0, image.size.1));
+            }
+            (None, None) => {
+                ui.label("no second image");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{write_gif, GifOptions};
+    use eframe::egui::Color32;
+
+    fn frame(color: Color32, delay: f64) -> AnimationFrame {
+        AnimationFrame {
+            image: Image {
+                size: (16, 8),
+                pixels: vec![color; 128],
+            },
+            delay,
+        }
+    }
+
+    #[test]
+    fn gif_round_trip() {
+        let filename = std::env::temp_dir()
+            .join(format!("egui_image_animation_{}.gif", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let frames = vec![
+            frame(Color32::RED, 0.2),
+            frame(Color32::GREEN, 0.5),
+            frame(Color32::BLUE, 0.05),
+        ];
+        write_gif(
+            &frames,
+            &filename,
+            &GifOptions::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        let loaded = load_animation(&filename, &Progress::default());
+        std::fs::remove_file(&filename).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.len(), 3);
+        for (frame, original) in loaded.iter().zip(frames.iter()) {
+            assert_eq!(frame.image.size, (16, 8));
+            assert!(
+                (frame.delay - original.delay).abs() < 1e-9,
+                "{}",
+                frame.delay
+            );
+            // the palette may move the colors a little, it needs enough pixels to learn them
+            let (p, q) = (frame.image.pixels[0], original.image.pixels[0]);
+            let close = |a: u8, b: u8| (a as i32 - b as i32).abs() <= 8;
+            assert!(
+                close(p.r(), q.r()) && close(p.g(), q.g()) && close(p.b(), q.b()),
+                "{:?} {:?}",
+                p,
+                q
+            );
+            assert!(frame.image.pixels.iter().all(|pixel| *pixel == p));
+        }
+    }
+
+    #[test]
+    fn single_frames_and_errors() {
+        let dir = std::env::temp_dir();
+        let png = dir
+            .join(format!("egui_image_still_{}.png", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        save_image(&frame(Color32::WHITE, 0.0).image, &png).unwrap();
+        let loaded = load_animation(&png, &Progress::default());
+        std::fs::remove_file(&png).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].delay, 0.1);
+        assert_eq!(loaded[0].image.pixels, vec![Color32::WHITE; 128]);
+
+        assert!(load_animation("missing.gif", &Progress::default()).is_err());
+        assert!(load_animation("frames.bmp", &Progress::default()).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+/*
+ * Background jobs for loading images and tables without blocking the ui.
+ *
+ * The work runs on its own thread and updates a shared Progress, which the ui
+ * displays as a progress bar and can use to cancel the job. The result is polled
+ * for each frame and swapped into the app when it is done.
+ */
+
+use eframe::egui;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+
+/// Shared between a job and the ui, the job reports how far along it is and
+/// checks whether it has been cancelled.
+#[derive(Default)]
+pub struct Progress {
+    bytes_read: AtomicU64,
+    total_bytes: AtomicU64,
+    /// Rows parsed so far when loading a table
+    rows: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn set_total_bytes(&self, total_bytes: u64) {
+        self.total_bytes.store(total_bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_rows(&self, rows: u64) {
+        self.rows.store(rows, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fraction of the bytes read, None if the total isn't known
+    pub fn fraction(&self) -> Option<f32> {
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        if total_bytes == 0 {
+            return None;
+        }
+        let bytes_read = self.bytes_read.load(Ordering::Relaxed);
+        Some((bytes_read as f64 / total_bytes as f64).min(1.0) as f32)
+    }
+
+    pub fn text(&self) -> String {
+        let mb = 1024.0 * 1024.0;
+        let bytes_read = self.bytes_read.load(Ordering::Relaxed) as f64 / mb;
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed) as f64 / mb;
+        let rows = self.rows.load(Ordering::Relaxed);
+        let mut text = format!("{:.1} / {:.1} MB", bytes_read, total_bytes);
+        if rows > 0 {
+            text += &format!(", {} rows", rows);
+        }
+        text
+    }
+}
+
+/// Counts the bytes read through it and fails once the job is cancelled
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    pub fn new(inner: R, progress: &'a Progress) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.progress.is_cancelled() {
+            return Err(io::Error::other("cancelled"));
+        }
+        let len = self.inner.read(buf)?;
+        self.progress.add_bytes(len as u64);
+        Ok(len)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+pub struct Job<T> {
+    pub label: String,
+    progress: Arc<Progress>,
+    receiver: Receiver<Result<T, String>>,
+}
+
+impl<T: Send + 'static> Job<T> {
+    pub fn spawn(
+        label: impl Into<String>,
+        work: impl FnOnce(&Progress) -> Result<T, String> + Send + 'static,
+    ) -> Self {
+        let progress = Arc::new(Progress::default());
+        let (sender, receiver) = channel();
+        let job_progress = progress.clone();
+        let run = move || {
+            let result = if job_progress.is_cancelled() {
+                Err("cancelled".to_string())
+            } else {
+                work(&job_progress)
+            };
+            // the ui may have dropped the job already
+            let _ = sender.send(result);
+        };
+        // there are no threads on the web, so do the work right away
+        #[cfg(target_arch = "wasm32")]
+        run();
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(run);
+
+        Self {
+            label: label.into(),
+            progress,
+            receiver,
+        }
+    }
+
+    /// The result once the job has finished, call once per frame until it returns Some
+    pub fn poll(&self) -> Option<Result<T, String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(std::sync::mpsc::TryRecvError::Empty) => None,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                Some(Err(format!("{} failed", self.label)))
+            }
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the job was cancelled, as opposed to failing on its own
+    pub fn is_cancelled(&self) -> bool {
+        self.progress.is_cancelled()
+    }
+
+    /// Show a progress bar with a cancel button
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(&self.label);
+            progress_bar(ui, self.progress.fraction(), &self.progress.text());
+            if ui.button("cancel").clicked {
+                self.cancel();
+            }
+        });
+        // keep polling while the job runs even if there is no input
+        ui.ctx().request_repaint();
+    }
+}
+
+fn progress_bar(ui: &mut egui::Ui, fraction: Option<f32>, text: &str) {
+    let size = egui::Vec2::new(200.0, ui.style().spacing.interact_size.y);
+    let (rect, _response) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let visuals = &ui.style().visuals;
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, visuals.dark_bg_color);
+    if let Some(fraction) = fraction {
+        let mut fill = rect;
+        fill.max.x = rect.min.x + rect.width() * fraction;
+        painter.rect_filled(fill, 2.0, visuals.selection.bg_fill);
+    }
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        text,
+        egui::TextStyle::Body,
+        visuals.text_color(),
+    );
+}
@@ -2,9 +2,48 @@
 #![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
 #![warn(clippy::all, rust_2018_idioms)]
 
+mod annotate;
 mod app;
+mod blend;
+mod browser;
+mod compare;
+mod draw;
+mod edge;
+mod filter;
+mod font;
+mod geometry;
+mod hdr;
+mod history;
+mod image_io;
+pub mod job;
+mod morphology;
+mod netpbm;
+mod npy;
+mod painting;
+mod pixel;
+mod raw;
+mod raw_import;
+mod record;
+mod roi;
+mod sequence;
+mod shift;
 mod utility;
+pub use annotate::{Annotation, AnnotationShape, Annotations};
 pub use app::ImageApp;
+pub use blend::Combine;
+pub use draw::{blend_over, text_size};
+pub use edge::EdgeMode;
+pub use filter::Kernel;
+pub use hdr::{read_exr, read_hdr};
+pub use image_io::AnimationFrame;
+pub use morphology::{MorphOp, Shape, StructuringElement};
+pub use netpbm::{read_netpbm, save_netpbm, write_pfm, write_pnm};
+pub use npy::{load_npy, read_npy, read_npz, NpyArray};
+pub use raw::{export, load_native, Colormap, Depth, Levels, Loaded, RawImage, ToneMap};
+pub use raw_import::{decode_raw, import_raw, BayerPattern, PixelFormat, RawLayout};
+pub use record::{write_gif, GifOptions, GifPalette, Recorder};
+pub use roi::{measure, Roi, RoiStats};
+pub use utility::{Image, KeyedTexMngr, TexMngr};
 
 // ----------------------------------------------------------------------------
 // When compiling for web:
@@ -2,11 +2,19 @@
 #![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
 #![warn(clippy::all, rust_2018_idioms)]
 
-mod utility;
-
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
+    // egui_image --export input output [--ascii] converts without opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() >= 4 && args[1] == "--export" {
+        let ascii = args.iter().skip(4).any(|arg| arg == "--ascii");
+        if let Err(why) = egui_image::export(&args[2], &args[3], ascii) {
+            eprintln!("{}", why);
+            std::process::exit(1);
+        }
+        return;
+    }
     let app = egui_image::ImageApp::default();
     eframe::run_native(Box::new(app));
 }
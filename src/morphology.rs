@@ -0,0 +1,482 @@
+/*
+ * Morphology and thresholding for segmentation masks.
+ *
+ * Erosion and dilation take the per channel min and max over a structuring element,
+ * which works the same on grayscale images and on the black and white masks made
+ * by the thresholds. Masks are opaque with 255 inside and 0 outside.
+ */
+
+use crate::blend::check_same_size;
+use crate::edge::EdgeMode;
+use crate::utility::Image;
+use eframe::egui::{self, Color32};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Square,
+    Cross,
+    Disk,
+}
+
+/// Neighborhood of a pixel used by the morphology operations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StructuringElement {
+    pub shape: Shape,
+    pub radius: usize,
+}
+
+impl StructuringElement {
+    /// Offsets from the center pixel that are part of the element
+    pub fn offsets(&self) -> Vec<(i64, i64)> {
+        let r = self.radius as i64;
+        let mut offsets = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let inside = match self.shape {
+                    Shape::Square => true,
+                    Shape::Cross => dx == 0 || dy == 0,
+                    Shape::Disk => dx * dx + dy * dy <= r * r + r,
+                };
+                if inside {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        offsets
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MorphOp {
+    Erode,
+    Dilate,
+    Open,
+    Close,
+}
+
+/// Luma of the premultiplied color, so transparent pixels count as dark
+pub fn luma(color: Color32) -> f32 {
+    0.299 * color.r() as f32 + 0.587 * color.g() as f32 + 0.114 * color.b() as f32
+}
+
+fn mask_value(inside: bool) -> Color32 {
+    if inside {
+        Color32::WHITE
+    } else {
+        Color32::BLACK
+    }
+}
+
+impl Image {
+    fn min_max_filter(&self, element: &StructuringElement, edge: EdgeMode, max: bool) -> Image {
+        let offsets = element.offsets();
+        let (width, height) = self.size;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let mut acc = if max { [0u8; 4] } else { [255u8; 4] };
+                for (dx, dy) in offsets.iter() {
+                    let color = match (edge.index(x + dx, width), edge.index(y + dy, height)) {
+                        (Some(xi), Some(yi)) => self.pixels[yi * width + xi],
+                        _ => match edge {
+                            EdgeMode::Fill(color) => color,
+                            _ => continue,
+                        },
+                    };
+                    for (a, c) in acc.iter_mut().zip(color.to_array().iter()) {
+                        *a = if max { (*a).max(*c) } else { (*a).min(*c) };
+                    }
+                }
+                pixels.push(Color32::from_rgba_premultiplied(
+                    acc[0], acc[1], acc[2], acc[3],
+                ));
+            }
+        }
+        Image {
+            size: self.size,
+            pixels,
+        }
+    }
+
+    pub fn erode(&self, element: &StructuringElement, edge: EdgeMode) -> Image {
+        self.min_max_filter(element, edge, false)
+    }
+
+    pub fn dilate(&self, element: &StructuringElement, edge: EdgeMode) -> Image {
+        self.min_max_filter(element, edge, true)
+    }
+
+    /// Erode then dilate, removes specks smaller than the element
+    pub fn open(&self, element: &StructuringElement, edge: EdgeMode) -> Image {
+        self.erode(element, edge).dilate(element, edge)
+    }
+
+    /// Dilate then erode, fills holes smaller than the element
+    pub fn close(&self, element: &StructuringElement, edge: EdgeMode) -> Image {
+        self.dilate(element, edge).erode(element, edge)
+    }
+
+    pub fn morph(&self, op: MorphOp, element: &StructuringElement, edge: EdgeMode) -> Image {
+        match op {
+            MorphOp::Erode => self.erode(element, edge),
+            MorphOp::Dilate => self.dilate(element, edge),
+            MorphOp::Open => self.open(element, edge),
+            MorphOp::Close => self.close(element, edge),
+        }
+    }
+
+    /// Mask of the pixels with luma above the level
+    pub fn threshold(&self, level: f32) -> Image {
+        Image {
+            size: self.size,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|p| mask_value(luma(*p) > level))
+                .collect(),
+        }
+    }
+
+    /// The level that best separates the luma histogram into two classes
+    pub fn otsu_level(&self) -> f32 {
+        let mut histogram = [0usize; 256];
+        for p in self.pixels.iter() {
+            histogram[luma(*p).round().clamp(0.0, 255.0) as usize] += 1;
+        }
+        let total = self.pixels.len() as f64;
+        let sum: f64 = histogram
+            .iter()
+            .enumerate()
+            .map(|(i, count)| i as f64 * *count as f64)
+            .sum();
+
+        let mut best = (0.0, 0);
+        let mut below_count = 0.0;
+        let mut below_sum = 0.0;
+        for (i, count) in histogram.iter().enumerate() {
+            below_count += *count as f64;
+            below_sum += i as f64 * *count as f64;
+            let above_count = total - below_count;
+            if below_count == 0.0 || above_count == 0.0 {
+                continue;
+            }
+            let mean_below = below_sum / below_count;
+            let mean_above = (sum - below_sum) / above_count;
+            // between class variance
+            let variance = below_count * above_count * (mean_below - mean_above).powi(2);
+            if variance > best.0 {
+                best = (variance, i);
+            }
+        }
+        best.1 as f32
+    }
+
+    /// Mask of the pixels brighter than the mean of the (2 * radius + 1) square around
+    /// them less the offset, for images with uneven lighting.
+    pub fn adaptive_threshold(&self, radius: usize, offset: f32) -> Image {
+        let gray = Image {
+            size: self.size,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|p| {
+                    let l = luma(*p).round() as u8;
+                    Color32::from_rgb(l, l, l)
+                })
+                .collect(),
+        };
+        let mean = gray.box_blur(radius, EdgeMode::Clamp);
+        Image {
+            size: self.size,
+            pixels: self
+                .pixels
+                .iter()
+                .zip(mean.pixels.iter())
+                .map(|(p, m)| mask_value(luma(*p) > m.r() as f32 - offset))
+                .collect(),
+        }
+    }
+
+    /// Blend the color over the pixels in proportion to the mask brightness,
+    /// the alpha of the color sets the opacity of the overlay.
+    pub fn overlay_mask(&self, mask: &Image, color: Color32) -> Result<Image, String> {
+        check_same_size(self, mask)?;
+        let color = color.to_array();
+        let pixels = self
+            .pixels
+            .iter()
+            .zip(mask.pixels.iter())
+            .map(|(p, m)| {
+                let fr = m.r() as u32 * color[3] as u32 / 255;
+                let p = p.to_array();
+                let blend = |c: usize| {
+                    let over = color[c] as u32 * m.r() as u32 / 255;
+                    (over + p[c] as u32 * (255 - fr) / 255).min(255) as u8
+                };
+                Color32::from_rgba_premultiplied(blend(0), blend(1), blend(2), blend(3))
+            })
+            .collect();
+        Ok(Image {
+            size: self.size,
+            pixels,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ThresholdKind {
+    Global,
+    Otsu,
+    Adaptive,
+}
+
+/// Panel for making a threshold mask, cleaning it up with morphology and
+/// overlaying it on the image.
+pub struct MorphologyTool {
+    threshold: ThresholdKind,
+    level: f32,
+    adaptive_radius: usize,
+    adaptive_offset: f32,
+    op: MorphOp,
+    element: StructuringElement,
+    pub mask: Option<Image>,
+    pub show_mask: bool,
+    overlay_color: Color32,
+}
+
+impl Default for MorphologyTool {
+    fn default() -> Self {
+        Self {
+            threshold: ThresholdKind::Otsu,
+            level: 128.0,
+            adaptive_radius: 7,
+            adaptive_offset: 5.0,
+            op: MorphOp::Open,
+            element: StructuringElement {
+                shape: Shape::Disk,
+                radius: 1,
+            },
+            mask: None,
+            show_mask: true,
+            // red at half opacity, premultiplied
+            overlay_color: Color32::from_rgba_premultiplied(128, 0, 0, 128),
+        }
+    }
+}
+
+impl MorphologyTool {
+    /// Whether a mask is shown over the image, only one of its size is drawn
+    pub fn is_overlaid(&self, image: &Image) -> bool {
+        self.show_mask
+            && self
+                .mask
+                .as_ref()
+                .is_some_and(|mask| mask.size == image.size)
+    }
+
+    /// Drop the mask, e.g. when another image is loaded
+    pub fn reset(&mut self) {
+        self.mask = None;
+    }
+
+    /// The image with the mask overlaid, if there is a mask of the same size to show
+    pub fn overlay(&self, image: &Image) -> Option<Image> {
+        if !self.show_mask {
+            return None;
+        }
+        self.mask
+            .as_ref()
+            .and_then(|mask| image.overlay_mask(mask, self.overlay_color).ok())
+    }
+
+    /// Returns the name of the operation and the new image when one replaces the image
+    pub fn ui(&mut self, ui: &mut egui::Ui, image: &Image) -> Option<(String, Image)> {
+        let mut edit = None;
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.threshold, ThresholdKind::Global, "global");
+            ui.radio_value(&mut self.threshold, ThresholdKind::Otsu, "otsu");
+            ui.radio_value(&mut self.threshold, ThresholdKind::Adaptive, "adaptive");
+        });
+        match self.threshold {
+            ThresholdKind::Global => {
+                ui.add(egui::Slider::f32(&mut self.level, 0.0..=255.0).text("level"));
+            }
+            ThresholdKind::Otsu => {
+                ui.label(format!("level {}", self.level));
+            }
+            ThresholdKind::Adaptive => {
+                ui.add(egui::Slider::usize(&mut self.adaptive_radius, 1..=50).text("radius"));
+                ui.add(egui::Slider::f32(&mut self.adaptive_offset, -50.0..=50.0).text("offset"));
+            }
+        }
+        if ui.button("make mask").clicked {
+            self.mask = Some(match self.threshold {
+                ThresholdKind::Global => image.threshold(self.level),
+                ThresholdKind::Otsu => {
+                    self.level = image.otsu_level();
+                    image.threshold(self.level)
+                }
+                ThresholdKind::Adaptive => {
+                    image.adaptive_threshold(self.adaptive_radius, self.adaptive_offset)
+                }
+            });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            for op in [
+                MorphOp::Erode,
+                MorphOp::Dilate,
+                MorphOp::Open,
+                MorphOp::Close,
+            ]
+            .iter()
+            {
+                ui.radio_value(&mut self.op, *op, format!("{:?}", op).to_lowercase());
+            }
+        });
+        ui.horizontal(|ui| {
+            for shape in [Shape::Square, Shape::Cross, Shape::Disk].iter() {
+                let text = format!("{:?}", shape).to_lowercase();
+                ui.radio_value(&mut self.element.shape, *shape, text);
+            }
+        });
+        ui.add(egui::Slider::usize(&mut self.element.radius, 1..=10).text("element radius"));
+        let name = format!(
+            "{:?} {:?} {}",
+            self.op, self.element.shape, self.element.radius
+        )
+        .to_lowercase();
+        ui.horizontal(|ui| {
+            if self.mask.is_some() && ui.button("apply to mask").clicked {
+                self.mask = self
+                    .mask
+                    .as_ref()
+                    .map(|mask| mask.morph(self.op, &self.element, EdgeMode::Clamp));
+            }
+            if ui.button("apply to image").clicked {
+                edit = Some((name, image.morph(self.op, &self.element, EdgeMode::Clamp)));
+            }
+        });
+
+        ui.separator();
+        let mut clear = false;
+        if let Some(mask) = &self.mask {
+            if mask.size != image.size {
+                ui.label("the mask is a different size than the image");
+            }
+            let show_mask = &mut self.show_mask;
+            let overlay_color = &mut self.overlay_color;
+            ui.horizontal(|ui| {
+                ui.checkbox(show_mask, "overlay");
+                ui.color_edit_button_srgba(overlay_color);
+                if ui.button("mask to image").clicked {
+                    edit = Some(("mask".to_string(), mask.clone()));
+                }
+                clear = ui.button("clear").clicked;
+            });
+        }
+        if clear {
+            self.mask = None;
+        }
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mask from rows of '#' for inside and '.' for outside
+    fn mask(rows: &[&str]) -> Image {
+        Image {
+            size: (rows[0].len(), rows.len()),
+            pixels: rows
+                .iter()
+                .flat_map(|row| row.chars().map(|ch| mask_value(ch == '#')))
+                .collect(),
+        }
+    }
+
+    fn square(radius: usize) -> StructuringElement {
+        StructuringElement {
+            shape: Shape::Square,
+            radius,
+        }
+    }
+
+    #[test]
+    fn elements() {
+        assert_eq!(square(1).offsets().len(), 9);
+        let cross = StructuringElement {
+            shape: Shape::Cross,
+            radius: 2,
+        };
+        assert_eq!(cross.offsets().len(), 9);
+        let disk = StructuringElement {
+            shape: Shape::Disk,
+            radius: 2,
+        };
+        assert!(!disk.offsets().contains(&(2, 2)));
+        assert!(disk.offsets().contains(&(2, 1)));
+    }
+
+    #[test]
+    fn erode_and_dilate() {
+        let dot = mask(&[".....", ".....", "..#..", ".....", "....."]);
+        let block = mask(&[".....", ".###.", ".###.", ".###.", "....."]);
+        assert_eq!(dot.dilate(&square(1), EdgeMode::Clamp).pixels, block.pixels);
+        assert_eq!(block.erode(&square(1), EdgeMode::Clamp).pixels, dot.pixels);
+        // opening removes the speck, closing fills the hole
+        let speck = mask(&["#....", ".....", ".....", ".....", "....."]);
+        let blank = mask(&[".....", ".....", ".....", ".....", "....."]);
+        assert_eq!(speck.open(&square(1), EdgeMode::Clamp).pixels, blank.pixels);
+        let holed = mask(&["#####", "#####", "##.##", "#####", "#####"]);
+        let full = mask(&["#####", "#####", "#####", "#####", "#####"]);
+        assert_eq!(holed.close(&square(1), EdgeMode::Clamp).pixels, full.pixels);
+    }
+
+    #[test]
+    fn edges() {
+        let full = mask(&["###", "###", "###"]);
+        // clamped edges are inside, filled with black they erode
+        assert_eq!(full.erode(&square(1), EdgeMode::Clamp).pixels, full.pixels);
+        let eroded = full.erode(&square(1), EdgeMode::Fill(Color32::BLACK));
+        assert_eq!(eroded.pixels, mask(&["...", ".#.", "..."]).pixels);
+    }
+
+    #[test]
+    fn otsu() {
+        let pixels = (0..64)
+            .map(|ind| Color32::from_gray(if ind % 4 == 0 { 200 } else { 50 }))
+            .collect();
+        let image = Image {
+            size: (8, 8),
+            pixels,
+        };
+        let level = image.otsu_level();
+        assert!((50.0..200.0).contains(&level), "{}", level);
+        let inside = image.threshold(level);
+        assert_eq!(
+            inside
+                .pixels
+                .iter()
+                .filter(|p| **p == Color32::WHITE)
+                .count(),
+            16
+        );
+    }
+
+    #[test]
+    fn overlaid_only_at_the_image_size() {
+        let image = mask(&["...", "..."]);
+        let mut tool = MorphologyTool::default();
+        assert!(!tool.is_overlaid(&image));
+        tool.mask = Some(image.threshold(128.0));
+        assert!(tool.is_overlaid(&image));
+        assert!(!tool.is_overlaid(&mask(&["..", ".."])));
+        tool.reset();
+        assert!(!tool.is_overlaid(&image));
+    }
+}
@@ -0,0 +1,386 @@
+/*
+ * Reading and writing the netpbm formats: pgm and ppm in ascii (P2, P3) and
+ * binary (P5, P6) with a maxval of up to 65535, and pfm floats.
+ *
+ * Samples above 8 bits are kept at their values in a 16 bit RawImage rather
+ * than being scaled, so a 12 bit pgm shows its 12 bit values in the inspector.
+ * Their maxval is kept with the image and written back. Other 16 bit images are
+ * written with the smallest maxval of all ones bits that holds their values.
+ * Binary samples over 8 bits are big endian as the format requires. Alpha is
+ * dropped since netpbm has none.
+ *
+ * The header's size isn't trusted, the pixels are read up to the size it gives
+ * and a file that is cut short is an error rather than a large allocation.
+ */
+
+use crate::raw::{Depth, RawImage};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Next whitespace separated token, skipping comments from # to the end of the line
+fn read_token(bytes: &mut impl Iterator<Item = std::io::Result<u8>>) -> Result<String, String> {
+    let mut token = String::new();
+    let mut comment = false;
+    for byte in bytes {
+        let byte = byte.map_err(|why| why.to_string())?;
+        if comment {
+            comment = byte != b'\n' && byte != b'\r';
+            continue;
+        }
+        if byte == b'#' && token.is_empty() {
+            comment = true;
+        } else if byte.is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            }
+            // the whitespace ending the token is consumed, which matters before binary data
+            return Ok(token);
+        } else {
+            token.push(byte as char);
+        }
+    }
+    if token.is_empty() {
+        Err("unexpected end of the file".to_string())
+    } else {
+        Ok(token)
+    }
+}
+
+fn parse<T: std::str::FromStr>(token: String) -> Result<T, String> {
+    token
+        .parse::<T>()
+        .map_err(|_| format!("{} isn't a number", token))
+}
+
+/// The len bytes of pixel data, None if the header's size overflows
+fn read_pixels(reader: impl Read, len: Option<usize>) -> Result<Vec<u8>, String> {
+    let len = len.ok_or("the image size is too large")?;
+    let mut bytes = Vec::new();
+    reader
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|why| format!("couldn't read the pixels: {}", why))?;
+    if bytes.len() < len {
+        return Err(format!(
+            "{} bytes of pixels are needed but the file has {}",
+            len,
+            bytes.len()
+        ));
+    }
+    Ok(bytes)
+}
+
+fn read_pfm(
+    width: usize,
+    height: usize,
+    channels: usize,
+    mut reader: impl BufRead,
+) -> Result<RawImage, String> {
+    let little_endian = parse::<f32>(read_token(&mut (&mut reader).bytes())?)? < 0.0;
+    let len = width
+        .checked_mul(height)
+        .and_then(|len| len.checked_mul(channels * 4));
+    let bytes = read_pixels(reader, len)?;
+    let values: Vec<f32> = bytes
+        .chunks(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect();
+    // rows go from the bottom up
+    let mut data = Vec::with_capacity(values.len());
+    for row in values.chunks((width * channels).max(1)).rev() {
+        data.extend_from_slice(row);
+    }
+    Ok(RawImage {
+        size: (width, height),
+        channels,
+        depth: Depth::F32,
+        data,
+        maxval: None,
+    })
+}
+
+/// Decode a pgm, ppm or pfm. Maxvals below 255 are scaled up to 8 bits, larger
+/// ones keep their values at 16 bits.
+pub fn read_netpbm(mut reader: impl BufRead) -> Result<RawImage, String> {
+    let mut header = (&mut reader).bytes();
+    let magic = read_token(&mut header)?;
+    let (channels, ascii) = match magic.as_str() {
+        "P2" => (1, true),
+        "P3" => (3, true),
+        "P5" => (1, false),
+        "P6" => (3, false),
+        "Pf" => (1, false),
+        "PF" => (3, false),
+        "P1" | "P4" => return Err("bitmaps aren't supported".to_string()),
+        _ => return Err(format!("{} isn't a netpbm header", magic)),
+    };
+    let width = parse::<usize>(read_token(&mut header)?)?;
+    let height = parse::<usize>(read_token(&mut header)?)?;
+    if magic == "Pf" || magic == "PF" {
+        return read_pfm(width, height, channels, reader);
+    }
+    let maxval = parse::<u32>(read_token(&mut header)?)?;
+    if maxval == 0 || maxval > 65535 {
+        return Err(format!("maxval {} is out of range", maxval));
+    }
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(channels))
+        .ok_or("the image size is too large")?;
+    let values: Vec<u32> = if ascii {
+        (0..count)
+            .map(|_| parse::<u32>(read_token(&mut header)?))
+            .collect::<Result<_, _>>()?
+    } else {
+        let sample_bytes = if maxval > 255 { 2 } else { 1 };
+        let bytes = read_pixels(reader, count.checked_mul(sample_bytes))?;
+        if sample_bytes == 2 {
+            bytes
+                .chunks(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                .collect()
+        } else {
+            bytes.into_iter().map(|b| b as u32).collect()
+        }
+    };
+    let (depth, scale) = if maxval > 255 {
+        (Depth::U16, 1.0)
+    } else {
+        (Depth::U8, 255.0 / maxval as f32)
+    };
+    Ok(RawImage {
+        size: (width, height),
+        channels,
+        depth,
+        data: values
+            .into_iter()
+            .map(|v| (v.min(maxval) as f32 * scale).round())
+            .collect(),
+        maxval: if maxval > 255 { Some(maxval) } else { None },
+    })
+}
+
+/// Whether the extension is one netpbm is read and written for
+pub fn is_netpbm(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ["pgm", "ppm", "pnm", "pfm"].contains(&ext.as_str()))
+}
+
+/// Gray or rgb samples of each pixel with alpha dropped, gray from rgb is the
+/// BT.601 luma.
+fn samples(raw: &RawImage, rgb: bool) -> Vec<f32> {
+    let mut samples = Vec::with_capacity(raw.size.0 * raw.size.1 * if rgb { 3 } else { 1 });
+    for pixel in raw.data.chunks(raw.channels) {
+        match (raw.channels >= 3, rgb) {
+            (true, true) => samples.extend_from_slice(&pixel[..3]),
+            (true, false) => samples.push(0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2]),
+            (false, true) => samples.extend_from_slice(&[pixel[0]; 3]),
+            (false, false) => samples.push(pixel[0]),
+        }
+    }
+    samples
+}
+
+/// Write a pgm or ppm, 8 bit images with a maxval of 255 and 16 bit ones with
+/// the maxval they were read with or else the smallest that holds their values,
+/// floats from 0.0 to 1.0 are scaled to 16 bits.
+pub fn write_pnm(
+    mut writer: impl Write,
+    raw: &RawImage,
+    rgb: bool,
+    ascii: bool,
+) -> std::io::Result<()> {
+    let magic = match (rgb, ascii) {
+        (false, true) => "P2",
+        (true, true) => "P3",
+        (false, false) => "P5",
+        (true, false) => "P6",
+    };
+    let (maxval, scale) = match raw.depth {
+        Depth::U8 => (255, 1.0),
+        Depth::U16 => match raw.maxval {
+            Some(maxval) => (maxval.clamp(1, 65535), 1.0),
+            None => {
+                let max = raw.range().map_or(0.0, |(_, hi)| hi).clamp(0.0, 65535.0) as u32;
+                ((max + 1).next_power_of_two().clamp(512, 65536) - 1, 1.0)
+            }
+        },
+        Depth::F32 => (65535, 65535.0),
+    };
+    write!(
+        writer,
+        "{}\n{} {}\n{}\n",
+        magic, raw.size.0, raw.size.1, maxval
+    )?;
+    let values = samples(raw, rgb)
+        .into_iter()
+        .map(|v| (v * scale).round().clamp(0.0, maxval as f32) as u16);
+    if ascii {
+        // rows on their own lines, which keeps them short enough for most readers
+        let row_len = raw.size.0 * if rgb { 3 } else { 1 };
+        for (ind, v) in values.enumerate() {
+            let end = if (ind + 1) % row_len.max(1) == 0 {
+                "\n"
+            } else {
+                " "
+            };
+            write!(writer, "{}{}", v, end)?;
+        }
+    } else if maxval > 255 {
+        for v in values {
+            writer.write_all(&v.to_be_bytes())?;
+        }
+    } else {
+        let bytes: Vec<u8> = values.map(|v| v as u8).collect();
+        writer.write_all(&bytes)?;
+    }
+    writer.flush()
+}
+
+/// Write a little endian pfm, integer depths are scaled to 0.0 to 1.0
+pub fn write_pfm(mut writer: impl Write, raw: &RawImage) -> std::io::Result<()> {
+    let rgb = raw.channels >= 3;
+    write!(
+        writer,
+        "{}\n{} {}\n-1.0\n",
+        if rgb { "PF" } else { "Pf" },
+        raw.size.0,
+        raw.size.1
+    )?;
+    let scale = 1.0 / raw.depth.max_value();
+    let values = samples(raw, rgb);
+    for row in values
+        .chunks((raw.size.0 * if rgb { 3 } else { 1 }).max(1))
+        .rev()
+    {
+        for v in row {
+            writer.write_all(&(v * scale).to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+/// Write the native values as a pgm, ppm, pnm (gray or rgb going by the image) or pfm
+pub fn save_netpbm(raw: &RawImage, filename: &str, ascii: bool) -> Result<(), String> {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let file =
+        File::create(filename).map_err(|why| format!("couldn't create {}: {}", filename, why))?;
+    let writer = BufWriter::new(file);
+    let result = match extension.as_str() {
+        "pgm" => write_pnm(writer, raw, false, ascii),
+        "ppm" => write_pnm(writer, raw, true, ascii),
+        "pnm" => write_pnm(writer, raw, raw.channels >= 3, ascii),
+        "pfm" => write_pfm(writer, raw),
+        _ => return Err(format!("{} isn't a netpbm file", filename)),
+    };
+    result.map_err(|why| format!("couldn't write {}: {}", filename, why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(size: (usize, usize), channels: usize, depth: Depth, data: Vec<f32>) -> RawImage {
+        RawImage {
+            size,
+            channels,
+            depth,
+            data,
+            maxval: None,
+        }
+    }
+
+    fn round_trip(raw: &RawImage, rgb: bool, ascii: bool) -> (String, RawImage) {
+        let mut bytes = Vec::new();
+        write_pnm(&mut bytes, raw, rgb, ascii).unwrap();
+        let magic = String::from_utf8_lossy(&bytes[..2]).into_owned();
+        (magic, read_netpbm(&bytes[..]).unwrap())
+    }
+
+    #[test]
+    fn gray_8_bit() {
+        let image = raw(
+            (3, 2),
+            1,
+            Depth::U8,
+            vec![0.0, 1.0, 2.0, 100.0, 200.0, 255.0],
+        );
+        for (ascii, expected) in [(true, "P2"), (false, "P5")].iter() {
+            let (magic, read) = round_trip(&image, false, *ascii);
+            assert_eq!(magic, *expected);
+            assert_eq!(
+                (read.size, read.channels, read.depth),
+                ((3, 2), 1, Depth::U8)
+            );
+            assert_eq!(read.data, image.data);
+        }
+    }
+
+    #[test]
+    fn rgb_16_bit() {
+        let data = vec![0.0, 1.0, 2.0, 3.0, 4095.0, 1000.0];
+        let image = raw((2, 1), 3, Depth::U16, data.clone());
+        for (ascii, expected) in [(true, "P3"), (false, "P6")].iter() {
+            let (magic, read) = round_trip(&image, true, *ascii);
+            assert_eq!(magic, *expected);
+            assert_eq!(
+                (read.size, read.channels, read.depth),
+                ((2, 1), 3, Depth::U16)
+            );
+            assert_eq!(read.data, data);
+            assert_eq!(read.maxval, Some(4095));
+        }
+    }
+
+    #[test]
+    fn keeps_maxval() {
+        let pgm = b"P5\n2 1\n1000\n\x00\x07\x03\xe8";
+        let image = read_netpbm(&pgm[..]).unwrap();
+        assert_eq!(image.data, vec![7.0, 1000.0]);
+        assert_eq!(image.maxval, Some(1000));
+        let mut bytes = Vec::new();
+        write_pnm(&mut bytes, &image, false, false).unwrap();
+        assert_eq!(bytes, pgm.to_vec());
+    }
+
+    #[test]
+    fn pfm() {
+        let data = vec![0.0, 0.25, 0.5, 1.0, 2.0, -1.0];
+        for (channels, size) in [(1, (3, 2)), (3, (2, 1))].iter() {
+            let image = raw(*size, *channels, Depth::F32, data.clone());
+            let mut bytes = Vec::new();
+            write_pfm(&mut bytes, &image).unwrap();
+            let read = read_netpbm(&bytes[..]).unwrap();
+            assert_eq!(
+                (read.size, read.channels, read.depth),
+                (*size, *channels, Depth::F32)
+            );
+            assert_eq!(read.data, data);
+        }
+    }
+
+    #[test]
+    fn bad_sizes() {
+        // cut short, and sizes that overflow
+        assert!(read_netpbm(&b"P5\n2 2\n255\n\x00\x01\x02"[..]).is_err());
+        assert!(read_netpbm(&b"P2\n2 2\n255\n0 1 2"[..]).is_err());
+        let huge = format!("P6\n{} {}\n65535\n", usize::MAX / 2, 3);
+        assert!(read_netpbm(huge.as_bytes()).is_err());
+        let huge = format!("PF\n{} 1\n-1.0\n", usize::MAX / 8);
+        assert!(read_netpbm(huge.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,542 @@
+/*
+ * Reading the arrays numpy saves with np.save and np.savez.
+ *
+ * An npy file is a magic string and version, a python dict literal giving the
+ * dtype, memory order and shape, then the raw element data. An npz file is a
+ * zip archive of npy files, stored or deflated, which is read through its
+ * central directory.
+ */
+
+use crate::job::{Progress, ProgressReader};
+use crate::raw::{Depth, RawImage};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// An n dimensional array in row major order, the elements converted to f64
+#[derive(Clone, Debug)]
+pub struct NpyArray {
+    pub shape: Vec<usize>,
+    /// numpy type character, b for bool, u unsigned, i signed, f float
+    pub kind: char,
+    /// bytes per element
+    pub item_size: usize,
+    pub data: Vec<f64>,
+}
+
+/// Text following 'key': in the header dict
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    Some(header[start..].trim_start())
+}
+
+fn parse_header(header: &str) -> Result<(String, bool, Vec<usize>), String> {
+    let descr = header_value(header, "descr")
+        .and_then(|value| value.strip_prefix('\''))
+        .and_then(|value| value.split('\'').next())
+        .ok_or("no descr in the header")?;
+    let fortran_order = header_value(header, "fortran_order")
+        .ok_or("no fortran_order in the header")?
+        .starts_with("True");
+    let shape = header_value(header, "shape")
+        .and_then(|value| value.strip_prefix('('))
+        .and_then(|value| value.split(')').next())
+        .ok_or("no shape in the header")?;
+    let shape = shape
+        .split(',')
+        .map(|dim| dim.trim())
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse::<usize>()
+                .map_err(|_| format!("bad shape {}", dim))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((descr.to_string(), fortran_order, shape))
+}
+
+fn decode_element(bytes: &[u8], kind: char, little_endian: bool) -> f64 {
+    // ints of any size go through u64 or i64, sign extended from their top byte
+    let mut ordered = [0u8; 8];
+    let size = bytes.len();
+    if little_endian {
+        ordered[..size].copy_from_slice(bytes);
+    } else {
+        for (ind, byte) in bytes.iter().rev().enumerate() {
+            ordered[ind] = *byte;
+        }
+    }
+    match (kind, size) {
+        ('f', 4) => f32::from_le_bytes([ordered[0], ordered[1], ordered[2], ordered[3]]) as f64,
+        ('f', _) => f64::from_le_bytes(ordered),
+        ('i', _) => {
+            let negative = ordered[size - 1] & 0x80 != 0;
+            if negative {
+                for byte in ordered[size..].iter_mut() {
+                    *byte = 0xff;
+                }
+            }
+            i64::from_le_bytes(ordered) as f64
+        }
+        _ => u64::from_le_bytes(ordered) as f64,
+    }
+}
+
+/// Parse the bytes of an npy file
+pub fn read_npy(bytes: &[u8]) -> Result<NpyArray, String> {
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err("not an npy file".to_string());
+    }
+    let (header_len, header_start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10usize),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            12,
+        ),
+        version => return Err(format!("npy version {} isn't supported", version)),
+    };
+    let data_start = header_start
+        .checked_add(header_len)
+        .ok_or("the header is cut short")?;
+    let header = bytes
+        .get(header_start..data_start)
+        .ok_or("the header is cut short")?;
+    let (descr, fortran_order, shape) = parse_header(&String::from_utf8_lossy(header))?;
+
+    let mut chars = descr.chars();
+    let order = chars.next().ok_or("empty descr")?;
+    let kind = chars.next().ok_or("empty descr")?;
+    let item_size = chars
+        .as_str()
+        .parse::<usize>()
+        .map_err(|_| format!("dtype {} isn't supported", descr))?;
+    let supported = match kind {
+        'b' | 'u' | 'i' => [1, 2, 4, 8].contains(&item_size),
+        'f' => item_size == 4 || item_size == 8,
+        _ => false,
+    };
+    if !supported {
+        return Err(format!("dtype {} isn't supported", descr));
+    }
+    let little_endian = order != '>';
+
+    let count = shape
+        .iter()
+        .try_fold(1usize, |count, dim| count.checked_mul(*dim))
+        .ok_or(format!("a {:?} array is too large", shape))?;
+    let data = count
+        .checked_mul(item_size)
+        .and_then(|len| data_start.checked_add(len))
+        .and_then(|data_end| bytes.get(data_start..data_end))
+        .ok_or(format!(
+            "{} elements of {} bytes are needed but the data is cut short",
+            count, item_size
+        ))?;
+    let values: Vec<f64> = data
+        .chunks(item_size)
+        .map(|element| decode_element(element, kind, little_endian))
+        .collect();
+    let data = if fortran_order && shape.len() > 1 {
+        // column major, the first index varies fastest
+        let mut data = Vec::with_capacity(count);
+        let mut index = vec![0; shape.len()];
+        for _ in 0..count {
+            let mut ind = 0;
+            let mut stride = 1;
+            for (dim, size) in index.iter().zip(shape.iter()) {
+                ind += dim * stride;
+                stride *= size;
+            }
+            data.push(values[ind]);
+            // step the row major index, the last dimension fastest
+            for (dim, size) in index.iter_mut().zip(shape.iter()).rev() {
+                *dim += 1;
+                if *dim < *size {
+                    break;
+                }
+                *dim = 0;
+            }
+        }
+        data
+    } else {
+        values
+    };
+    Ok(NpyArray {
+        shape,
+        kind,
+        item_size,
+        data,
+    })
+}
+
+/// The len bytes at an offset read from the file
+fn zip_bytes(bytes: &[u8], at: usize, len: usize) -> Result<&[u8], String> {
+    at.checked_add(len)
+        .and_then(|end| bytes.get(at..end))
+        .ok_or_else(|| "the zip is cut short".to_string())
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<u16, String> {
+    let b = zip_bytes(bytes, at, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, String> {
+    let b = zip_bytes(bytes, at, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Result<u64, String> {
+    let b = zip_bytes(bytes, at, 8)?;
+    Ok(u64::from_le_bytes([
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+    ]))
+}
+
+/// Names and contents of the files in a zip archive
+fn read_zip(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    // the end of central directory record, followed by a comment of up to 64k
+    let search_start = bytes.len().saturating_sub(22 + 0xffff);
+    let end = (search_start..bytes.len().saturating_sub(21))
+        .rev()
+        .find(|&at| bytes[at..at + 4] == [0x50, 0x4b, 0x05, 0x06])
+        .ok_or("not a zip file")?;
+    let mut entries = read_u16(bytes, end + 10)? as u64;
+    let mut offset = read_u32(bytes, end + 16)? as u64;
+    if offset == 0xffff_ffff && end >= 20 {
+        // zip64, the locator before the record points to the zip64 record
+        let record = read_u64(bytes, end - 20 + 8)? as usize;
+        let record = zip_bytes(bytes, record, 56)?;
+        entries = read_u64(record, 32)?;
+        offset = read_u64(record, 48)?;
+    }
+
+    let mut files = Vec::new();
+    let mut at = offset as usize;
+    for _ in 0..entries {
+        if read_u32(bytes, at)? != 0x0201_4b50 {
+            return Err("bad zip central directory".to_string());
+        }
+        let method = read_u16(bytes, at + 10)?;
+        let mut compressed = read_u32(bytes, at + 20)? as u64;
+        let name_len = read_u16(bytes, at + 28)? as usize;
+        let extra_len = read_u16(bytes, at + 30)? as usize;
+        let comment_len = read_u16(bytes, at + 32)? as usize;
+        let mut local = read_u32(bytes, at + 42)? as u64;
+        let mut uncompressed = read_u32(bytes, at + 24)? as u64;
+        // the reads above mean at is within the file, so these sums can't overflow
+        let name = zip_bytes(bytes, at + 46, name_len)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        // zip64 extra field, holding whichever sizes didn't fit in 32 bits
+        let mut extra = at + 46 + name_len;
+        let extra_end = extra + extra_len;
+        while extra + 4 <= extra_end {
+            let id = read_u16(bytes, extra)?;
+            let len = read_u16(bytes, extra + 2)? as usize;
+            if id == 1 {
+                let mut field = extra + 4;
+                for value in [&mut uncompressed, &mut compressed, &mut local] {
+                    if *value == 0xffff_ffff {
+                        *value = read_u64(bytes, field)?;
+                        field += 8;
+                    }
+                }
+            }
+            extra += 4 + len;
+        }
+        at = extra_end + comment_len;
+
+        let local = local as usize;
+        if read_u32(bytes, local)? != 0x0403_4b50 {
+            return Err(format!("bad zip header for {}", name));
+        }
+        let data_start = local
+            + 30
+            + read_u16(bytes, local + 26)? as usize
+            + read_u16(bytes, local + 28)? as usize;
+        let data = zip_bytes(bytes, data_start, compressed as usize)
+            .map_err(|_| format!("{} is cut short", name))?;
+        let data = match method {
+            0 => data.to_vec(),
+            // the output buffer doubles as it grows, and stops with an error rather
+            // than growing past the limit even when the data would fit
+            8 => miniz_oxide::inflate::decompress_to_vec_with_limit(
+                data,
+                (uncompressed as usize).saturating_mul(2),
+            )
+            .map_err(|why| format!("couldn't inflate {}: {:?}", name, why))?,
+            _ => return Err(format!("zip compression method {} isn't supported", method)),
+        };
+        if data.len() as u64 != uncompressed {
+            return Err(format!(
+                "{} is {} bytes but the zip gives its size as {}",
+                name,
+                data.len(),
+                uncompressed
+            ));
+        }
+        files.push((name, data));
+    }
+    Ok(files)
+}
+
+/// Parse the bytes of an npz file into its named arrays
+pub fn read_npz(bytes: &[u8]) -> Result<Vec<(String, NpyArray)>, String> {
+    read_zip(bytes)?
+        .into_iter()
+        .filter_map(|(name, data)| {
+            let name = name.strip_suffix(".npy")?.to_string();
+            Some(
+                read_npy(&data)
+                    .map(|array| (name.clone(), array))
+                    .map_err(|why| format!("{}: {}", name, why)),
+            )
+        })
+        .collect()
+}
+
+/// Arrays in an npy or npz file, an npy array is named after the file
+pub fn load_npy(filename: &str, progress: &Progress) -> Result<Vec<(String, NpyArray)>, String> {
+    let file =
+        File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+    if let Ok(metadata) = file.metadata() {
+        progress.set_total_bytes(metadata.len());
+    }
+    let mut bytes = Vec::new();
+    ProgressReader::new(file, progress)
+        .read_to_end(&mut bytes)
+        .map_err(|why| format!("couldn't read {}: {}", filename, why))?;
+    let path = Path::new(filename);
+    let arrays = if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("npz"))
+    {
+        read_npz(&bytes)
+    } else {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        read_npy(&bytes).map(|array| vec![(name, array)])
+    };
+    let arrays = arrays.map_err(|why| format!("couldn't decode {}: {}", filename, why))?;
+    for (name, array) in arrays.iter() {
+        println!(
+            "{} {} {:?} {}{}",
+            filename,
+            name,
+            array.shape,
+            array.kind,
+            array.item_size * 8
+        );
+    }
+    Ok(arrays)
+}
+
+impl NpyArray {
+    /// Whether the array is height x width, or height x width x 1 to 4 channels,
+    /// with at least one pixel
+    pub fn is_image(&self) -> bool {
+        let shape = match self.shape.len() {
+            2 => true,
+            3 => (1..=4).contains(&self.shape[2]),
+            _ => false,
+        };
+        shape && self.shape.iter().all(|dim| *dim > 0)
+    }
+
+    /// The array as an image, uint8 and uint16 keep their depth and the rest are floats
+    pub fn to_raw(&self) -> Result<RawImage, String> {
+        if !self.is_image() {
+            return Err(format!("a {:?} array isn't an image", self.shape));
+        }
+        let depth = match (self.kind, self.item_size) {
+            ('u', 1) | ('b', 1) => Depth::U8,
+            ('u', 2) => Depth::U16,
+            _ => Depth::F32,
+        };
+        Ok(RawImage {
+            size: (self.shape[1], self.shape[0]),
+            channels: self.shape.get(2).copied().unwrap_or(1),
+            depth,
+            data: self.data.iter().map(|v| *v as f32).collect(),
+            maxval: None,
+        })
+    }
+
+    /// Columns to plot, a 1D array is one column and the columns of a 2D array
+    /// are its second index.
+    pub fn to_columns(&self) -> Result<Vec<Vec<f64>>, String> {
+        match self.shape[..] {
+            [_] => Ok(vec![self.data.clone()]),
+            [rows, cols] => Ok((0..cols)
+                .map(|col| (0..rows).map(|row| self.data[row * cols + col]).collect())
+                .collect()),
+            _ => Err(format!("a {:?} array can't be plotted", self.shape)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An npy file, version 1, with the header padded to 64 bytes like numpy does
+    fn npy_bytes(descr: &str, fortran_order: bool, shape: &str, data: &[u8]) -> Vec<u8> {
+        let order = if fortran_order { "True" } else { "False" };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+            descr, order, shape
+        );
+        let pad = 63 - (10 + header.len()) % 64;
+        header.push_str(&" ".repeat(pad));
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// A zip of the named files, deflated or stored
+    fn zip_bytes(files: &[(&str, Vec<u8>)], deflate: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut central = Vec::new();
+        for (name, data) in files {
+            let (method, stored) = if deflate {
+                (8u16, miniz_oxide::deflate::compress_to_vec(data, 6))
+            } else {
+                (0, data.clone())
+            };
+            let local = bytes.len() as u32;
+            bytes.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+            bytes.extend_from_slice(&[20, 0, 0, 0]);
+            bytes.extend_from_slice(&method.to_le_bytes());
+            bytes.extend_from_slice(&[0; 8]);
+            bytes.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&[0, 0]);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&stored);
+
+            central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            central.extend_from_slice(&method.to_le_bytes());
+            central.extend_from_slice(&[0; 8]);
+            central.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&local.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let offset = bytes.len() as u32;
+        bytes.extend_from_slice(&central);
+        bytes.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn header() {
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (3, 4), }";
+        assert_eq!(
+            parse_header(header).unwrap(),
+            ("<f8".to_string(), false, vec![3, 4])
+        );
+        let header = "{'descr': '|u1', 'fortran_order': True, 'shape': (5,), }";
+        assert_eq!(
+            parse_header(header).unwrap(),
+            ("|u1".to_string(), true, vec![5])
+        );
+        let header = "{'descr': '<i4', 'fortran_order': False, 'shape': (), }";
+        assert_eq!(parse_header(header).unwrap().2, Vec::<usize>::new());
+        assert!(parse_header("{'descr': '<i4', 'shape': (2,), }").is_err());
+        assert!(parse_header("{'descr': '<i4', 'fortran_order': False, 'shape': (x,), }").is_err());
+    }
+
+    #[test]
+    fn little_endian_ints() {
+        let data: Vec<u8> = [-2i16, 300, 7]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let array = read_npy(&npy_bytes("<i2", false, "(3,)", &data)).unwrap();
+        assert_eq!(array.shape, vec![3]);
+        assert_eq!((array.kind, array.item_size), ('i', 2));
+        assert_eq!(array.data, vec![-2.0, 300.0, 7.0]);
+    }
+
+    #[test]
+    fn big_endian() {
+        let data: Vec<u8> = [1.5f32, -0.25]
+            .iter()
+            .flat_map(|v| v.to_be_bytes().to_vec())
+            .collect();
+        let array = read_npy(&npy_bytes(">f4", false, "(2,)", &data)).unwrap();
+        assert_eq!(array.data, vec![1.5, -0.25]);
+        let data: Vec<u8> = [-70000i32, 5]
+            .iter()
+            .flat_map(|v| v.to_be_bytes().to_vec())
+            .collect();
+        let array = read_npy(&npy_bytes(">i4", false, "(2,)", &data)).unwrap();
+        assert_eq!(array.data, vec![-70000.0, 5.0]);
+    }
+
+    #[test]
+    fn fortran_order() {
+        // [[1, 2, 3], [4, 5, 6]] stored column by column
+        let data = [1, 4, 2, 5, 3, 6];
+        let array = read_npy(&npy_bytes("|u1", true, "(2, 3)", &data)).unwrap();
+        assert_eq!(array.shape, vec![2, 3]);
+        assert_eq!(array.data, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(
+            array.to_columns().unwrap(),
+            vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]
+        );
+    }
+
+    #[test]
+    fn bad_sizes() {
+        assert!(read_npy(&npy_bytes("|u1", false, "(2, 3)", &[0; 5])).is_err());
+        let huge = format!("({}, {})", usize::MAX / 2, 4);
+        assert!(read_npy(&npy_bytes("<f8", false, &huge, &[])).is_err());
+        let empty = read_npy(&npy_bytes("|u1", false, "(0, 3)", &[])).unwrap();
+        assert!(!empty.is_image());
+    }
+
+    #[test]
+    fn npz() {
+        let image = npy_bytes("|u1", false, "(2, 2)", &[1, 2, 3, 4]);
+        let values = npy_bytes("<f8", false, "(1,)", &2.5f64.to_le_bytes());
+        let files = [("image.npy", image), ("values.npy", values)];
+        for deflate in [false, true].iter() {
+            let arrays = read_npz(&zip_bytes(&files, *deflate)).unwrap();
+            let names: Vec<&str> = arrays.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, vec!["image", "values"]);
+            assert_eq!(arrays[0].1.data, vec![1.0, 2.0, 3.0, 4.0]);
+            assert_eq!(arrays[1].1.data, vec![2.5]);
+        }
+        // sizes that don't match the data
+        for deflate in [false, true].iter() {
+            for size in [10u32, 100_000].iter() {
+                let mut lying = zip_bytes(&files, *deflate);
+                let central = lying
+                    .windows(4)
+                    .position(|sig| sig == 0x0201_4b50u32.to_le_bytes())
+                    .unwrap();
+                lying[central + 24..central + 28].copy_from_slice(&size.to_le_bytes());
+                assert!(read_npz(&lying).is_err());
+            }
+        }
+        let mut cut = zip_bytes(&files, true);
+        cut.drain(40..60);
+        assert!(read_npz(&cut).is_err());
+    }
+}
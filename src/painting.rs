@@ -0,0 +1,195 @@
+/*
+ * Freehand painting into the pixels of the image, grown from the egui Painting
+ * example that used to be commented out in app.rs.
+ *
+ * Each stroke is stamped with round dabs into a coverage mask that keeps the
+ * largest coverage of any dab, so a soft or translucent brush doesn't darken
+ * where dabs overlap. The stroke is then blended over a copy of the image from
+ * before the stroke started.
+ */
+
+use crate::image_io::save_image;
+use crate::pixel::{from_f32, lerp, to_f32};
+use crate::utility::Image;
+use eframe::egui::{self, Color32, Rect, Vec2};
+
+pub struct Painting {
+    pub enabled: bool,
+    /// brush radius in image pixels
+    radius: f32,
+    color: Color32,
+    /// fraction of the radius painted at full strength before fading out
+    hardness: f32,
+    /// paint transparency instead of the color
+    eraser: bool,
+    /// image position of the last dab of the stroke in progress
+    last: Option<[f32; 2]>,
+    /// the image when the stroke started
+    before: Option<Image>,
+    /// coverage of each pixel by the stroke in progress
+    coverage: Vec<f32>,
+    filename: String,
+    status: String,
+}
+
+impl Default for Painting {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 4.0,
+            color: Color32::LIGHT_BLUE,
+            hardness: 0.5,
+            eraser: false,
+            last: None,
+            before: None,
+            coverage: Vec::new(),
+            filename: "painted.png".to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+/// Brush strength at a fraction of the radius from the center
+fn falloff(t: f32, hardness: f32) -> f32 {
+    if t <= hardness {
+        1.0
+    } else if t >= 1.0 {
+        0.0
+    } else {
+        let s = (t - hardness) / (1.0 - hardness);
+        1.0 - s * s * (3.0 - 2.0 * s)
+    }
+}
+
+impl Painting {
+    pub fn ui_control(&mut self, ui: &mut egui::Ui, image: &Image) {
+        ui.checkbox(&mut self.enabled, "paint");
+        ui.add(egui::Slider::f32(&mut self.radius, 0.5..=64.0).text("brush radius"));
+        ui.add(egui::Slider::f32(&mut self.hardness, 0.0..=1.0).text("hardness"));
+        ui.horizontal(|ui| {
+            ui.color_edit_button_srgba(&mut self.color);
+            ui.checkbox(&mut self.eraser, "eraser");
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.filename);
+            if ui.button("save").clicked {
+                self.status = match save_image(image, &self.filename) {
+                    Ok(()) => format!("saved {}", self.filename),
+                    Err(why) => why,
+                };
+            }
+        });
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+    }
+
+    /// Stamp a dab into the coverage and repaint the pixels it covers from the
+    /// image before the stroke.
+    fn dab(&mut self, image: &mut Image, center: [f32; 2]) {
+        let before = match &self.before {
+            Some(before) if before.size == image.size => before,
+            _ => return,
+        };
+        let (width, height) = image.size;
+        let r = self.radius;
+        let x0 = ((center[0] - r).floor().max(0.0) as usize).min(width);
+        let x1 = ((center[0] + r).ceil().max(0.0) as usize).min(width);
+        let y0 = ((center[1] - r).floor().max(0.0) as usize).min(height);
+        let y1 = ((center[1] + r).ceil().max(0.0) as usize).min(height);
+        let paint = if self.eraser {
+            [0.0; 4]
+        } else {
+            to_f32(self.color)
+        };
+        // translucent colors cover partially even at full brush strength
+        let strength = if self.eraser {
+            1.0
+        } else {
+            self.color.a() as f32 / 255.0
+        };
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let dx = x as f32 + 0.5 - center[0];
+                let dy = y as f32 + 0.5 - center[1];
+                let cover = falloff(dx.hypot(dy) / r, self.hardness);
+                let ind = y * width + x;
+                if cover <= self.coverage[ind] {
+                    continue;
+                }
+                self.coverage[ind] = cover;
+                let fr = cover * strength;
+                let src = to_f32(before.pixels[ind]);
+                image.pixels[ind] = if self.eraser {
+                    from_f32(lerp(src, paint, fr))
+                } else {
+                    // paint over, the color is premultiplied so scale by coverage only
+                    let over = [
+                        paint[0] * cover + src[0] * (1.0 - fr),
+                        paint[1] * cover + src[1] * (1.0 - fr),
+                        paint[2] * cover + src[2] * (1.0 - fr),
+                        paint[3] * cover + src[3] * (1.0 - fr),
+                    ];
+                    from_f32(over)
+                };
+            }
+        }
+    }
+
+    /// Paint where the mouse drags over the image, rect is where the image is on screen
+    /// and scale the screen points per image pixel. Returns the name of a finished stroke
+    /// and the image from before it.
+    pub fn ui_content(
+        &mut self,
+        ui: &mut egui::Ui,
+        response: &egui::Response,
+        rect: Rect,
+        scale: Vec2,
+        image: &mut Image,
+    ) -> Option<(String, Image)> {
+        if !self.enabled {
+            return None;
+        }
+        let input = ui.input();
+        let mouse = input.mouse.pos.map(|pos| {
+            let p = pos - rect.min;
+            [p.x / scale.x, p.y / scale.y]
+        });
+
+        // outline of the brush
+        if let (true, Some(pos)) = (response.hovered, input.mouse.pos) {
+            let radius = self.radius * scale.x;
+            ui.painter()
+                .circle_stroke(pos, radius, (1.0, Color32::from_gray(200)));
+        }
+
+        if response.active {
+            let pos = mouse?;
+            if self.before.is_none() {
+                self.before = Some(image.clone());
+                self.coverage = vec![0.0; image.pixels.len()];
+            }
+            // space the dabs so the stroke is continuous however fast the mouse moves
+            let last = self.last.unwrap_or(pos);
+            let dist = (pos[0] - last[0]).hypot(pos[1] - last[1]);
+            let spacing = (self.radius * 0.25).max(0.5);
+            let steps = (dist / spacing).ceil().max(1.0) as usize;
+            for step in 1..=steps {
+                let fr = step as f32 / steps as f32;
+                let p = [
+                    last[0] + (pos[0] - last[0]) * fr,
+                    last[1] + (pos[1] - last[1]) * fr,
+                ];
+                self.dab(image, p);
+            }
+            self.last = Some(pos);
+            None
+        } else {
+            let before = self.before.take()?;
+            self.last = None;
+            self.coverage.clear();
+            let name = if self.eraser { "erase" } else { "paint" };
+            Some((name.to_string(), before))
+        }
+    }
+}
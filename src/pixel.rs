@@ -0,0 +1,51 @@
+/*
+ * Conversions between the 8-bit premultiplied pixels and floats for the image
+ * operations that need to interpolate or accumulate.
+ */
+
+use eframe::egui::{Color32, Rgba};
+
+pub fn to_f32(color: Color32) -> [f32; 4] {
+    let [r, g, b, a] = color.to_array();
+    [r as f32, g as f32, b as f32, a as f32]
+}
+
+/// Straight alpha 8-bit rgba, undoing the premultiplication of Color32::from_rgba_unmultiplied
+/// by dividing the color channels by alpha in the linear space it was applied in
+pub fn unmultiply(color: Color32) -> [u8; 4] {
+    let a = color.a();
+    if a == 0 || a == 255 {
+        return color.to_array();
+    }
+    let linear = Rgba::from(color);
+    let alpha = linear.a();
+    let straight = Rgba::from_rgb(linear.r() / alpha, linear.g() / alpha, linear.b() / alpha);
+    let [r, g, b, _] = Color32::from(straight).to_array();
+    [r, g, b, a]
+}
+
+/// Round and saturate back to 8-bit, the color channels are kept no larger than alpha
+/// so the result is still valid premultiplied color.
+pub fn from_f32(rgba: [f32; 4]) -> Color32 {
+    let a = rgba[3].round().clamp(0.0, 255.0);
+    let channel = |v: f32| v.round().clamp(0.0, a) as u8;
+    Color32::from_rgba_premultiplied(
+        channel(rgba[0]),
+        channel(rgba[1]),
+        channel(rgba[2]),
+        a as u8,
+    )
+}
+
+pub fn add(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]
+}
+
+pub fn scale(a: [f32; 4], sc: f32) -> [f32; 4] {
+    [a[0] * sc, a[1] * sc, a[2] * sc, a[3] * sc]
+}
+
+/// Linear interpolation, fr of 0.0 is all a and 1.0 is all b
+pub fn lerp(a: [f32; 4], b: [f32; 4], fr: f32) -> [f32; 4] {
+    add(scale(a, 1.0 - fr), scale(b, fr))
+}
@@ -1,14 +1,32 @@
 mod csv_plot;
-mod utility;
+mod data_store;
+mod decimate;
+mod source;
 
 use eframe::{egui, epi};
 // use std::fs::File;
 use crate::csv_plot::{get_filename, load_csv};
-use crate::utility::{Image, TexMngr};
+use crate::data_store::{DataStore, Retention};
+use crate::source::Source;
+use egui_image::job::{Job, Progress, ProgressReader};
+use egui_image::{load_npy, Image, Recorder, TexMngr};
+use rayon::prelude::*;
 use std::fs::File;
 use std::path::Path;
+use std::sync::mpsc::Receiver;
 use std::time::{Duration, Instant};
 
+/// Number of plots side by side
+const TILES: usize = 2;
+/// Height of each plot tile
+const TILE_HEIGHT: usize = 180;
+/// Pixels from the top of each tile to the zero line of the plot
+const BASELINE: usize = 120;
+/// Pixels to the left of the plot within each tile
+const LEFT_MARGIN: usize = 60;
+/// Pixels below the bottom row of tiles
+const BOTTOM_MARGIN: usize = 60;
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
 pub struct PlotImage {
@@ -18,68 +36,212 @@ pub struct PlotImage {
     y_scale: f32,
     y_ind: usize,
     last_update: Instant,
-    filename: String,
+    last_load: Instant,
+    /// Reload the file periodically so another process can be updating it
+    auto_reload: bool,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    source: Source,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    receiver: Option<Receiver<Vec<f64>>>,
+    /// Loads the csv file in the background
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    loader: Option<Job<Vec<Vec<f64>>>>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    store: DataStore,
+    retain_by_time: bool,
+    max_samples: usize,
+    max_seconds: f64,
+    /// Keep the latest samples in view as new data arrives
+    follow_latest: bool,
+    /// First sample plotted when not following the latest, counted from the start of the data
+    /// so the view doesn't move when retention drops old samples
+    view_start: usize,
+    /// Horizontal pixels per sample, below 1.0 the samples are decimated to min/max envelopes
+    plot_x_scale: f64,
+    /// End of the data that is plotted while paused, counted like view_start,
+    /// ingestion continues into the store
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    frozen: Option<usize>,
     #[cfg_attr(feature = "persistence", serde(skip))]
     image: Image,
     #[cfg_attr(feature = "persistence", serde(skip))]
+    tiles: Vec<Image>,
+    #[cfg_attr(feature = "persistence", serde(skip))]
+    recorder: Recorder,
+    #[cfg_attr(feature = "persistence", serde(skip))]
     tex_mngr: TexMngr,
 }
 
-fn draw_point(image: &mut Image, x: f64, y: f64, color: egui::Color32) {
-    let width = image.size.0;
-    if x < 0.0 || x as usize >= width {
-        return;
+fn load_columns(filename: &str, progress: &Progress) -> Result<Vec<Vec<f64>>, String> {
+    let path = Path::new(filename);
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("npy") || ext.eq_ignore_ascii_case("npz"))
+    {
+        // the first array that can be plotted
+        let arrays = load_npy(filename, progress)?;
+        return arrays
+            .iter()
+            .find(|(_, array)| array.shape.len() <= 2 && !array.shape.is_empty())
+            .ok_or(format!("{} has no 1D or 2D array to plot", filename))?
+            .1
+            .to_columns();
     }
-    let height = image.size.1;
-    if y < 0.0 || y as usize >= height {
-        return;
+    let csv_file = match File::open(&path) {
+        Err(why) => return Err(format!("couldn't open {}: {}", path.display(), why)),
+        Ok(csv_file) => csv_file,
+    };
+    if let Ok(metadata) = csv_file.metadata() {
+        progress.set_total_bytes(metadata.len());
     }
-    let x = x as usize;
-    let y = y as usize;
-    let ind = y * width + x;
-    image.pixels[ind] = color;
+
+    let reader = ProgressReader::new(csv_file, progress);
+    load_csv(reader, |rows| progress.set_rows(rows as u64))
+        .map_err(|why| format!("couldn't parse {}: {}", path.display(), why))
 }
 
-fn make_plot(mut image: &mut Image, filename: &str, x_scale: f64, y_scale: f64) {
-    let width = image.size.0;
-    let height = image.size.1;
-    let sc = 0.95;
-    for i in 0..(width * height) {
-        let r = image.pixels[i].r();
-        let g = image.pixels[i].g();
-        let b = image.pixels[i].b();
-        image.pixels[i] = egui::Color32::from_rgb(
-            (r as f64 * sc) as u8,
-            (g as f64 * sc) as u8,
-            (b as f64 * sc) as u8,
+/// Load the csv file on a separate thread so the ui stays responsive while a large file loads
+fn spawn_load_columns(filename: &str) -> Job<Vec<Vec<f64>>> {
+    let filename = filename.to_string();
+    Job::spawn(format!("loading {}", filename), move |progress| {
+        load_columns(&filename, progress)
+    })
+}
+
+/// Width in pixels of one plot tile
+fn tile_width(width: usize) -> usize {
+    (width / TILES).saturating_sub(LEFT_MARGIN)
+}
+
+/// How many samples fit across one plot tile
+fn visible_samples(width: usize, x_scale: f64) -> usize {
+    (tile_width(width) as f64 / x_scale) as usize
+}
+
+fn fade(image: &mut Image, sc: f64) {
+    for pixel in image.pixels.iter_mut() {
+        *pixel = egui::Color32::from_rgb(
+            (pixel.r() as f64 * sc) as u8,
+            (pixel.g() as f64 * sc) as u8,
+            (pixel.b() as f64 * sc) as u8,
         );
     }
+}
 
-    let path = Path::new(filename);
-    let csv_file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", path.display(), why),
-        Ok(csv_file) => csv_file,
-    };
+/// Copy the tile into the image with its top left corner at x0, y0, clipping to the image
+fn blit(image: &mut Image, tile: &Image, x0: usize, y0: i64) {
+    let width = image.size.0;
+    if x0 >= width {
+        return;
+    }
+    let copy_width = tile.size.0.min(width - x0);
+    for (tile_y, row) in tile.pixels.chunks(tile.size.0).enumerate() {
+        let y = y0 + tile_y as i64;
+        if y < 0 || y >= image.size.1 as i64 {
+            continue;
+        }
+        let ind = y as usize * width + x0;
+        image.pixels[ind..ind + copy_width].copy_from_slice(&row[..copy_width]);
+    }
+}
 
-    let columns = load_csv(csv_file).unwrap();
+/// The range of samples and the scales shared by all the tiles of one plot
+#[derive(Clone, Copy)]
+struct PlotView {
+    /// First sample plotted as an index into the store
+    start: usize,
+    /// One past the last sample that may be plotted
+    end: usize,
+    x_scale: f64,
+    y_scale: f64,
+}
 
-    for (col_ind, column) in columns.iter().enumerate() {
-        // println!("{} {:?}", col_ind, column);
-        let color = egui::Color32::from_rgb(
-            (col_ind * 30) as u8,
-            (255 - (col_ind * 20)) as u8,
-            (50 + col_ind * 10) as u8,
-        );
-        let tiles = 2;
-        let x_offset = ((col_ind % tiles) * width / tiles + 10) as f64;
-        let y_offset = (col_ind / tiles) as f64 * 180.0 + 120.0;
-
-        for (i, val) in column.iter().enumerate() {
-            let x = i as f64 * x_scale + 50.0 + x_offset;
-            let y = val * y_scale + y_offset;
-            draw_point(&mut image, x, height as f64 - y, color);
-            draw_point(&mut image, x, height as f64 - y_offset, egui::Color32::GRAY);
+/// Draw one column into its own tile, width is the full image width and tile_x the
+/// left edge of the tile within it
+fn plot_column(
+    tile: &mut Image,
+    store: &DataStore,
+    col_ind: usize,
+    width: usize,
+    tile_x: usize,
+    view: PlotView,
+) {
+    let PlotView {
+        start,
+        end,
+        x_scale,
+        y_scale,
+    } = view;
+    let column = &store.columns()[col_ind];
+    let end = end.min(column.len());
+    // println!("{} {:?}", col_ind, column);
+    let color = egui::Color32::from_rgb(
+        (col_ind * 30) as u8,
+        (255 - (col_ind * 20)) as u8,
+        (50 + col_ind * 10) as u8,
+    );
+    // don't draw past the right edge of the image
+    let plot_width = tile_width(width).min(width.saturating_sub(tile_x + LEFT_MARGIN));
+    let x_offset = LEFT_MARGIN as f64;
+    let y_offset = BASELINE as f64;
+
+    let zero_line = (
+        (x_offset as f32, y_offset as f32),
+        ((x_offset + plot_width as f64) as f32, y_offset as f32),
+    );
+    tile.draw_line(zero_line.0, zero_line.1, egui::Color32::GRAY);
+    tile.draw_text((4.0, 4.0), &format!("col {}", col_ind), 1, color);
+
+    if x_scale >= 1.0 {
+        let visible = (plot_width as f64 / x_scale) as usize;
+        for (i, val) in column[..end].iter().skip(start).take(visible).enumerate() {
+            let x = i as f64 * x_scale + x_offset;
+            let y = y_offset - val * y_scale;
+            tile.blend_pixel(x.floor() as i64, y.floor() as i64, color);
         }
+    } else {
+        // more than one sample per pixel column, draw the min to max envelope
+        for px in 0..plot_width {
+            let sample0 = start + (px as f64 / x_scale) as usize;
+            let sample1 = (start + ((px + 1) as f64 / x_scale) as usize).min(end);
+            if let Some((min, max)) = store.min_max(col_ind, sample0, sample1) {
+                let x = px as f64 + x_offset;
+                // keep the line short when the envelope is far outside of the tile
+                let max_y = tile.size.1 as f64;
+                let y0 = (y_offset - min * y_scale).max(-1.0).min(max_y) as f32;
+                let y1 = (y_offset - max * y_scale).max(-1.0).min(max_y) as f32;
+                tile.draw_line((x as f32, y0), (x as f32, y1), color);
+            }
+        }
+    }
+}
+
+/// Each column is plotted into its own tile, the tiles are faded and drawn in parallel
+/// then copied into their disjoint regions of the image.
+fn make_plot(image: &mut Image, tiles: &mut Vec<Image>, store: &DataStore, view: PlotView) {
+    let width = image.size.0;
+    let height = image.size.1;
+    let tile_size = (width / TILES, TILE_HEIGHT);
+    tiles.resize_with(store.columns().len(), || Image {
+        size: tile_size,
+        pixels: vec![egui::Color32::BLACK; tile_size.0 * tile_size.1],
+    });
+
+    tiles
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(col_ind, tile)| {
+            fade(tile, 0.95);
+            let tile_x = (col_ind % TILES) * tile_size.0;
+            plot_column(tile, store, col_ind, width, tile_x, view);
+        });
+
+    for (col_ind, tile) in tiles.iter().enumerate() {
+        let x0 = (col_ind % TILES) * tile_size.0;
+        // the first row of tiles is at the bottom of the image
+        let row = (col_ind / TILES) as i64;
+        let y0 = height as i64 - BOTTOM_MARGIN as i64 - (row + 1) * TILE_HEIGHT as i64;
+        blit(image, tile, x0, y0);
     }
 }
 
@@ -92,9 +254,17 @@ impl Default for PlotImage {
         for _ in 0..(size.0 * size.1) {
             pixels.push(egui::Color32::BLACK);
         }
-        let mut image = Image { size, pixels };
+        let image = Image { size, pixels };
         let filename = get_filename();
-        make_plot(&mut image, &filename, 10.0, 50.0);
+        let source = Source::new(&filename);
+        let receiver = source.spawn();
+        let (loader, max_samples) = match source {
+            // keep everything loaded from a file by default
+            Source::File(ref filename) => (Some(spawn_load_columns(filename)), 100_000_000),
+            _ => (None, 2000),
+        };
+        let store = DataStore::new(Retention::Samples(max_samples));
+        let plot_x_scale = 10.0;
 
         Self {
             // Example stuff:
@@ -103,8 +273,22 @@ impl Default for PlotImage {
             y_scale: 1.0,
             y_ind: 30,
             last_update: Instant::now(),
-            filename,
+            last_load: Instant::now(),
+            auto_reload: true,
+            source,
+            receiver,
+            loader,
+            store,
+            retain_by_time: false,
+            max_samples,
+            max_seconds: 10.0,
+            follow_latest: true,
+            view_start: 0,
+            plot_x_scale,
+            frozen: None,
             image,
+            tiles: Vec::new(),
+            recorder: Default::default(),
             tex_mngr: Default::default(),
         }
     }
@@ -147,8 +331,22 @@ impl epi::App for PlotImage {
             y_scale,
             y_ind,
             last_update,
-            filename,
+            last_load,
+            auto_reload,
+            source,
+            receiver,
+            loader,
+            store,
+            retain_by_time,
+            max_samples,
+            max_seconds,
+            follow_latest,
+            view_start,
+            plot_x_scale,
+            frozen,
             ref mut image,
+            tiles,
+            recorder,
             tex_mngr,
         } = self;
 
@@ -211,6 +409,65 @@ impl epi::App for PlotImage {
 
             ui.add(egui::Slider::usize(y_ind, 0..=(image.size.1 - 1)).text("y ind"));
 
+            ui.horizontal(|ui| {
+                ui.label("retain last");
+                ui.radio_value(retain_by_time, false, "samples");
+                ui.radio_value(retain_by_time, true, "seconds");
+                if *retain_by_time {
+                    ui.add(
+                        egui::Slider::f64(max_seconds, 0.1..=3600.0)
+                            .logarithmic(true)
+                            .text("seconds"),
+                    );
+                } else {
+                    ui.add(
+                        egui::Slider::usize(max_samples, 10..=100_000_000)
+                            .logarithmic(true)
+                            .text("samples"),
+                    );
+                }
+            });
+            if *retain_by_time {
+                store.set_retention(Retention::Seconds(*max_seconds));
+            } else {
+                store.set_retention(Retention::Samples(*max_samples));
+            }
+
+            if let Source::File(_) = source {
+                ui.checkbox(auto_reload, "reload file");
+            }
+            if let Some(job) = loader {
+                job.ui(ui);
+            }
+            ui.collapsing("record gif", |ui| recorder.ui(ui));
+
+            ui.horizontal(|ui| {
+                ui.checkbox(follow_latest, "follow latest");
+                let pause_text = if frozen.is_some() { "resume" } else { "pause" };
+                if ui.button(pause_text).clicked {
+                    if frozen.is_some() {
+                        *frozen = None;
+                    } else {
+                        *frozen = Some(store.end_sample());
+                    }
+                }
+                if !*follow_latest {
+                    let end = frozen.unwrap_or_else(|| store.end_sample());
+                    ui.add(
+                        egui::Slider::usize(
+                            view_start,
+                            store.first_sample()..=end.saturating_sub(1),
+                        )
+                        .text("first sample"),
+                    );
+                }
+            });
+            ui.add(
+                egui::Slider::f64(plot_x_scale, 0.000_01..=20.0)
+                    .logarithmic(true)
+                    .text("pixels per sample"),
+            );
+
             egui::ScrollArea::auto_sized().show(ui, |ui| {
                 // TODO(lucsw) this is only happening when there is a mouse motion or other change
                 // over the window- as noted above the repaint needs to be triggered.
@@ -218,10 +475,62 @@ impl epi::App for PlotImage {
                 // image.shift(1, 0);
                 //
                 //
+                let update_period = match source {
+                    Source::File(filename) => {
+                        let finished = loader
+                            .as_ref()
+                            .and_then(|job| job.poll().map(|result| (result, job.is_cancelled())));
+                        if let Some((result, cancelled)) = finished {
+                            match result {
+                                Ok(columns) => store.set_columns(columns),
+                                Err(why) => {
+                                    println!("{}", why);
+                                    // don't start loading again right after a cancel, other
+                                    // errors like a file that is being rewritten are retried
+                                    if cancelled {
+                                        *auto_reload = false;
+                                    }
+                                }
+                            }
+                            *loader = None;
+                            *last_load = Instant::now();
+                        }
+                        if *auto_reload
+                            && loader.is_none()
+                            && last_load.elapsed() > Duration::from_millis(1000)
+                        {
+                            *loader = Some(spawn_load_columns(filename));
+                        }
+                        Duration::from_millis(1000)
+                    }
+                    _ => {
+                        if let Some(receiver) = receiver {
+                            store.append_rows(receiver.try_iter().collect());
+                        }
+                        Duration::from_millis(50)
+                    }
+                };
+
                 let update_image;
-                if last_update.elapsed() > Duration::from_millis(1000) {
+                if last_update.elapsed() > update_period {
+                    let end = frozen.unwrap_or_else(|| store.end_sample());
+                    if *follow_latest {
+                        *view_start =
+                            end.saturating_sub(visible_samples(image.size.0, *plot_x_scale));
+                    }
+                    // convert from positions since the start of the data to indices into the store
+                    let first = store.first_sample();
+                    let start = view_start.saturating_sub(first);
+                    let end = end.saturating_sub(first);
                     // this takes around 50 ms unoptimized
-                    make_plot(image, &filename, 10.0, 50.0);
+                    let view = PlotView {
+                        start,
+                        end,
+                        x_scale: *plot_x_scale,
+                        y_scale: 50.0,
+                    };
+                    make_plot(image, tiles, store, view);
+                    recorder.capture(image, ui.input().time);
                     *last_update = Instant::now();
                     update_image = true;
                     println!("----");
@@ -0,0 +1,777 @@
+/*
+ * Images with more precision than the 8 bits per channel that are displayed:
+ * 16 bit pngs, tiffs and pgms, float tiffs, pfm files and numpy arrays, like
+ * those from depth cameras, and hdr and exr images.
+ *
+ * The native values are kept alongside the displayed Image, which is made from
+ * them with levels that map a range of values to 0..255 or tone map linear
+ * light, gamma, and a colormap for single channel images. Non finite values, which depth cameras use for
+ * missing measurements, are shown transparent.
+ */
+
+use crate::hdr::{read_exr, read_hdr};
+use crate::image_io::{save_image, to_image};
+use crate::job::{Progress, ProgressReader};
+use crate::netpbm::{is_netpbm, read_netpbm, save_netpbm};
+use crate::npy::load_npy;
+use crate::pixel::unmultiply;
+use crate::utility::Image;
+use eframe::egui::{self, Color32};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Depth {
+    U8,
+    U16,
+    F32,
+}
+
+impl Depth {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Depth::U8 => "8 bit",
+            Depth::U16 => "16 bit",
+            Depth::F32 => "float",
+        }
+    }
+
+    /// Value of full intensity, which for floats is taken to be 1.0
+    pub fn max_value(&self) -> f32 {
+        match self {
+            Depth::U8 => 255.0,
+            Depth::U16 => 65535.0,
+            Depth::F32 => 1.0,
+        }
+    }
+}
+
+/// Pixel values at their native depth, 1 to 4 interleaved channels of gray,
+/// gray and alpha, rgb or rgba.
+#[derive(Clone)]
+pub struct RawImage {
+    pub size: (usize, usize),
+    pub channels: usize,
+    pub depth: Depth,
+    pub data: Vec<f32>,
+    /// Largest value the file declared, for 16 bit netpbm files so their maxval is
+    /// written back
+    pub maxval: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colormap {
+    Gray,
+    Viridis,
+    Inferno,
+    Turbo,
+}
+
+// colors at nine evenly spaced points, interpolated in between
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [31, 12, 72],
+    [85, 15, 109],
+    [136, 34, 106],
+    [186, 54, 85],
+    [227, 89, 51],
+    [249, 140, 10],
+    [249, 201, 50],
+    [252, 255, 164],
+];
+const TURBO: [[u8; 3]; 9] = [
+    [48, 18, 59],
+    [70, 107, 227],
+    [40, 187, 236],
+    [50, 242, 152],
+    [164, 252, 60],
+    [236, 208, 35],
+    [251, 128, 34],
+    [208, 47, 5],
+    [122, 4, 3],
+];
+
+impl Colormap {
+    pub const ALL: [Colormap; 4] = [
+        Colormap::Gray,
+        Colormap::Viridis,
+        Colormap::Inferno,
+        Colormap::Turbo,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Colormap::Gray => "gray",
+            Colormap::Viridis => "viridis",
+            Colormap::Inferno => "inferno",
+            Colormap::Turbo => "turbo",
+        }
+    }
+
+    /// Color for t from 0.0 to 1.0
+    pub fn map(&self, t: f32) -> [u8; 3] {
+        let table = match self {
+            Colormap::Gray => {
+                let v = (t * 255.0).round() as u8;
+                return [v, v, v];
+            }
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Turbo => &TURBO,
+        };
+        let x = t.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+        let ind = (x as usize).min(table.len() - 2);
+        let fr = x - ind as f32;
+        let lerp = |c: usize| {
+            (table[ind][c] as f32 * (1.0 - fr) + table[ind + 1][c] as f32 * fr).round() as u8
+        };
+        [lerp(0), lerp(1), lerp(2)]
+    }
+}
+
+/// How values are brought into the displayed range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// linear from the black to the white value
+    Levels,
+    /// scaled by the exposure and clipped at 1.0
+    Exposure,
+    /// v / (1 + v) after the exposure, rolling off highlights
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve after the exposure
+    Aces,
+}
+
+impl ToneMap {
+    pub const ALL: [ToneMap; 4] = [
+        ToneMap::Levels,
+        ToneMap::Exposure,
+        ToneMap::Reinhard,
+        ToneMap::Aces,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMap::Levels => "levels",
+            ToneMap::Exposure => "exposure",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Aces => "aces",
+        }
+    }
+}
+
+/// Mapping from native values to displayed ones
+#[derive(Clone, Debug, PartialEq)]
+pub struct Levels {
+    pub tone: ToneMap,
+    /// value shown as black with ToneMap::Levels
+    pub black: f32,
+    /// value shown as white with ToneMap::Levels
+    pub white: f32,
+    /// stops the values are scaled by for the other tone maps
+    pub exposure: f32,
+    pub gamma: f32,
+    /// used for single channel images
+    pub colormap: Colormap,
+}
+
+impl Levels {
+    /// Levels covering all the values the depth can hold
+    pub fn full(depth: Depth) -> Self {
+        Self {
+            tone: ToneMap::Levels,
+            black: 0.0,
+            white: depth.max_value(),
+            exposure: 0.0,
+            gamma: 1.0,
+            colormap: Colormap::Gray,
+        }
+    }
+
+    /// Displayed intensity of the value, 0.0 to 1.0 with gamma applied
+    pub fn normalize(&self, value: f32) -> f32 {
+        let range = self.white - self.black;
+        let exposed = || (value * (2.0f32).powf(self.exposure)).max(0.0);
+        let t = match self.tone {
+            ToneMap::Levels if range.abs() > f32::EPSILON => {
+                ((value - self.black) / range).clamp(0.0, 1.0)
+            }
+            ToneMap::Levels if value >= self.white => 1.0,
+            ToneMap::Levels => 0.0,
+            ToneMap::Exposure => exposed().min(1.0),
+            ToneMap::Reinhard => {
+                let v = exposed();
+                v / (1.0 + v)
+            }
+            ToneMap::Aces => {
+                let v = exposed();
+                (v * (2.51 * v + 0.03) / (v * (2.43 * v + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }
+        };
+        if self.gamma == 1.0 {
+            t
+        } else {
+            t.powf(1.0 / self.gamma)
+        }
+    }
+}
+
+impl RawImage {
+    /// 8 bit rgba values of the displayed pixels
+    pub fn from_image(image: &Image) -> Self {
+        let data = image
+            .pixels
+            .iter()
+            // undo the premultiplication, as when saving
+            .flat_map(|pixel| unmultiply(*pixel).map(|v| v as f32))
+            .collect();
+        Self {
+            size: image.size,
+            channels: 4,
+            depth: Depth::U8,
+            data,
+            maxval: None,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> &[f32] {
+        let ind = (y * self.size.0 + x) * self.channels;
+        &self.data[ind..ind + self.channels]
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.channels == 2 || self.channels == 4
+    }
+
+    /// Smallest and largest finite values of the color channels, alpha is left out
+    pub fn range(&self) -> Option<(f32, f32)> {
+        let colors = if self.has_alpha() {
+            self.channels - 1
+        } else {
+            self.channels
+        };
+        self.data
+            .chunks(self.channels)
+            .flat_map(|pixel| pixel[..colors].iter())
+            .filter(|v| v.is_finite())
+            .fold(None, |range, &v| match range {
+                None => Some((v, v)),
+                Some((lo, hi)) => Some((v.min(lo), v.max(hi))),
+            })
+    }
+
+    /// Displayed pixels of the values mapped through the levels
+    pub fn to_image(&self, levels: &Levels) -> Image {
+        let max_value = self.depth.max_value();
+        let alpha = |pixel: &[f32]| {
+            if self.has_alpha() {
+                (pixel[self.channels - 1] / max_value * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            } else {
+                255
+            }
+        };
+        let level = |v: f32| (levels.normalize(v) * 255.0).round() as u8;
+        let pixels = self
+            .data
+            .chunks(self.channels)
+            .map(|pixel| {
+                if self.channels <= 2 {
+                    if !pixel[0].is_finite() {
+                        return Color32::TRANSPARENT;
+                    }
+                    let [r, g, b] = levels.colormap.map(levels.normalize(pixel[0]));
+                    Color32::from_rgba_unmultiplied(r, g, b, alpha(pixel))
+                } else {
+                    if !pixel[..3].iter().all(|v| v.is_finite()) {
+                        return Color32::TRANSPARENT;
+                    }
+                    let (r, g, b) = (level(pixel[0]), level(pixel[1]), level(pixel[2]));
+                    Color32::from_rgba_unmultiplied(r, g, b, alpha(pixel))
+                }
+            })
+            .collect();
+        Image {
+            size: self.size,
+            pixels,
+        }
+    }
+}
+
+/// A decoded file, 8 bit images go straight to display
+pub enum Loaded {
+    Image(Image),
+    Raw(RawImage),
+}
+
+impl Loaded {
+    fn from_raw(raw: RawImage) -> Self {
+        if raw.depth == Depth::U8 {
+            Loaded::Image(raw.to_image(&Levels::full(Depth::U8)))
+        } else {
+            Loaded::Raw(raw)
+        }
+    }
+
+    /// The image as the viewer first shows it, native values drawn with auto levels
+    pub fn into_image(self) -> Image {
+        match self {
+            Loaded::Image(image) => image,
+            Loaded::Raw(raw) => LevelsTool::default().reset(&raw),
+        }
+    }
+}
+
+/// Tiff through the tiff crate, which unlike the image crate decodes float samples
+fn read_tiff(reader: impl Read + Seek) -> Result<RawImage, String> {
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::ColorType;
+    let mut decoder = Decoder::new(reader).map_err(|why| why.to_string())?;
+    let (width, height) = decoder.dimensions().map_err(|why| why.to_string())?;
+    let channels = match decoder.colortype().map_err(|why| why.to_string())? {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) => 3,
+        ColorType::RGBA(_) => 4,
+        other => return Err(format!("{:?} tiffs aren't supported", other)),
+    };
+    let (depth, data) = match decoder.read_image().map_err(|why| why.to_string())? {
+        DecodingResult::U8(v) => (Depth::U8, v.into_iter().map(|v| v as f32).collect()),
+        DecodingResult::U16(v) => (Depth::U16, v.into_iter().map(|v| v as f32).collect()),
+        DecodingResult::F32(v) => (Depth::F32, v),
+        DecodingResult::F64(v) => (Depth::F32, v.into_iter().map(|v| v as f32).collect()),
+        _ => return Err("32 and 64 bit integer tiffs aren't supported".to_string()),
+    };
+    Ok(RawImage {
+        size: (width as usize, height as usize),
+        channels,
+        depth,
+        data,
+        maxval: None,
+    })
+}
+
+fn from_u16(size: (u32, u32), channels: usize, data: Vec<u16>) -> RawImage {
+    RawImage {
+        size: (size.0 as usize, size.1 as usize),
+        channels,
+        depth: Depth::U16,
+        data: data.into_iter().map(|v| v as f32).collect(),
+        maxval: None,
+    }
+}
+
+/// Extensions load_native decodes itself, anything else goes to the image crate
+pub const NATIVE_EXTENSIONS: &[&str] = &[
+    "npy", "npz", "pgm", "ppm", "pnm", "pfm", "tif", "tiff", "hdr", "exr",
+];
+
+/// Whether load_native decodes the file itself, going by its extension
+pub fn is_native(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy().to_lowercase();
+        NATIVE_EXTENSIONS.contains(&ext.as_str())
+    })
+}
+
+/// Whether load_native can open the file, going by its extension
+pub fn can_load(path: &Path) -> bool {
+    is_native(path) || image::ImageFormat::from_path(path).is_ok()
+}
+
+/// Decode an image file keeping values that don't fit in 8 bits at their native depth
+pub fn load_native(filename: &str, progress: &Progress) -> Result<Loaded, String> {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if extension == "npy" || extension == "npz" {
+        // the first array that can be shown, npz files often hold other arrays too
+        let arrays = load_npy(filename, progress)?;
+        let (_, array) = arrays
+            .iter()
+            .find(|(_, array)| array.is_image())
+            .ok_or(format!("{} has no 2D or 3D array to show", filename))?;
+        return Ok(Loaded::from_raw(array.to_raw()?));
+    }
+    let file =
+        File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+    if let Ok(metadata) = file.metadata() {
+        progress.set_total_bytes(metadata.len());
+    }
+    let reader = BufReader::new(ProgressReader::new(file, progress));
+    let decode_error = |why: String| format!("couldn't decode {}: {}", filename, why);
+    let loaded = match extension.as_str() {
+        "pgm" | "ppm" | "pnm" | "pfm" => {
+            Loaded::from_raw(read_netpbm(reader).map_err(decode_error)?)
+        }
+        "tif" | "tiff" => Loaded::from_raw(read_tiff(reader).map_err(decode_error)?),
+        "hdr" => Loaded::from_raw(read_hdr(reader).map_err(decode_error)?),
+        "exr" => {
+            let mut bytes = Vec::new();
+            let mut reader = reader;
+            reader
+                .read_to_end(&mut bytes)
+                .map_err(|why| format!("couldn't read {}: {}", filename, why))?;
+            Loaded::from_raw(read_exr(&bytes).map_err(decode_error)?)
+        }
+        _ => {
+            let image = image::io::Reader::new(reader)
+                .with_guessed_format()
+                .map_err(|why| format!("couldn't read {}: {}", filename, why))?
+                .decode()
+                .map_err(|why| decode_error(why.to_string()))?;
+            match image {
+                image::DynamicImage::ImageLuma16(buffer) => {
+                    Loaded::Raw(from_u16(buffer.dimensions(), 1, buffer.into_raw()))
+                }
+                image::DynamicImage::ImageLumaA16(buffer) => {
+                    Loaded::Raw(from_u16(buffer.dimensions(), 2, buffer.into_raw()))
+                }
+                image::DynamicImage::ImageRgb16(buffer) => {
+                    Loaded::Raw(from_u16(buffer.dimensions(), 3, buffer.into_raw()))
+                }
+                image::DynamicImage::ImageRgba16(buffer) => {
+                    Loaded::Raw(from_u16(buffer.dimensions(), 4, buffer.into_raw()))
+                }
+                image => Loaded::Image(to_image(&image)),
+            }
+        }
+    };
+    if let Loaded::Raw(raw) = &loaded {
+        println!(
+            "{} {:?} {} channels {}",
+            filename,
+            raw.size,
+            raw.channels,
+            raw.depth.name()
+        );
+    }
+    Ok(loaded)
+}
+
+/// Convert a file without the ui, netpbm outputs keep the native values and
+/// others get the image drawn with levels spanning its values.
+pub fn export(input: &str, output: &str, ascii: bool) -> Result<(), String> {
+    let loaded = load_native(input, &Progress::default())?;
+    match (loaded, is_netpbm(output)) {
+        (Loaded::Raw(raw), true) => save_netpbm(&raw, output, ascii),
+        (Loaded::Raw(raw), false) => {
+            let levels = LevelsTool::auto(&raw, raw.range());
+            save_image(&raw.to_image(&levels), output)
+        }
+        (Loaded::Image(image), true) => save_netpbm(&RawImage::from_image(&image), output, ascii),
+        (Loaded::Image(image), false) => save_image(&image, output),
+    }?;
+    println!("exported {} to {}", input, output);
+    Ok(())
+}
+
+/// Text describing the displayed pixel and its native values at the image position,
+/// raw should only be given while the image is still drawn from it unedited
+pub fn describe_pixel(image: &Image, raw: Option<&RawImage>, x: usize, y: usize) -> String {
+    if x >= image.size.0 || y >= image.size.1 {
+        return String::new();
+    }
+    let [r, g, b, a] = image.pixels[y * image.size.0 + x].to_array();
+    let mut text = format!("{}, {}  shown {} {} {} {}", x, y, r, g, b, a);
+    // the native values only line up while the image keeps its size
+    if let Some(raw) = raw.filter(|raw| raw.size == image.size) {
+        let values: Vec<String> = raw
+            .pixel(x, y)
+            .iter()
+            .map(|v| match raw.depth {
+                Depth::F32 => format!("{:.5}", v),
+                _ => format!("{}", v),
+            })
+            .collect();
+        text += &format!("\n{} {}", raw.depth.name(), values.join(" "));
+    }
+    text
+}
+
+/// Levels and colormap controls with a live preview, applying them redraws the
+/// image from the native values.
+pub struct LevelsTool {
+    pub levels: Levels,
+    /// levels the image was last drawn with
+    applied: Levels,
+    /// smallest and largest values of the image, found once when it is loaded
+    range: Option<(f32, f32)>,
+    preview: Option<(Levels, Image)>,
+    /// netpbm file the native values are saved to
+    filename: String,
+    ascii: bool,
+    status: String,
+}
+
+impl Default for LevelsTool {
+    fn default() -> Self {
+        Self {
+            levels: Levels::full(Depth::U16),
+            applied: Levels::full(Depth::U16),
+            range: None,
+            preview: None,
+            filename: "values.pgm".to_string(),
+            ascii: false,
+            status: String::new(),
+        }
+    }
+}
+
+impl LevelsTool {
+    /// Levels spanning the range of values in the image, float rgb is taken to be
+    /// linear light from a render or hdr capture and is tone mapped instead.
+    fn auto(raw: &RawImage, range: Option<(f32, f32)>) -> Levels {
+        let (black, white) = range.unwrap_or((0.0, raw.depth.max_value()));
+        let linear = raw.depth == Depth::F32 && raw.channels >= 3;
+        Levels {
+            tone: if linear {
+                ToneMap::Aces
+            } else {
+                ToneMap::Levels
+            },
+            black,
+            white,
+            gamma: if linear { 2.2 } else { 1.0 },
+            ..Levels::full(raw.depth)
+        }
+    }
+
+    /// Start over with a newly loaded image, returning it drawn with auto levels
+    pub fn reset(&mut self, raw: &RawImage) -> Image {
+        self.range = raw.range();
+        self.levels = Levels {
+            colormap: self.levels.colormap,
+            ..Self::auto(raw, self.range)
+        };
+        self.applied = self.levels.clone();
+        self.preview = None;
+        raw.to_image(&self.levels)
+    }
+
+    /// The image drawn with the levels being adjusted, None if they have been applied
+    pub fn preview(&mut self, raw: &RawImage) -> Option<&Image> {
+        if self.levels == self.applied {
+            return None;
+        }
+        let stale = self
+            .preview
+            .as_ref()
+            .is_none_or(|(levels, _)| *levels != self.levels);
+        if stale {
+            self.preview = Some((self.levels.clone(), raw.to_image(&self.levels)));
+        }
+        self.preview.as_ref().map(|(_, image)| image)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, raw: &RawImage) -> Option<(String, Image)> {
+        let (lo, hi) = self.range.unwrap_or((0.0, raw.depth.max_value()));
+        ui.label(format!(
+            "{}x{} {} channels {}, values {} to {}",
+            raw.size.0,
+            raw.size.1,
+            raw.channels,
+            raw.depth.name(),
+            lo,
+            hi
+        ));
+        let levels = &mut self.levels;
+        ui.horizontal(|ui| {
+            ui.label("tone map");
+            for tone in ToneMap::ALL.iter() {
+                ui.radio_value(&mut levels.tone, *tone, tone.name());
+            }
+        });
+        if levels.tone == ToneMap::Levels {
+            // a little past the values so either end can be pulled out
+            let margin = ((hi - lo) * 0.1).max(f32::EPSILON);
+            let range = (lo - margin)..=(hi + margin);
+            ui.add(egui::Slider::f32(&mut levels.black, range.clone()).text("black"));
+            ui.add(egui::Slider::f32(&mut levels.white, range).text("white"));
+        } else {
+            ui.add(egui::Slider::f32(&mut levels.exposure, -10.0..=10.0).text("exposure"));
+        }
+        ui.add(egui::Slider::f32(&mut levels.gamma, 0.1..=5.0).text("gamma"));
+        if raw.channels <= 2 {
+            egui::combo_box_with_label(ui, "colormap", levels.colormap.name(), |ui| {
+                for colormap in Colormap::ALL.iter() {
+                    ui.selectable_value(&mut levels.colormap, *colormap, colormap.name());
+                }
+            });
+        }
+        let mut applied = None;
+        ui.horizontal(|ui| {
+            if ui.button("auto").clicked {
+                self.levels = Levels {
+                    colormap: self.levels.colormap,
+                    ..Self::auto(raw, self.range)
+                };
+            }
+            if ui.button("full range").clicked {
+                self.levels.tone = ToneMap::Levels;
+                self.levels.black = 0.0;
+                self.levels.white = raw.depth.max_value();
+            }
+            let changed = self.levels != self.applied;
+            if ui.add(egui::Button::new("apply").enabled(changed)).clicked {
+                self.applied = self.levels.clone();
+                let image = match self.preview.take() {
+                    Some((levels, image)) if levels == self.levels => image,
+                    _ => raw.to_image(&self.levels),
+                };
+                applied = Some(("levels".to_string(), image));
+            }
+        });
+        ui.label("applying redraws the image from the loaded values, replacing edits");
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.filename);
+            ui.checkbox(&mut self.ascii, "ascii");
+            if ui.button("save values").clicked {
+                self.status = match save_netpbm(raw, &self.filename, self.ascii) {
+                    Ok(()) => format!("saved {}", self.filename),
+                    Err(why) => why,
+                };
+            }
+        });
+        ui.label("pgm, ppm, pnm or pfm keep the loaded values");
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(depth: Depth, data: Vec<f32>) -> RawImage {
+        RawImage {
+            size: (data.len(), 1),
+            channels: 1,
+            depth,
+            data,
+            maxval: None,
+        }
+    }
+
+    #[test]
+    fn levels_mapping() {
+        let levels = Levels {
+            black: 100.0,
+            white: 300.0,
+            ..Levels::full(Depth::U16)
+        };
+        assert_eq!(levels.normalize(100.0), 0.0);
+        assert_eq!(levels.normalize(200.0), 0.5);
+        assert_eq!(levels.normalize(300.0), 1.0);
+        assert_eq!(levels.normalize(-5.0), 0.0);
+        assert_eq!(levels.normalize(1e9), 1.0);
+
+        // an empty range is a threshold at the white value
+        let threshold = Levels {
+            black: 7.0,
+            white: 7.0,
+            ..levels.clone()
+        };
+        assert_eq!(threshold.normalize(6.9), 0.0);
+        assert_eq!(threshold.normalize(7.0), 1.0);
+
+        let gamma = Levels {
+            gamma: 2.0,
+            ..levels.clone()
+        };
+        assert_eq!(gamma.normalize(150.0), 0.5);
+
+        let tone = |tone, exposure, value| {
+            Levels {
+                tone,
+                exposure,
+                ..Levels::full(Depth::F32)
+            }
+            .normalize(value)
+        };
+        assert_eq!(tone(ToneMap::Exposure, 1.0, 0.25), 0.5);
+        assert_eq!(tone(ToneMap::Exposure, 0.0, 4.0), 1.0);
+        assert_eq!(tone(ToneMap::Reinhard, 0.0, 1.0), 0.5);
+        assert_eq!(tone(ToneMap::Reinhard, 0.0, -1.0), 0.0);
+        assert_eq!(tone(ToneMap::Aces, 0.0, 0.0), 0.0);
+        assert_eq!(tone(ToneMap::Aces, 0.0, 1e6), 1.0);
+    }
+
+    #[test]
+    fn auto_range() {
+        let raw = gray(Depth::U16, vec![1000.0, 3000.0, f32::NAN, 2000.0]);
+        assert_eq!(raw.range(), Some((1000.0, 3000.0)));
+        let mut tool = LevelsTool::default();
+        tool.levels.colormap = Colormap::Inferno;
+        let image = tool.reset(&raw);
+        assert_eq!((tool.levels.black, tool.levels.white), (1000.0, 3000.0));
+        assert_eq!(tool.levels.tone, ToneMap::Levels);
+        // the colormap is kept for the next image
+        assert_eq!(tool.levels.colormap, Colormap::Inferno);
+        let [r, g, b] = INFERNO[0];
+        assert_eq!(image.pixels[0], Color32::from_rgb(r, g, b));
+        let [r, g, b] = INFERNO[8];
+        assert_eq!(image.pixels[1], Color32::from_rgb(r, g, b));
+        assert_eq!(image.pixels[2], Color32::TRANSPARENT);
+        assert!(tool.preview(&raw).is_none());
+
+        // alpha isn't part of the range
+        let with_alpha = RawImage {
+            size: (2, 1),
+            channels: 2,
+            ..gray(Depth::U16, vec![10.0, 65535.0, 20.0, 0.0])
+        };
+        assert_eq!(with_alpha.range(), Some((10.0, 20.0)));
+
+        // float rgb is tone mapped, and without finite values the full range is used
+        let hdr = RawImage {
+            size: (1, 1),
+            channels: 3,
+            ..gray(Depth::F32, vec![f32::INFINITY, f32::NAN, f32::INFINITY])
+        };
+        assert_eq!(hdr.range(), None);
+        let levels = LevelsTool::auto(&hdr, hdr.range());
+        assert_eq!((levels.tone, levels.gamma), (ToneMap::Aces, 2.2));
+        assert_eq!((levels.black, levels.white), (0.0, 1.0));
+    }
+
+    #[test]
+    fn colormap_endpoints() {
+        assert_eq!(Colormap::Gray.map(0.0), [0, 0, 0]);
+        assert_eq!(Colormap::Gray.map(1.0), [255, 255, 255]);
+        assert_eq!(Colormap::Gray.map(0.5), [128, 128, 128]);
+        for (colormap, table) in [
+            (Colormap::Viridis, &VIRIDIS),
+            (Colormap::Inferno, &INFERNO),
+            (Colormap::Turbo, &TURBO),
+        ]
+        .iter()
+        {
+            assert_eq!(colormap.map(0.0), table[0], "{}", colormap.name());
+            assert_eq!(colormap.map(1.0), table[8], "{}", colormap.name());
+            assert_eq!(colormap.map(0.5), table[4], "{}", colormap.name());
+            // outside of 0 to 1 stays at the ends
+            assert_eq!(colormap.map(-1.0), table[0], "{}", colormap.name());
+            assert_eq!(colormap.map(2.0), table[8], "{}", colormap.name());
+        }
+        // halfway between the first two entries
+        assert_eq!(Colormap::Viridis.map(0.0625), [70, 23, 103]);
+    }
+}
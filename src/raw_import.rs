@@ -0,0 +1,552 @@
+/*
+ * Importing headerless frames dumped straight from a sensor: bayer mosaics at
+ * 8 to 16 bits and yuv in the YUYV, NV12 and I420 layouts.
+ *
+ * Nothing in the file says how it is laid out, so the size, row stride, byte
+ * offset and pixel format are given by hand. Bayer samples deeper than 8 bits
+ * are read as little endian 16 bit words and demosaiced bilinearly, yuv is
+ * converted to rgb with the BT.601 video range coefficients.
+ */
+
+use crate::job::{Job, Progress};
+use crate::utility::Image;
+use eframe::egui::{self, Color32};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Colors of the top left 2x2 block of the mosaic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    pub const ALL: [BayerPattern; 4] = [
+        BayerPattern::Rggb,
+        BayerPattern::Bggr,
+        BayerPattern::Grbg,
+        BayerPattern::Gbrg,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BayerPattern::Rggb => "RGGB",
+            BayerPattern::Bggr => "BGGR",
+            BayerPattern::Grbg => "GRBG",
+            BayerPattern::Gbrg => "GBRG",
+        }
+    }
+
+    /// Channel 0 red, 1 green or 2 blue sampled at the pixel
+    fn channel(&self, x: usize, y: usize) -> usize {
+        let block = match self {
+            BayerPattern::Rggb => [[0, 1], [1, 2]],
+            BayerPattern::Bggr => [[2, 1], [1, 0]],
+            BayerPattern::Grbg => [[1, 0], [2, 1]],
+            BayerPattern::Gbrg => [[1, 2], [0, 1]],
+        };
+        block[y % 2][x % 2]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    /// mosaic with the bits per sample, 8 or 10 to 16 in 16 bit words
+    Bayer(BayerPattern, u32),
+    /// packed Y0 U Y1 V for each pair of pixels
+    Yuyv,
+    /// y plane then a half resolution plane of interleaved U V
+    Nv12,
+    /// y plane then half resolution U and V planes
+    I420,
+}
+
+impl PixelFormat {
+    pub fn name(&self) -> String {
+        match self {
+            PixelFormat::Bayer(pattern, bits) => format!("{} {} bit", pattern.name(), bits),
+            PixelFormat::Yuyv => "YUYV".to_string(),
+            PixelFormat::Nv12 => "NV12".to_string(),
+            PixelFormat::I420 => "I420".to_string(),
+        }
+    }
+}
+
+/// How a frame is laid out in a headerless file
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawLayout {
+    pub width: usize,
+    pub height: usize,
+    /// bytes from the start of one row to the next, 0 for rows without padding
+    pub stride: usize,
+    /// bytes to skip at the start of the file
+    pub offset: u64,
+    pub format: PixelFormat,
+}
+
+impl RawLayout {
+    /// Bytes in a row of the first plane
+    pub fn row_bytes(&self) -> usize {
+        let min = match self.format {
+            PixelFormat::Bayer(_, bits) if bits > 8 => self.width * 2,
+            PixelFormat::Bayer(..) => self.width,
+            PixelFormat::Yuyv => self.width.div_ceil(2) * 4,
+            // the interleaved chroma rows have a U V pair for the last odd column too
+            PixelFormat::Nv12 => self.width.div_ceil(2) * 2,
+            PixelFormat::I420 => self.width,
+        };
+        self.stride.max(min)
+    }
+
+    /// Bytes the frame takes up after the offset
+    pub fn frame_bytes(&self) -> usize {
+        let luma = self.row_bytes() * self.height;
+        let chroma_rows = self.height.div_ceil(2);
+        match self.format {
+            // the chroma rows are as long as the luma ones, two half width planes for I420
+            PixelFormat::Nv12 => luma + self.row_bytes() * chroma_rows,
+            PixelFormat::I420 => luma + 2 * self.row_bytes().div_ceil(2) * chroma_rows,
+            _ => luma,
+        }
+    }
+}
+
+/// Bilinear demosaic, each missing color is the mean of the samples of that
+/// color among the 8 neighbors.
+fn demosaic(layout: &RawLayout, pattern: BayerPattern, bits: u32, bytes: &[u8]) -> Image {
+    let (width, height) = (layout.width, layout.height);
+    let stride = layout.row_bytes();
+    let sample = |x: usize, y: usize| -> f32 {
+        let row = &bytes[y * stride..];
+        if bits > 8 {
+            u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]) as f32
+        } else {
+            row[x] as f32
+        }
+    };
+    let scale = 255.0 / ((1u32 << bits) - 1) as f32;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sums = [0.0f32; 3];
+            let mut counts = [0u32; 3];
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let channel = pattern.channel(nx, ny);
+                    // a color sampled at the pixel itself is used as is
+                    if pattern.channel(x, y) == channel && (nx, ny) != (x, y) {
+                        continue;
+                    }
+                    sums[channel] += sample(nx, ny);
+                    counts[channel] += 1;
+                }
+            }
+            let value = |c: usize| {
+                (sums[c] / counts[c].max(1) as f32 * scale)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            };
+            pixels.push(Color32::from_rgb(value(0), value(1), value(2)));
+        }
+    }
+    Image {
+        size: (width, height),
+        pixels,
+    }
+}
+
+/// BT.601 video range yuv to rgb
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> Color32 {
+    let c = (y as f32 - 16.0) * 1.164;
+    let d = u as f32 - 128.0;
+    let e = v as f32 - 128.0;
+    let clamp = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    Color32::from_rgb(
+        clamp(c + 1.596 * e),
+        clamp(c - 0.392 * d - 0.813 * e),
+        clamp(c + 2.017 * d),
+    )
+}
+
+fn convert_yuv(layout: &RawLayout, bytes: &[u8]) -> Image {
+    let (width, height) = (layout.width, layout.height);
+    let stride = layout.row_bytes();
+    let chroma = &bytes[stride * height..];
+    let chroma_stride = stride.div_ceil(2);
+    let chroma_rows = height.div_ceil(2);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (luma, u, v) = match layout.format {
+                PixelFormat::Yuyv => {
+                    let pair = &bytes[y * stride + x / 2 * 4..];
+                    (pair[(x % 2) * 2], pair[1], pair[3])
+                }
+                PixelFormat::Nv12 => {
+                    let uv = &chroma[y / 2 * stride + x / 2 * 2..];
+                    (bytes[y * stride + x], uv[0], uv[1])
+                }
+                _ => {
+                    let ind = y / 2 * chroma_stride + x / 2;
+                    let v_plane = &chroma[chroma_stride * chroma_rows..];
+                    (bytes[y * stride + x], chroma[ind], v_plane[ind])
+                }
+            };
+            pixels.push(yuv_to_rgb(luma, u, v));
+        }
+    }
+    Image {
+        size: (width, height),
+        pixels,
+    }
+}
+
+/// Convert the bytes of a frame, starting after the offset, into rgb
+pub fn decode_raw(bytes: &[u8], layout: &RawLayout) -> Result<Image, String> {
+    if layout.width == 0 || layout.height == 0 {
+        return Err("the width and height have to be set".to_string());
+    }
+    let needed = layout.frame_bytes();
+    if bytes.len() < needed {
+        return Err(format!(
+            "{} bytes are needed for a {}x{} {} frame but there are only {}",
+            needed,
+            layout.width,
+            layout.height,
+            layout.format.name(),
+            bytes.len()
+        ));
+    }
+    match layout.format {
+        PixelFormat::Bayer(_, bits) if !(8..=16).contains(&bits) => {
+            Err(format!("{} bit bayer isn't supported", bits))
+        }
+        PixelFormat::Bayer(pattern, bits) => Ok(demosaic(layout, pattern, bits, bytes)),
+        PixelFormat::Yuyv if !layout.width.is_multiple_of(2) => {
+            Err("YUYV needs an even width".to_string())
+        }
+        _ => Ok(convert_yuv(layout, bytes)),
+    }
+}
+
+/// Read a frame from a headerless file and convert it into rgb
+pub fn import_raw(
+    filename: &str,
+    layout: &RawLayout,
+    progress: &Progress,
+) -> Result<Image, String> {
+    let mut file =
+        File::open(filename).map_err(|why| format!("couldn't open {}: {}", filename, why))?;
+    let read_error = |why: std::io::Error| format!("couldn't read {}: {}", filename, why);
+    file.seek(SeekFrom::Start(layout.offset))
+        .map_err(read_error)?;
+    let needed = layout.frame_bytes();
+    progress.set_total_bytes(needed as u64);
+    let mut bytes = Vec::with_capacity(needed);
+    file.take(needed as u64)
+        .read_to_end(&mut bytes)
+        .map_err(read_error)?;
+    progress.add_bytes(bytes.len() as u64);
+    let image = decode_raw(&bytes, layout).map_err(|why| format!("{}: {}", filename, why))?;
+    println!("{} {:?} {}", filename, image.size, layout.format.name());
+    Ok(image)
+}
+
+fn drag_usize(ui: &mut egui::Ui, value: &mut usize, min: usize, max: usize) {
+    let mut dragged = *value as i32;
+    ui.add(egui::DragValue::i32(&mut dragged).range(min as f32..=max as f32));
+    *value = (dragged.max(0) as usize).clamp(min, max);
+}
+
+/// Dialog to describe the layout of a headerless file and import it
+pub struct RawImportTool {
+    filename: String,
+    /// size of the file, only looked up again when the filename changes
+    file_bytes: Option<(String, Option<u64>)>,
+    layout: RawLayout,
+    importing: Option<Job<Image>>,
+    error: Option<String>,
+}
+
+impl Default for RawImportTool {
+    fn default() -> Self {
+        Self {
+            filename: "frame.raw".to_string(),
+            file_bytes: None,
+            layout: RawLayout {
+                width: 640,
+                height: 480,
+                stride: 0,
+                offset: 0,
+                format: PixelFormat::Bayer(BayerPattern::Rggb, 8),
+            },
+            importing: None,
+            error: None,
+        }
+    }
+}
+
+impl RawImportTool {
+    /// Layout and format controls, returns the filename and image once an import finishes
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<(String, Image)> {
+        let mut imported = None;
+        if let Some(result) = self.importing.as_ref().and_then(|job| job.poll()) {
+            match result {
+                Ok(image) => {
+                    self.error = None;
+                    imported = Some((self.filename.clone(), image));
+                }
+                Err(why) => self.error = Some(why),
+            }
+            self.importing = None;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("file");
+            ui.text_edit_singleline(&mut self.filename);
+        });
+        let filename = &self.filename;
+        if self
+            .file_bytes
+            .as_ref()
+            .is_none_or(|(name, _)| name != filename)
+        {
+            let file_bytes = std::fs::metadata(filename).map(|metadata| metadata.len());
+            self.file_bytes = Some((filename.clone(), file_bytes.ok()));
+        }
+        let file_bytes = self.file_bytes.as_ref().and_then(|(_, bytes)| *bytes);
+        let layout = &mut self.layout;
+        egui::Grid::new("raw_layout").show(ui, |ui| {
+            ui.label("width");
+            drag_usize(ui, &mut layout.width, 1, 16384);
+            ui.end_row();
+            ui.label("height");
+            drag_usize(ui, &mut layout.height, 1, 16384);
+            ui.end_row();
+            ui.label("stride");
+            drag_usize(ui, &mut layout.stride, 0, 65536);
+            ui.end_row();
+            ui.label("offset");
+            let mut offset = layout.offset as f64;
+            ui.add(egui::DragValue::f64(&mut offset).speed(1.0).max_decimals(0));
+            layout.offset = offset.max(0.0) as u64;
+            ui.end_row();
+        });
+
+        let bits = match layout.format {
+            PixelFormat::Bayer(_, bits) => bits,
+            _ => 8,
+        };
+        ui.horizontal(|ui| {
+            ui.label("bayer");
+            for pattern in BayerPattern::ALL.iter() {
+                let format = PixelFormat::Bayer(*pattern, bits);
+                ui.radio_value(&mut layout.format, format, pattern.name());
+            }
+        });
+        if let PixelFormat::Bayer(pattern, bits) = layout.format {
+            ui.horizontal(|ui| {
+                ui.label("bits");
+                for depth in [8, 10, 12, 16].iter() {
+                    let format = PixelFormat::Bayer(pattern, *depth);
+                    ui.radio_value(&mut layout.format, format, depth.to_string());
+                }
+            });
+            if bits > 8 {
+                ui.label("samples are little endian 16 bit words");
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.label("yuv");
+            ui.radio_value(&mut layout.format, PixelFormat::Yuyv, "YUYV");
+            ui.radio_value(&mut layout.format, PixelFormat::Nv12, "NV12");
+            ui.radio_value(&mut layout.format, PixelFormat::I420, "I420");
+        });
+
+        let needed = layout.offset + layout.frame_bytes() as u64;
+        match file_bytes {
+            Some(file_bytes) => ui.label(format!(
+                "{} of the {} bytes in the file are needed",
+                needed, file_bytes
+            )),
+            None => ui.label(format!("{} bytes are needed", needed)),
+        };
+
+        let ready = self.importing.is_none();
+        if ui.add(egui::Button::new("import").enabled(ready)).clicked {
+            // the file may have changed since its size was looked up
+            self.file_bytes = None;
+            let filename = self.filename.clone();
+            let layout = self.layout.clone();
+            self.importing = Some(Job::spawn(
+                format!("importing {}", filename),
+                move |progress| import_raw(&filename, &layout, progress),
+            ));
+        }
+        if let Some(job) = &self.importing {
+            job.ui(ui);
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        imported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(width: usize, height: usize, stride: usize, format: PixelFormat) -> RawLayout {
+        RawLayout {
+            width,
+            height,
+            stride,
+            offset: 0,
+            format,
+        }
+    }
+
+    fn rgb(image: &Image) -> Vec<[u8; 3]> {
+        image.pixels.iter().map(|p| [p.r(), p.g(), p.b()]).collect()
+    }
+
+    #[test]
+    fn frame_bytes() {
+        let bayer = |bits| PixelFormat::Bayer(BayerPattern::Rggb, bits);
+        assert_eq!(layout(640, 480, 0, bayer(8)).frame_bytes(), 640 * 480);
+        assert_eq!(layout(640, 480, 0, bayer(12)).frame_bytes(), 640 * 480 * 2);
+        assert_eq!(layout(640, 480, 1000, bayer(8)).frame_bytes(), 1000 * 480);
+        // a stride shorter than a row is ignored
+        assert_eq!(
+            layout(640, 480, 100, bayer(16)).frame_bytes(),
+            640 * 2 * 480
+        );
+        assert_eq!(layout(5, 2, 0, PixelFormat::Yuyv).row_bytes(), 12);
+        // odd sizes round the chroma up
+        assert_eq!(
+            layout(5, 3, 0, PixelFormat::Nv12).frame_bytes(),
+            6 * 3 + 6 * 2
+        );
+        assert_eq!(
+            layout(5, 3, 0, PixelFormat::I420).frame_bytes(),
+            5 * 3 + 2 * 3 * 2
+        );
+        assert_eq!(
+            layout(4, 2, 8, PixelFormat::I420).frame_bytes(),
+            8 * 2 + 2 * 4
+        );
+    }
+
+    #[test]
+    fn demosaic_rggb_with_stride() {
+        // R G1 / G2 B with two bytes of padding at the end of each row
+        let bytes = [200, 100, 0xEE, 0xEE, 120, 50, 0xEE, 0xEE];
+        let pattern = BayerPattern::Rggb;
+        let layout = layout(2, 2, 4, PixelFormat::Bayer(pattern, 8));
+        let image = decode_raw(&bytes, &layout).unwrap();
+        assert_eq!(
+            rgb(&image),
+            vec![
+                [200, 110, 50],
+                [200, 100, 50],
+                [200, 120, 50],
+                [200, 110, 50]
+            ]
+        );
+    }
+
+    #[test]
+    fn demosaic_bggr() {
+        let bytes = [200, 100, 120, 50];
+        let pattern = BayerPattern::Bggr;
+        let image = decode_raw(&bytes, &layout(2, 2, 0, PixelFormat::Bayer(pattern, 8))).unwrap();
+        assert_eq!(
+            rgb(&image),
+            vec![
+                [50, 110, 200],
+                [50, 100, 200],
+                [50, 120, 200],
+                [50, 110, 200]
+            ]
+        );
+    }
+
+    #[test]
+    fn demosaic_16_bit_words() {
+        // 12 bit little endian samples, full scale is white
+        let bytes: Vec<u8> = (0..4)
+            .flat_map(|_| 4095u16.to_le_bytes().to_vec())
+            .collect();
+        let pattern = BayerPattern::Grbg;
+        let image = decode_raw(&bytes, &layout(2, 2, 0, PixelFormat::Bayer(pattern, 12))).unwrap();
+        assert_eq!(rgb(&image), vec![[255, 255, 255]; 4]);
+    }
+
+    /// Every pixel of a 3x3 frame with its luma and the chroma of its 2x2 block
+    fn expected_yuv(luma: &[u8], u: &[u8], v: &[u8]) -> Vec<Color32> {
+        let mut pixels = Vec::new();
+        for y in 0..3 {
+            for x in 0..3 {
+                let block = y / 2 * 2 + x / 2;
+                pixels.push(yuv_to_rgb(luma[y * 3 + x], u[block], v[block]));
+            }
+        }
+        pixels
+    }
+
+    const LUMA: [u8; 9] = [16, 60, 100, 140, 180, 235, 30, 90, 200];
+    const U: [u8; 4] = [128, 90, 200, 60];
+    const V: [u8; 4] = [128, 240, 70, 160];
+
+    #[test]
+    fn nv12_odd_width() {
+        // luma rows padded to the 4 bytes of the interleaved chroma rows
+        let pad = 0xEE;
+        let mut bytes = Vec::new();
+        for row in LUMA.chunks(3) {
+            bytes.extend_from_slice(row);
+            bytes.push(pad);
+        }
+        for block_row in 0..2 {
+            for block in 0..2 {
+                bytes.push(U[block_row * 2 + block]);
+                bytes.push(V[block_row * 2 + block]);
+            }
+        }
+        let layout = layout(3, 3, 0, PixelFormat::Nv12);
+        assert_eq!(layout.frame_bytes(), bytes.len());
+        let image = decode_raw(&bytes, &layout).unwrap();
+        assert_eq!(image.pixels, expected_yuv(&LUMA, &U, &V));
+    }
+
+    #[test]
+    fn i420_padded_stride() {
+        // rows of 6 bytes, chroma rows of 3
+        let pad = 0xEE;
+        let mut bytes = Vec::new();
+        for row in LUMA.chunks(3) {
+            bytes.extend_from_slice(row);
+            bytes.extend_from_slice(&[pad; 3]);
+        }
+        for plane in [U, V].iter() {
+            for block_row in plane.chunks(2) {
+                bytes.extend_from_slice(block_row);
+                bytes.push(pad);
+            }
+        }
+        let layout = layout(3, 3, 6, PixelFormat::I420);
+        assert_eq!(layout.frame_bytes(), bytes.len());
+        let image = decode_raw(&bytes, &layout).unwrap();
+        assert_eq!(image.pixels, expected_yuv(&LUMA, &U, &V));
+    }
+
+    #[test]
+    fn yuv_range() {
+        assert_eq!(yuv_to_rgb(16, 128, 128), Color32::from_rgb(0, 0, 0));
+        assert_eq!(yuv_to_rgb(235, 128, 128), Color32::from_rgb(255, 255, 255));
+    }
+}
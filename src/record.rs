@@ -0,0 +1,364 @@
+/*
+ * Recording what is shown into an animated gif.
+ *
+ * While recording, the rendered image is captured at most at the gif frame rate
+ * into memory along with when it was captured, so each frame is shown for as long
+ * as it was on screen even when frames come slower than that. When recording stops the frames are quantized and written out
+ * on a background thread. Gif has no partial transparency, so translucent
+ * pixels end up composited over black.
+ */
+
+use crate::geometry::Filter;
+use crate::image_io::AnimationFrame;
+use crate::job::{Job, Progress};
+use crate::utility::Image;
+use color_quant::NeuQuant;
+use eframe::egui;
+use std::fs::File;
+use std::io::BufWriter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GifPalette {
+    /// one palette for the whole animation, colors don't shift from frame to frame
+    Global,
+    /// a palette for each frame, better for frames with very different colors
+    PerFrame,
+}
+
+#[derive(Clone, Debug)]
+pub struct GifOptions {
+    /// most frames captured per second
+    pub fps: f32,
+    /// size of the gif relative to the captured frames
+    pub scale: f32,
+    pub palette: GifPalette,
+    /// colors in each palette, up to 256
+    pub colors: usize,
+    /// quantizer sampling from 1 (best) to 30 (fastest)
+    pub speed: i32,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            fps: 10.0,
+            scale: 1.0,
+            palette: GifPalette::Global,
+            colors: 256,
+            speed: 10,
+        }
+    }
+}
+
+/// Rgba bytes of the premultiplied pixels made opaque, which puts them over black
+fn rgba_bytes(image: &Image) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(image.pixels.len() * 4);
+    for pixel in image.pixels.iter() {
+        let [r, g, b, _] = pixel.to_array();
+        bytes.extend_from_slice(&[r, g, b, 255]);
+    }
+    bytes
+}
+
+/// Write the frames as an animated gif that loops forever, each shown for its delay,
+/// frames that aren't the size of the first are resized to it.
+pub fn write_gif(
+    frames: &[AnimationFrame],
+    filename: &str,
+    options: &GifOptions,
+    progress: &Progress,
+) -> Result<(), String> {
+    let first = &frames.first().ok_or("no frames to write")?.image;
+    let scale = options.scale.max(0.01);
+    let width = ((first.size.0 as f32 * scale).round() as usize).max(1);
+    let height = ((first.size.1 as f32 * scale).round() as usize).max(1);
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(format!("{}x{} is too large for a gif", width, height));
+    }
+    let filter = if scale < 1.0 {
+        Filter::Bilinear
+    } else {
+        Filter::Nearest
+    };
+    let resized = |image: &Image| {
+        if image.size == (width, height) {
+            rgba_bytes(image)
+        } else {
+            rgba_bytes(&image.resize(width, height, filter))
+        }
+    };
+    let colors = options.colors.clamp(4, 256);
+    let speed = options.speed.clamp(1, 30);
+
+    let global = match options.palette {
+        GifPalette::Global => {
+            // train on an even sampling of pixels from every frame
+            let per_frame = (1 << 20) / frames.len().max(1);
+            let mut samples = Vec::new();
+            for frame in frames.iter() {
+                let bytes = resized(&frame.image);
+                let step = (bytes.len() / 4 / per_frame.max(1)).max(1);
+                for pixel in bytes.chunks(4).step_by(step) {
+                    samples.extend_from_slice(pixel);
+                }
+            }
+            Some(NeuQuant::new(speed, colors, &samples))
+        }
+        GifPalette::PerFrame => None,
+    };
+    let global_palette = global
+        .as_ref()
+        .map(|quant| quant.color_map_rgb())
+        .unwrap_or_default();
+
+    let file =
+        File::create(filename).map_err(|why| format!("couldn't create {}: {}", filename, why))?;
+    let write_error = |why: gif::EncodingError| format!("couldn't write {}: {}", filename, why);
+    let mut encoder = gif::Encoder::new(
+        BufWriter::new(file),
+        width as u16,
+        height as u16,
+        &global_palette,
+    )
+    .map_err(write_error)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(write_error)?;
+    // gif delays are in hundredths of a second, they are rounded from the total time
+    // so the rounding doesn't add up over many frames
+    let mut elapsed = 0.0;
+    let mut written = 0;
+    for (ind, animation_frame) in frames.iter().enumerate() {
+        if progress.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        let bytes = resized(&animation_frame.image);
+        let mut frame = match &global {
+            Some(quant) => {
+                let indices: Vec<u8> = bytes.chunks(4).map(|p| quant.index_of(p) as u8).collect();
+                gif::Frame::from_indexed_pixels(width as u16, height as u16, &indices, None)
+            }
+            None => {
+                let quant = NeuQuant::new(speed, colors, &bytes);
+                let indices: Vec<u8> = bytes.chunks(4).map(|p| quant.index_of(p) as u8).collect();
+                gif::Frame::from_palette_pixels(
+                    width as u16,
+                    height as u16,
+                    &indices,
+                    &quant.color_map_rgb(),
+                    None,
+                )
+            }
+        };
+        elapsed += animation_frame.delay.max(0.0);
+        let delay = ((elapsed * 100.0).round() as i64 - written).clamp(1, u16::MAX as i64);
+        written += delay;
+        frame.delay = delay as u16;
+        encoder.write_frame(&frame).map_err(write_error)?;
+        progress.set_rows(ind as u64 + 1);
+    }
+    Ok(())
+}
+
+/// Record toggle that captures the rendered images and writes them as a gif when stopped
+pub struct Recorder {
+    recording: bool,
+    frames: Vec<Image>,
+    /// time each frame was captured
+    times: Vec<f64>,
+    /// bytes of the captured frames
+    bytes: usize,
+    /// recording stops once the captured frames would take more bytes than this
+    pub max_bytes: usize,
+    options: GifOptions,
+    filename: String,
+    saving: Option<Job<()>>,
+    status: String,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            recording: false,
+            frames: Vec::new(),
+            times: Vec::new(),
+            bytes: 0,
+            max_bytes: 1024 * 1024 * 1024,
+            options: Default::default(),
+            filename: "recording.gif".to_string(),
+            saving: None,
+            status: String::new(),
+        }
+    }
+}
+
+impl Recorder {
+    /// Keep a copy of the image if recording and a frame is due, time is in seconds
+    pub fn capture(&mut self, image: &Image, time: f64) {
+        if !self.recording {
+            return;
+        }
+        let min_interval = 1.0 / self.options.fps as f64;
+        if self
+            .times
+            .last()
+            .is_some_and(|last| time - last < min_interval)
+        {
+            return;
+        }
+        let bytes = image.pixels.len() * 4;
+        if self.bytes + bytes > self.max_bytes {
+            self.stop();
+            self.status = format!(
+                "stopped at the {} MB memory cap, {}",
+                self.max_bytes / (1024 * 1024),
+                self.status
+            );
+            return;
+        }
+        self.frames.push(image.clone());
+        self.times.push(time);
+        self.bytes += bytes;
+    }
+
+    fn stop(&mut self) {
+        self.recording = false;
+        let images = std::mem::take(&mut self.frames);
+        let times = std::mem::take(&mut self.times);
+        self.bytes = 0;
+        if images.is_empty() {
+            self.status = "nothing was recorded".to_string();
+            return;
+        }
+        // each frame is shown until the next was captured, the last for the shortest interval
+        let last_delay = 1.0 / self.options.fps.max(0.1) as f64;
+        let frames: Vec<AnimationFrame> = images
+            .into_iter()
+            .enumerate()
+            .map(|(ind, image)| AnimationFrame {
+                image,
+                delay: times
+                    .get(ind + 1)
+                    .map_or(last_delay, |next| next - times[ind]),
+            })
+            .collect();
+        let filename = self.filename.clone();
+        let options = self.options.clone();
+        self.status = format!("writing {} frames to {}", frames.len(), filename);
+        self.saving = Some(Job::spawn(
+            format!("writing {}", filename),
+            move |progress| write_gif(&frames, &filename, &options, progress),
+        ));
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if let Some(result) = self.saving.as_ref().and_then(|job| job.poll()) {
+            self.status = match result {
+                Ok(()) => format!("saved {}", self.filename),
+                Err(why) => why,
+            };
+            self.saving = None;
+        }
+
+        ui.horizontal(|ui| {
+            if self.saving.is_some() && !self.recording {
+                // a second writer could write over the same file, and its result would be lost
+                ui.add(egui::Button::new("record").enabled(false));
+                ui.label(&self.filename);
+                return;
+            }
+            let text = if self.recording { "stop" } else { "record" };
+            if ui.selectable_label(self.recording, text).clicked {
+                if self.recording {
+                    self.stop();
+                } else {
+                    self.recording = true;
+                    self.status.clear();
+                }
+            }
+            ui.text_edit_singleline(&mut self.filename);
+        });
+        if self.recording {
+            ui.label(format!(
+                "{} frames, {:.1} MB",
+                self.frames.len(),
+                self.bytes as f64 / (1024.0 * 1024.0)
+            ));
+            ui.ctx().request_repaint();
+        }
+        let mut max_mb = self.max_bytes / (1024 * 1024);
+        ui.add(egui::Slider::usize(&mut max_mb, 16..=8192).text("memory cap MB"));
+        self.max_bytes = max_mb * 1024 * 1024;
+        let options = &mut self.options;
+        ui.add(egui::Slider::f32(&mut options.fps, 1.0..=50.0).text("fps"));
+        ui.add(egui::Slider::f32(&mut options.scale, 0.1..=4.0).text("scale"));
+        ui.horizontal(|ui| {
+            ui.label("palette");
+            ui.radio_value(&mut options.palette, GifPalette::Global, "global");
+            ui.radio_value(&mut options.palette, GifPalette::PerFrame, "per frame");
+        });
+        ui.add(egui::Slider::usize(&mut options.colors, 4..=256).text("colors"));
+        ui.add(egui::Slider::i32(&mut options.speed, 1..=30).text("quantize speed"));
+        if let Some(job) = &self.saving {
+            job.ui(ui);
+        }
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::egui::Color32;
+
+    fn frame(color: Color32, delay: f64) -> AnimationFrame {
+        AnimationFrame {
+            image: Image {
+                size: (4, 2),
+                pixels: vec![color; 8],
+            },
+            delay,
+        }
+    }
+
+    /// Delays and whether each frame has its own palette
+    fn read_back(filename: &str) -> (bool, Vec<(u16, bool)>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(filename).unwrap()).unwrap();
+        let global = decoder
+            .global_palette()
+            .is_some_and(|palette| !palette.is_empty());
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.palette.is_some()));
+        }
+        (global, frames)
+    }
+
+    #[test]
+    fn gif_delays_and_palettes() {
+        let filename = std::env::temp_dir()
+            .join(format!("egui_image_record_{}.gif", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        // a third of a tenth of a second each, rounding each on its own would give 3 3 3
+        let frames = vec![
+            frame(Color32::RED, 0.033),
+            frame(Color32::GREEN, 0.033),
+            frame(Color32::BLUE, 0.034),
+        ];
+        let mut options = GifOptions::default();
+        write_gif(&frames, &filename, &options, &Progress::default()).unwrap();
+        let global = read_back(&filename);
+        options.palette = GifPalette::PerFrame;
+        write_gif(&frames, &filename, &options, &Progress::default()).unwrap();
+        let per_frame = read_back(&filename);
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(global, (true, vec![(3, false), (4, false), (3, false)]));
+        assert_eq!(per_frame.1, vec![(3, true), (4, true), (3, true)]);
+    }
+}
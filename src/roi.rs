@@ -0,0 +1,586 @@
+/*
+ * Region of interest selection with per channel statistics, and a ruler for
+ * measuring between two points.
+ *
+ * Regions are in image pixel coordinates and a pixel is inside when its center
+ * is, the same as fill_polygon in draw.rs. The statistics are of the channel
+ * values as stored, which for translucent pixels are premultiplied by alpha.
+ */
+
+use crate::utility::Image;
+use eframe::egui::{self, Color32, Pos2, Rect, Vec2};
+
+pub const HISTOGRAM_BINS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Roi {
+    Rect {
+        min: [f32; 2],
+        max: [f32; 2],
+    },
+    /// the ellipse inscribed in the rectangle from min to max
+    Ellipse {
+        min: [f32; 2],
+        max: [f32; 2],
+    },
+    Polygon {
+        points: Vec<[f32; 2]>,
+    },
+}
+
+impl Roi {
+    /// Smallest rectangle containing the region as min and max corners
+    pub fn bounds(&self) -> ([f32; 2], [f32; 2]) {
+        match self {
+            Roi::Rect { min, max } | Roi::Ellipse { min, max } => (*min, *max),
+            Roi::Polygon { points } => points.iter().fold(
+                ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]),
+                |(min, max), p| {
+                    (
+                        [min[0].min(p[0]), min[1].min(p[1])],
+                        [max[0].max(p[0]), max[1].max(p[1])],
+                    )
+                },
+            ),
+        }
+    }
+
+    pub fn contains(&self, pos: [f32; 2]) -> bool {
+        match self {
+            Roi::Rect { min, max } => {
+                pos[0] >= min[0] && pos[0] < max[0] && pos[1] >= min[1] && pos[1] < max[1]
+            }
+            Roi::Ellipse { min, max } => {
+                let rx = (max[0] - min[0]) * 0.5;
+                let ry = (max[1] - min[1]) * 0.5;
+                if rx <= 0.0 || ry <= 0.0 {
+                    return false;
+                }
+                let dx = (pos[0] - (min[0] + rx)) / rx;
+                let dy = (pos[1] - (min[1] + ry)) / ry;
+                dx * dx + dy * dy <= 1.0
+            }
+            Roi::Polygon { points } => {
+                // even-odd rule
+                let mut inside = false;
+                for (ind, p0) in points.iter().enumerate() {
+                    let p1 = points[(ind + 1) % points.len()];
+                    if (p0[1] <= pos[1]) != (p1[1] <= pos[1]) {
+                        let x = p0[0] + (pos[1] - p0[1]) / (p1[1] - p0[1]) * (p1[0] - p0[0]);
+                        if pos[0] < x {
+                            inside = !inside;
+                        }
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    /// Points around the outline in image coordinates
+    fn outline(&self) -> Vec<[f32; 2]> {
+        match self {
+            Roi::Rect { min, max } => vec![*min, [max[0], min[1]], *max, [min[0], max[1]]],
+            Roi::Ellipse { min, max } => {
+                let center = [(min[0] + max[0]) * 0.5, (min[1] + max[1]) * 0.5];
+                let radii = [(max[0] - min[0]) * 0.5, (max[1] - min[1]) * 0.5];
+                (0..48)
+                    .map(|ind| {
+                        let angle = ind as f32 / 48.0 * std::f32::consts::TAU;
+                        [
+                            center[0] + radii[0] * angle.cos(),
+                            center[1] + radii[1] * angle.sin(),
+                        ]
+                    })
+                    .collect()
+            }
+            Roi::Polygon { points } => points.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Roi::Rect { .. } => "rectangle",
+            Roi::Ellipse { .. } => "ellipse",
+            Roi::Polygon { .. } => "polygon",
+        }
+    }
+}
+
+/// Statistics of the red, green, blue and alpha channels of the pixels in a region
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoiStats {
+    pub count: usize,
+    pub min: [u8; 4],
+    pub max: [u8; 4],
+    pub mean: [f64; 4],
+    pub stddev: [f64; 4],
+    pub histogram: [[u32; HISTOGRAM_BINS]; 4],
+}
+
+impl Image {
+    /// Statistics of the pixels with centers inside the region, None if there are none
+    pub fn roi_stats(&self, roi: &Roi) -> Option<RoiStats> {
+        let (width, height) = self.size;
+        let (min, max) = roi.bounds();
+        let x0 = (min[0].floor().max(0.0) as usize).min(width);
+        let x1 = (max[0].ceil().max(0.0) as usize).min(width);
+        let y0 = (min[1].floor().max(0.0) as usize).min(height);
+        let y1 = (max[1].ceil().max(0.0) as usize).min(height);
+
+        let mut count = 0;
+        let mut sum = [0.0f64; 4];
+        let mut sum_sq = [0.0f64; 4];
+        let mut lo = [u8::MAX; 4];
+        let mut hi = [0u8; 4];
+        let mut histogram = [[0u32; HISTOGRAM_BINS]; 4];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if !roi.contains([x as f32 + 0.5, y as f32 + 0.5]) {
+                    continue;
+                }
+                count += 1;
+                let rgba = self.pixels[y * width + x].to_array();
+                for ch in 0..4 {
+                    let v = rgba[ch];
+                    sum[ch] += v as f64;
+                    sum_sq[ch] += v as f64 * v as f64;
+                    lo[ch] = lo[ch].min(v);
+                    hi[ch] = hi[ch].max(v);
+                    histogram[ch][v as usize * HISTOGRAM_BINS / 256] += 1;
+                }
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+        let n = count as f64;
+        let mean = [sum[0] / n, sum[1] / n, sum[2] / n, sum[3] / n];
+        let stddev = |ch: usize| (sum_sq[ch] / n - mean[ch] * mean[ch]).max(0.0).sqrt();
+        Some(RoiStats {
+            count,
+            min: lo,
+            max: hi,
+            mean,
+            stddev: [stddev(0), stddev(1), stddev(2), stddev(3)],
+            histogram,
+        })
+    }
+}
+
+/// Distance in pixels and angle in degrees from p0 to p1, the angle is counter
+/// clockwise from the x axis as seen on screen, with y pointing down in the image.
+pub fn measure(p0: [f32; 2], p1: [f32; 2]) -> (f32, f32) {
+    let dx = p1[0] - p0[0];
+    let dy = p1[1] - p0[1];
+    (dx.hypot(dy), (-dy).atan2(dx).to_degrees())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoiMode {
+    Rect,
+    Ellipse,
+    Polygon,
+    Ruler,
+}
+
+const CHANNEL_COLORS: [Color32; 4] = [
+    Color32::RED,
+    Color32::GREEN,
+    Color32::from_rgb(80, 120, 255),
+    Color32::GRAY,
+];
+
+pub struct RoiTool {
+    pub enabled: bool,
+    pub mode: RoiMode,
+    pub roi: Option<Roi>,
+    /// end points of the ruler
+    pub ruler: Option<[[f32; 2]; 2]>,
+    /// physical size of a pixel, in unit
+    pixel_size: f32,
+    unit: String,
+    /// image position where the current drag started
+    drag_start: Option<[f32; 2]>,
+    /// vertices of the polygon being drawn
+    polygon: Vec<[f32; 2]>,
+    /// the image generation and region the statistics were taken from
+    stats_key: Option<(u64, Roi)>,
+    stats: Option<RoiStats>,
+}
+
+impl Default for RoiTool {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: RoiMode::Rect,
+            roi: None,
+            ruler: None,
+            pixel_size: 1.0,
+            unit: "mm".to_string(),
+            drag_start: None,
+            polygon: Vec::new(),
+            stats_key: None,
+            stats: None,
+        }
+    }
+}
+
+impl RoiTool {
+    /// Forget the region, ruler and polygon in progress, e.g. when another image is opened
+    pub fn reset(&mut self) {
+        self.roi = None;
+        self.ruler = None;
+        self.drag_start = None;
+        self.polygon.clear();
+        self.stats_key = None;
+        self.stats = None;
+    }
+
+    /// Statistics of the region, only taken again when the image generation or the
+    /// region changes
+    fn stats(&mut self, image: &Image, generation: u64) -> Option<&RoiStats> {
+        let roi = self.roi.as_ref()?;
+        let fresh = match &self.stats_key {
+            Some((key_generation, key_roi)) => *key_generation == generation && key_roi == roi,
+            None => false,
+        };
+        if !fresh {
+            self.stats = image.roi_stats(roi);
+            self.stats_key = Some((generation, roi.clone()));
+        }
+        self.stats.as_ref()
+    }
+
+    /// Mode and unit controls, the statistics of the region and the ruler measurement,
+    /// the generation changes whenever the image does
+    pub fn ui_control(&mut self, ui: &mut egui::Ui, image: &Image, generation: u64) {
+        ui.checkbox(&mut self.enabled, "select");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, RoiMode::Rect, "rectangle");
+            ui.radio_value(&mut self.mode, RoiMode::Ellipse, "ellipse");
+            ui.radio_value(&mut self.mode, RoiMode::Polygon, "polygon");
+            ui.radio_value(&mut self.mode, RoiMode::Ruler, "ruler");
+        });
+        if self.mode == RoiMode::Polygon {
+            ui.horizontal(|ui| {
+                ui.label(format!("{} points", self.polygon.len()));
+                if ui.button("finish").clicked {
+                    self.finish_polygon();
+                }
+                if ui.button("cancel").clicked {
+                    self.polygon.clear();
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::f32(&mut self.pixel_size)
+                    .speed(0.01)
+                    .range(0.000_001..=1e6)
+                    .prefix("pixel size "),
+            );
+            ui.text_edit_singleline(&mut self.unit);
+        });
+
+        ui.separator();
+        let name = self.roi.as_ref().map(Roi::name);
+        let (pixel_size, unit) = (self.pixel_size, self.unit.clone());
+        let mut clear = false;
+        match name.map(|name| (name, self.stats(image, generation))) {
+            None => {
+                ui.label("no region selected");
+            }
+            Some((name, None)) => {
+                ui.label(format!("{} has no pixels", name));
+            }
+            Some((name, Some(stats))) => {
+                let area = stats.count as f32 * pixel_size * pixel_size;
+                ui.label(format!(
+                    "{} {} pixels, {:.4} {}^2",
+                    name, stats.count, area, unit
+                ));
+                egui::Grid::new("roi_stats").show(ui, |ui| {
+                    ui.label("");
+                    for heading in ["min", "max", "mean", "stddev"].iter() {
+                        ui.label(*heading);
+                    }
+                    ui.end_row();
+                    for (ch, name) in ["red", "green", "blue", "alpha"].iter().enumerate() {
+                        ui.colored_label(CHANNEL_COLORS[ch], *name);
+                        ui.label(format!("{}", stats.min[ch]));
+                        ui.label(format!("{}", stats.max[ch]));
+                        ui.label(format!("{:.2}", stats.mean[ch]));
+                        ui.label(format!("{:.2}", stats.stddev[ch]));
+                        ui.end_row();
+                    }
+                });
+                histogram_ui(ui, stats);
+                clear = ui.button("clear").clicked;
+            }
+        }
+        if clear {
+            self.roi = None;
+        }
+
+        if let Some([p0, p1]) = self.ruler {
+            ui.separator();
+            let (dist, angle) = measure(p0, p1);
+            ui.label(format!(
+                "ruler {:.2} px, {:.4} {}, {:.1} degrees",
+                dist,
+                dist * self.pixel_size,
+                self.unit,
+                angle
+            ));
+        }
+    }
+
+    fn finish_polygon(&mut self) {
+        if self.polygon.len() >= 3 {
+            let points = std::mem::take(&mut self.polygon);
+            self.roi = Some(Roi::Polygon { points });
+        }
+    }
+
+    /// Handle the mouse over the image and draw the region and ruler on top of it,
+    /// rect is where the image is on screen and scale the screen points per image pixel.
+    pub fn ui_content(
+        &mut self,
+        ui: &mut egui::Ui,
+        response: &egui::Response,
+        rect: Rect,
+        scale: Vec2,
+    ) {
+        let to_image = |pos: Pos2| {
+            let p = pos - rect.min;
+            [p.x / scale.x, p.y / scale.y]
+        };
+        let to_screen = |p: [f32; 2]| rect.min + Vec2::new(p[0] * scale.x, p[1] * scale.y);
+
+        if self.enabled {
+            let input = ui.input();
+            let mouse = input.mouse.pos.map(to_image);
+            match self.mode {
+                RoiMode::Rect | RoiMode::Ellipse => {
+                    if input.mouse.pressed && response.hovered {
+                        self.drag_start = mouse;
+                    }
+                    if let (Some(start), Some(end)) = (self.drag_start, mouse) {
+                        let min = [start[0].min(end[0]), start[1].min(end[1])];
+                        let max = [start[0].max(end[0]), start[1].max(end[1])];
+                        self.roi = Some(if self.mode == RoiMode::Rect {
+                            Roi::Rect { min, max }
+                        } else {
+                            Roi::Ellipse { min, max }
+                        });
+                    }
+                }
+                RoiMode::Polygon => {
+                    let tolerance = 6.0 / scale.x.min(scale.y);
+                    if response.double_clicked {
+                        self.finish_polygon();
+                    } else if let (true, Some(pos)) = (response.clicked, mouse) {
+                        // the first click of a double click has already been added
+                        let repeated = self.polygon.last().is_some_and(|last| {
+                            (pos[0] - last[0]).hypot(pos[1] - last[1]) <= tolerance
+                        });
+                        if !repeated {
+                            self.polygon.push(pos);
+                        }
+                    }
+                    if input.key_pressed(egui::Key::Enter) {
+                        self.finish_polygon();
+                    }
+                    if input.key_pressed(egui::Key::Escape) {
+                        self.polygon.clear();
+                    }
+                }
+                RoiMode::Ruler => {
+                    if input.mouse.pressed && response.hovered {
+                        self.drag_start = mouse;
+                    }
+                    if let (Some(start), Some(end)) = (self.drag_start, mouse) {
+                        self.ruler = Some([start, end]);
+                    }
+                }
+            }
+            if !input.mouse.down {
+                self.drag_start = None;
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        let stroke = (1.5, Color32::YELLOW);
+        if let Some(roi) = &self.roi {
+            let outline = roi.outline();
+            for (p0, p1) in outline.iter().zip(outline.iter().cycle().skip(1)) {
+                painter.line_segment([to_screen(*p0), to_screen(*p1)], stroke);
+            }
+        }
+        for pair in self.polygon.windows(2) {
+            painter.line_segment([to_screen(pair[0]), to_screen(pair[1])], stroke);
+        }
+        for p in self.polygon.iter() {
+            painter.circle_filled(to_screen(*p), 2.0, Color32::YELLOW);
+        }
+        if let Some([p0, p1]) = self.ruler {
+            let (s0, s1) = (to_screen(p0), to_screen(p1));
+            let color = Color32::from_rgb(0, 255, 255);
+            painter.line_segment([s0, s1], (1.5, color));
+            painter.circle_filled(s0, 3.0, color);
+            painter.circle_filled(s1, 3.0, color);
+            let (dist, angle) = measure(p0, p1);
+            painter.text(
+                s1 + Vec2::new(6.0, 0.0),
+                egui::Align2::LEFT_CENTER,
+                format!("{:.1} px {:.1} deg", dist, angle),
+                egui::TextStyle::Small,
+                color,
+            );
+        }
+    }
+}
+
+/// Small histogram of the color channels, each scaled to its tallest bin
+fn histogram_ui(ui: &mut egui::Ui, stats: &RoiStats) {
+    let size = Vec2::new(HISTOGRAM_BINS as f32 * 4.0, 64.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+    // alpha is left out, it is usually all opaque and would hide the others
+    for (bins, color) in stats.histogram.iter().zip(CHANNEL_COLORS.iter()).take(3) {
+        let tallest = *bins.iter().max().unwrap_or(&1) as f32;
+        if tallest == 0.0 {
+            continue;
+        }
+        let point = |ind: usize| {
+            let x = rect.left() + (ind as f32 + 0.5) * size.x / HISTOGRAM_BINS as f32;
+            let y = rect.bottom() - bins[ind] as f32 / tallest * (size.y - 2.0);
+            Pos2::new(x, y)
+        };
+        for ind in 1..HISTOGRAM_BINS {
+            painter.line_segment([point(ind - 1), point(ind)], (1.0, *color));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4x2 gray image with values 0, 40, ... 280 clamped to 255
+    fn ramp() -> Image {
+        Image {
+            size: (4, 2),
+            pixels: (0..8)
+                .map(|v| Color32::from_gray((v * 40).min(255) as u8))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn rect_stats() {
+        let image = ramp();
+        let roi = Roi::Rect {
+            min: [1.0, 0.0],
+            max: [3.0, 2.0],
+        };
+        let stats = image.roi_stats(&roi).unwrap();
+        // values 40, 80, 200 and 240
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.min, [40, 40, 40, 255]);
+        assert_eq!(stats.max, [240, 240, 240, 255]);
+        assert_eq!(stats.mean, [140.0, 140.0, 140.0, 255.0]);
+        assert!((stats.stddev[0] - 82.46211251235321).abs() < 1e-9);
+        assert_eq!(stats.stddev[3], 0.0);
+        assert_eq!(stats.histogram[0][40 * HISTOGRAM_BINS / 256], 1);
+        assert_eq!(stats.histogram[3][HISTOGRAM_BINS - 1], 4);
+        assert_eq!(stats.histogram[1].iter().sum::<u32>(), 4);
+
+        // pixels are in when their centers are, and the region is clipped to the image
+        let roi = Roi::Rect {
+            min: [-5.0, 0.6],
+            max: [2.0, 9.0],
+        };
+        let stats = image.roi_stats(&roi).unwrap();
+        assert_eq!((stats.count, stats.min[0], stats.max[0]), (2, 160, 200));
+    }
+
+    #[test]
+    fn empty_regions() {
+        let image = ramp();
+        let outside = Roi::Rect {
+            min: [5.0, 5.0],
+            max: [9.0, 9.0],
+        };
+        assert_eq!(image.roi_stats(&outside), None);
+        let between = Roi::Rect {
+            min: [0.6, 0.0],
+            max: [1.4, 2.0],
+        };
+        assert_eq!(image.roi_stats(&between), None);
+        let flat = Roi::Ellipse {
+            min: [0.0, 1.0],
+            max: [4.0, 1.0],
+        };
+        assert_eq!(image.roi_stats(&flat), None);
+    }
+
+    #[test]
+    fn shapes() {
+        let image = Image {
+            size: (10, 10),
+            pixels: vec![Color32::WHITE; 100],
+        };
+        let ellipse = Roi::Ellipse {
+            min: [0.0, 0.0],
+            max: [10.0, 10.0],
+        };
+        // close to the area of the circle, pi * 25
+        let count = image.roi_stats(&ellipse).unwrap().count;
+        assert!((72..=84).contains(&count), "{}", count);
+        assert!(ellipse.contains([5.0, 5.0]) && !ellipse.contains([0.5, 0.5]));
+
+        // half of the square below the diagonal, the pixels on it are left out
+        let triangle = Roi::Polygon {
+            points: vec![[0.0, 0.0], [10.0, 10.0], [0.0, 10.0]],
+        };
+        assert_eq!(image.roi_stats(&triangle).unwrap().count, 45);
+        assert_eq!(triangle.bounds(), ([0.0, 0.0], [10.0, 10.0]));
+        assert_eq!(triangle.name(), "polygon");
+    }
+
+    #[test]
+    fn measuring() {
+        assert_eq!(measure([1.0, 1.0], [1.0, 1.0]), (0.0, 0.0));
+        let (dist, angle) = measure([0.0, 0.0], [3.0, -4.0]);
+        assert_eq!(dist, 5.0);
+        assert!((angle - 53.130_1).abs() < 1e-3);
+        assert_eq!(measure([2.0, 0.0], [2.0, 2.0]), (2.0, -90.0));
+        assert_eq!(measure([2.0, 0.0], [0.0, 0.0]).1.abs(), 180.0);
+    }
+
+    #[test]
+    fn cached_stats() {
+        let mut image = ramp();
+        let mut tool = RoiTool::default();
+        assert_eq!(tool.stats(&image, 1), None);
+        tool.roi = Some(Roi::Rect {
+            min: [0.0, 0.0],
+            max: [1.0, 1.0],
+        });
+        assert_eq!(tool.stats(&image, 1).unwrap().max[0], 0);
+        // the same generation is the same image
+        image.pixels[0] = Color32::WHITE;
+        assert_eq!(tool.stats(&image, 1).unwrap().max[0], 0);
+        assert_eq!(tool.stats(&image, 2).unwrap().max[0], 255);
+        tool.roi = Some(Roi::Rect {
+            min: [1.0, 0.0],
+            max: [2.0, 1.0],
+        });
+        assert_eq!(tool.stats(&image, 2).unwrap().max[0], 40);
+        tool.reset();
+        assert_eq!(tool.stats(&image, 2), None);
+    }
+}
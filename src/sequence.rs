@@ -0,0 +1,422 @@
+/*
+ * Playing a numbered image sequence, a directory of images or an animated gif
+ * or png like a video.
+ *
+ * Animations are decoded up front with the delays of their frames. Sequence frames
+ * are decoded on background threads a few frames ahead of the one being
+ * shown so playback doesn't stall on decoding, and frames behind are dropped to
+ * bound memory. If a frame isn't ready in time playback waits for it rather than
+ * skipping it.
+ */
+
+use crate::browser::{list_images, sort_files, SortBy};
+use crate::image_io::{load_animation, load_image, save_image, AnimationFrame};
+use crate::job::{Job, Progress};
+use crate::utility::Image;
+use eframe::egui;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Substitute the frame number for the first %d or %0Nd in the pattern,
+/// None if there isn't one.
+pub fn frame_path(pattern: &str, number: usize) -> Option<String> {
+    let start = pattern.find('%')?;
+    let rest = &pattern[start + 1..];
+    let end = rest.find('d')?;
+    let spec = &rest[..end];
+    if !spec.chars().all(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+    let width = spec.parse::<usize>().unwrap_or(0);
+    Some(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        number,
+        &rest[end + 1..],
+        width = width
+    ))
+}
+
+/// Files of the sequence in order, from the images in a directory sorted by name
+/// or from a pattern like frames/frame_%04d.png numbered from 0 or 1 until one is missing.
+pub fn list_sequence(source: &str, progress: &Progress) -> Result<Vec<PathBuf>, String> {
+    if Path::new(source).is_dir() {
+        let mut files = list_images(source, progress)?;
+        sort_files(&mut files, SortBy::Name);
+        // frames are decoded by the image crate
+        return Ok(files
+            .into_iter()
+            .map(|file| file.path)
+            .filter(|path| image::ImageFormat::from_path(path).is_ok())
+            .collect());
+    }
+    let path = |number| frame_path(source, number).map(PathBuf::from);
+    let first = path(0).ok_or(format!("{} isn't a directory or a pattern with %d", source))?;
+    let mut number = if first.is_file() { 0 } else { 1 };
+    let mut frames = Vec::new();
+    while let Some(frame) = path(number).filter(|frame| frame.is_file()) {
+        if progress.is_cancelled() {
+            return Err("cancelled".to_string());
+        }
+        frames.push(frame);
+        number += 1;
+        progress.set_rows(frames.len() as u64);
+    }
+    if frames.is_empty() {
+        return Err(format!("no frames found for {}", source));
+    }
+    Ok(frames)
+}
+
+/// Frames being played, either files decoded as they are needed or the
+/// decoded frames of an animation.
+pub enum Frames {
+    Files(Vec<PathBuf>),
+    Animation {
+        filename: String,
+        frames: Vec<AnimationFrame>,
+    },
+}
+
+impl Frames {
+    pub fn len(&self) -> usize {
+        match self {
+            Frames::Files(files) => files.len(),
+            Frames::Animation { frames, .. } => frames.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn filename(&self, ind: usize) -> String {
+        match self {
+            Frames::Files(files) => files[ind].to_string_lossy().into_owned(),
+            Frames::Animation { filename, .. } => filename.clone(),
+        }
+    }
+}
+
+/// Frames from an animated gif or png file, or a directory or pattern of files
+pub fn open_frames(source: &str, progress: &Progress) -> Result<Frames, String> {
+    if Path::new(source).is_file() {
+        let frames = load_animation(source, progress)?;
+        Ok(Frames::Animation {
+            filename: source.to_string(),
+            frames,
+        })
+    } else {
+        Ok(Frames::Files(list_sequence(source, progress)?))
+    }
+}
+
+pub struct SequencePlayer {
+    source: String,
+    frames: Frames,
+    listing: Option<Job<Frames>>,
+    /// frame to show, it is shown once it has been decoded
+    current: usize,
+    /// frame that was last returned for display
+    shown: Option<usize>,
+    playing: bool,
+    looping: bool,
+    fps: f32,
+    /// play animations with the delays of their frames rather than fps
+    file_delays: bool,
+    /// time the shown frame is due to be replaced by the next
+    next_time: f64,
+    /// files decoded ahead of the current one
+    prefetch: usize,
+    decoded: HashMap<usize, Image>,
+    decoding: Vec<(usize, Job<Image>)>,
+    /// files that couldn't be decoded, so they aren't tried again
+    failed: HashSet<usize>,
+    /// where the current frame is exported to
+    export_filename: String,
+    status: String,
+    error: Option<String>,
+}
+
+impl Default for SequencePlayer {
+    fn default() -> Self {
+        Self {
+            source: "data".to_string(),
+            frames: Frames::Files(Vec::new()),
+            listing: None,
+            current: 0,
+            shown: None,
+            playing: false,
+            looping: true,
+            fps: 10.0,
+            file_delays: true,
+            next_time: 0.0,
+            prefetch: 8,
+            decoded: HashMap::new(),
+            decoding: Vec::new(),
+            failed: HashSet::new(),
+            export_filename: "frame.png".to_string(),
+            status: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl SequencePlayer {
+    fn reset(&mut self, frames: Frames) {
+        for (_, job) in self.decoding.drain(..) {
+            job.cancel();
+        }
+        self.decoded.clear();
+        self.failed.clear();
+        self.frames = frames;
+        self.current = 0;
+        self.shown = None;
+    }
+
+    /// Index of the frame after ind, None at the end unless looping
+    fn next(&self, ind: usize) -> Option<usize> {
+        if ind + 1 < self.frames.len() {
+            Some(ind + 1)
+        } else if self.looping && !self.frames.is_empty() {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// The frame if it has been decoded
+    fn frame(&self, ind: usize) -> Option<&Image> {
+        match &self.frames {
+            Frames::Files(_) => self.decoded.get(&ind),
+            Frames::Animation { frames, .. } => frames.get(ind).map(|frame| &frame.image),
+        }
+    }
+
+    /// Seconds the frame is shown for
+    fn period(&self, ind: usize) -> f64 {
+        match &self.frames {
+            Frames::Animation { frames, .. } if self.file_delays => frames[ind].delay,
+            _ => 1.0 / self.fps as f64,
+        }
+    }
+
+    /// Collect decoded files, drop the ones that have been passed and start
+    /// decoding the ones coming up.
+    fn update_frames(&mut self) {
+        let files = match &self.frames {
+            Frames::Files(files) => files,
+            Frames::Animation { .. } => return,
+        };
+        let mut ind = 0;
+        while ind < self.decoding.len() {
+            let result = match self.decoding[ind].1.poll() {
+                Some(result) => result,
+                None => {
+                    ind += 1;
+                    continue;
+                }
+            };
+            let (frame, _) = self.decoding.swap_remove(ind);
+            match result {
+                Ok(image) => {
+                    self.decoded.insert(frame, image);
+                }
+                Err(why) => {
+                    self.failed.insert(frame);
+                    self.error = Some(why);
+                    self.playing = false;
+                }
+            }
+        }
+
+        let mut wanted = vec![self.current];
+        while wanted.len() <= self.prefetch {
+            match self.next(*wanted.last().unwrap()) {
+                Some(next) if !wanted.contains(&next) => wanted.push(next),
+                _ => break,
+            }
+        }
+        // keep the previous frame too so stepping back is quick
+        let previous = self.current.checked_sub(1);
+        self.decoded
+            .retain(|frame, _| wanted.contains(frame) || Some(*frame) == previous);
+        self.decoding.retain(|(frame, job)| {
+            let keep = wanted.contains(frame);
+            if !keep {
+                job.cancel();
+            }
+            keep
+        });
+        for frame in wanted {
+            if self.decoded.contains_key(&frame)
+                || self.failed.contains(&frame)
+                || self.decoding.iter().any(|(f, _)| *f == frame)
+            {
+                continue;
+            }
+            let filename = files[frame].to_string_lossy().into_owned();
+            let job = Job::spawn(format!("frame {}", frame), move |progress| {
+                load_image(&filename, progress)
+            });
+            self.decoding.push((frame, job));
+        }
+    }
+
+    /// Playback controls and timeline, returns the filename and image of a frame
+    /// to show when the frame changes.
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<(String, Image)> {
+        if let Some(result) = self.listing.as_ref().and_then(|job| job.poll()) {
+            match result {
+                Ok(frames) => {
+                    self.reset(frames);
+                    self.error = None;
+                }
+                Err(why) => self.error = Some(why),
+            }
+            self.listing = None;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("animation, directory or pattern");
+            ui.text_edit_singleline(&mut self.source);
+            if ui.button("open").clicked {
+                let source = self.source.clone();
+                self.listing = Some(Job::spawn(format!("opening {}", source), move |progress| {
+                    open_frames(&source, progress)
+                }));
+            }
+        });
+        if let Some(job) = &self.listing {
+            job.ui(ui);
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        if self.frames.is_empty() {
+            ui.label("no frames");
+            return None;
+        }
+
+        let time = ui.input().time;
+        let last = self.frames.len() - 1;
+        ui.horizontal(|ui| {
+            if ui.button("|<").clicked {
+                self.current = 0;
+            }
+            if ui.button("<").clicked {
+                self.playing = false;
+                self.current = self.current.saturating_sub(1);
+            }
+            let play = if self.playing { "pause" } else { "play" };
+            if ui.button(play).clicked {
+                self.playing = !self.playing;
+                if self.playing && self.current == last && !self.looping {
+                    self.current = 0;
+                }
+                self.next_time = time;
+            }
+            if ui.button(">").clicked {
+                self.playing = false;
+                self.current = (self.current + 1).min(last);
+            }
+            if ui.button(">|").clicked {
+                self.current = last;
+            }
+            ui.checkbox(&mut self.looping, "loop");
+        });
+        ui.add(egui::Slider::usize(&mut self.current, 0..=last).text("frame"));
+        match self.frames {
+            Frames::Files(_) => {
+                ui.add(egui::Slider::f32(&mut self.fps, 0.5..=120.0).text("fps"));
+                ui.add(egui::Slider::usize(&mut self.prefetch, 1..=64).text("prefetch frames"));
+            }
+            Frames::Animation { .. } => {
+                ui.checkbox(&mut self.file_delays, "frame delays from the file");
+                if !self.file_delays {
+                    ui.add(egui::Slider::f32(&mut self.fps, 0.5..=120.0).text("fps"));
+                }
+            }
+        }
+
+        // advance once the shown frame has been up long enough and the next is ready
+        if self.playing && self.shown == Some(self.current) && time >= self.next_time {
+            match self.next(self.current) {
+                Some(next) if self.frame(next).is_some() => self.current = next,
+                Some(_) => (),
+                None => self.playing = false,
+            }
+        }
+        self.update_frames();
+
+        let mut chosen = None;
+        if self.shown != Some(self.current) {
+            if let Some(image) = self.frame(self.current).cloned() {
+                let period = self.period(self.current);
+                // catch up after a stall rather than rushing through frames
+                self.next_time = if time - self.next_time > period {
+                    time + period
+                } else {
+                    self.next_time + period
+                };
+                chosen = Some((self.frames.filename(self.current), image));
+                self.shown = Some(self.current);
+            }
+        }
+        let decoded = match self.frames {
+            Frames::Files(_) => format!(", {} decoded", self.decoded.len()),
+            Frames::Animation { .. } => String::new(),
+        };
+        ui.label(format!(
+            "{} / {}{}",
+            self.current + 1,
+            self.frames.len(),
+            decoded
+        ));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.export_filename);
+            if ui.button("save frame").clicked {
+                self.status = match self.frame(self.current) {
+                    Some(image) => match save_image(image, &self.export_filename) {
+                        Ok(()) => format!("saved {}", self.export_filename),
+                        Err(why) => why,
+                    },
+                    None => "the frame hasn't been decoded yet".to_string(),
+                };
+            }
+        });
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+        if self.playing || !self.decoding.is_empty() {
+            ui.ctx().request_repaint();
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_frames_arent_decoded_again() {
+        let dir = std::env::temp_dir().join(format!("egui_image_sequence_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.png");
+        std::fs::write(&path, b"not a png").unwrap();
+
+        let mut player = SequencePlayer::default();
+        player.reset(Frames::Files(vec![path]));
+        let start = std::time::Instant::now();
+        while player.failed.is_empty() && start.elapsed().as_secs() < 10 {
+            player.update_frames();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(player.failed.contains(&0));
+        assert!(player.error.is_some());
+        player.update_frames();
+        assert!(player.decoding.is_empty());
+    }
+}
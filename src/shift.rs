@@ -0,0 +1,264 @@
+/*
+ * Translate the image contents in place, whole pixel shifts rotate each row and
+ * then the rows, sub-pixel shifts interpolate between neighbors afterwards.
+ *
+ * A shift repeated every frame keeps the image it started from and resamples it
+ * by the total offset, since interpolating the already interpolated image again
+ * would blur it a little more each frame.
+ */
+
+use crate::edge::EdgeMode;
+use crate::utility::Image;
+use eframe::egui::Color32;
+
+/// Integer linear interpolation from a to b, fr is in 1/256ths
+fn blend(a: Color32, b: Color32, fr: u32) -> Color32 {
+    let a = a.to_array();
+    let b = b.to_array();
+    let channel = |i: usize| ((a[i] as u32 * (256 - fr) + b[i] as u32 * fr + 128) >> 8) as u8;
+    Color32::from_rgba_premultiplied(channel(0), channel(1), channel(2), channel(3))
+}
+
+/// Shift runs of stride elements (single pixels within a row, or whole rows)
+/// by a number of runs, positive shifts move towards the end of the data.
+fn shift_runs(data: &mut [Color32], stride: usize, shift: i64, edge: EdgeMode) {
+    let len = data.len() / stride;
+    if len == 0 || shift == 0 {
+        return;
+    }
+    if edge == EdgeMode::Wrap {
+        let shift = shift.rem_euclid(len as i64) as usize;
+        data.rotate_right(shift * stride);
+        return;
+    }
+
+    let num = (shift.unsigned_abs() as usize).min(len);
+    // the runs that are uncovered by the shift
+    let uncovered = if shift > 0 {
+        data.copy_within(0..(len - num) * stride, num * stride);
+        // the first run still holds the original edge
+        1..num
+    } else {
+        data.copy_within(num * stride.., 0);
+        // and here the last run does
+        (len - num)..(len - 1)
+    };
+    let edge_run = if shift > 0 { 0 } else { len - 1 };
+    for run in uncovered {
+        for k in 0..stride {
+            data[run * stride + k] = match edge {
+                EdgeMode::Fill(color) => color,
+                _ => data[edge_run * stride + k],
+            };
+        }
+    }
+    if let EdgeMode::Fill(color) = edge {
+        for k in 0..stride {
+            data[edge_run * stride + k] = color;
+        }
+    }
+}
+
+/// Blend each run with its neighboring run, the one before for positive fr and the one
+/// after for negative, fr is in 1/256ths. The runs are visited in the order that leaves
+/// each neighbor unchanged until it has been used.
+fn blend_runs(data: &mut [Color32], stride: usize, fr: i32, edge: EdgeMode) {
+    let len = data.len() / stride;
+    if len == 0 || fr == 0 {
+        return;
+    }
+    // the run at the edge has its neighbor outside of the data
+    let (edge_run, opposite_run) = if fr > 0 { (0, len - 1) } else { (len - 1, 0) };
+    let outside: Vec<Color32> = match edge {
+        EdgeMode::Wrap => data[opposite_run * stride..(opposite_run + 1) * stride].to_vec(),
+        EdgeMode::Clamp => data[edge_run * stride..(edge_run + 1) * stride].to_vec(),
+        EdgeMode::Fill(color) => vec![color; stride],
+    };
+    let weight = fr.unsigned_abs();
+    for i in 0..len {
+        // runs in visiting order
+        let run = if fr > 0 { len - 1 - i } else { i };
+        for k in 0..stride {
+            let neighbor = if run == edge_run {
+                outside[k]
+            } else if fr > 0 {
+                data[(run - 1) * stride + k]
+            } else {
+                data[(run + 1) * stride + k]
+            };
+            let ind = run * stride + k;
+            data[ind] = blend(data[ind], neighbor, weight);
+        }
+    }
+}
+
+impl Image {
+    /// Shift by whole pixels wrapping around the edges, positive is right and down
+    pub fn shift(&mut self, shift_x: i32, shift_y: i32) {
+        self.shift_edge(shift_x, shift_y, EdgeMode::Wrap);
+    }
+
+    pub fn shift_edge(&mut self, shift_x: i32, shift_y: i32, edge: EdgeMode) {
+        let width = self.size.0;
+        if width == 0 {
+            return;
+        }
+        if shift_x != 0 {
+            for row in self.pixels.chunks_mut(width) {
+                shift_runs(row, 1, shift_x as i64, edge);
+            }
+        }
+        shift_runs(&mut self.pixels, width, shift_y as i64, edge);
+    }
+
+    /// Shift by fractions of a pixel, the whole part is shifted as in shift_edge
+    /// and the remainder linearly interpolated between neighboring pixels.
+    pub fn shift_subpixel(&mut self, shift_x: f32, shift_y: f32, edge: EdgeMode) {
+        let width = self.size.0;
+        if width == 0 {
+            return;
+        }
+        let whole_x = shift_x.trunc();
+        let whole_y = shift_y.trunc();
+        self.shift_edge(whole_x as i32, whole_y as i32, edge);
+        let fx = ((shift_x - whole_x) * 256.0).round() as i32;
+        let fy = ((shift_y - whole_y) * 256.0).round() as i32;
+        if fx != 0 {
+            for row in self.pixels.chunks_mut(width) {
+                blend_runs(row, 1, fx, edge);
+            }
+        }
+        blend_runs(&mut self.pixels, width, fy, edge);
+    }
+}
+
+/// Shifts an image a little further every frame
+#[derive(Default)]
+pub struct RunningShift {
+    /// the image before it was shifted
+    base: Option<Image>,
+    offset: (f32, f32),
+    /// generation of the image when it was last shifted, any other means it changed
+    generation: Option<u64>,
+}
+
+impl RunningShift {
+    /// Shift the image by step more than the last time, starting over from the
+    /// image as it is if it has changed since, and bump the generation.
+    pub fn step(
+        &mut self,
+        image: &mut Image,
+        generation: &mut u64,
+        step: (f32, f32),
+        edge: EdgeMode,
+    ) {
+        let unchanged = self.generation == Some(*generation)
+            && self
+                .base
+                .as_ref()
+                .is_some_and(|base| base.size == image.size);
+        if !unchanged {
+            self.base = Some(image.clone());
+            self.offset = (0.0, 0.0);
+        }
+        let advance = |offset: f32, step: f32, len: usize| {
+            let len = len.max(1) as f32;
+            match edge {
+                EdgeMode::Wrap => (offset + step).rem_euclid(len),
+                // beyond the size all of the image is edge
+                _ => (offset + step).clamp(-len, len),
+            }
+        };
+        self.offset = (
+            advance(self.offset.0, step.0, image.size.0),
+            advance(self.offset.1, step.1, image.size.1),
+        );
+        if let Some(base) = &self.base {
+            image.pixels.copy_from_slice(&base.pixels);
+        }
+        image.shift_subpixel(self.offset.0, self.offset.1, edge);
+        *generation += 1;
+        self.generation = Some(*generation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(values: &[u8], width: usize) -> Image {
+        Image {
+            size: (width, values.len() / width),
+            pixels: values.iter().map(|v| Color32::from_gray(*v)).collect(),
+        }
+    }
+
+    fn values(image: &Image) -> Vec<u8> {
+        image.pixels.iter().map(|p| p.r()).collect()
+    }
+
+    #[test]
+    fn whole_pixels() {
+        let mut image = gray(&[1, 2, 3, 4, 5, 6], 3);
+        image.shift(1, 0);
+        assert_eq!(values(&image), vec![3, 1, 2, 6, 4, 5]);
+        image.shift(-1, 1);
+        assert_eq!(values(&image), vec![4, 5, 6, 1, 2, 3]);
+        // more than the size wraps around again
+        image.shift(7, -3);
+        assert_eq!(values(&image), vec![3, 1, 2, 6, 4, 5]);
+    }
+
+    #[test]
+    fn edges() {
+        let row = [10, 20, 30, 40];
+        let mut clamped = gray(&row, 4);
+        clamped.shift_edge(2, 0, EdgeMode::Clamp);
+        assert_eq!(values(&clamped), vec![10, 10, 10, 20]);
+        let mut clamped = gray(&row, 4);
+        clamped.shift_edge(-1, 0, EdgeMode::Clamp);
+        assert_eq!(values(&clamped), vec![20, 30, 40, 40]);
+        let mut filled = gray(&row, 4);
+        filled.shift_edge(-2, 0, EdgeMode::Fill(Color32::from_gray(0)));
+        assert_eq!(values(&filled), vec![30, 40, 0, 0]);
+        let mut filled = gray(&row, 4);
+        filled.shift_edge(9, 0, EdgeMode::Fill(Color32::from_gray(0)));
+        assert_eq!(values(&filled), vec![0; 4]);
+    }
+
+    #[test]
+    fn subpixel() {
+        let mut image = gray(&[0, 100, 200, 100], 4);
+        image.shift_subpixel(0.5, 0.0, EdgeMode::Wrap);
+        assert_eq!(values(&image), vec![50, 50, 150, 150]);
+        let mut image = gray(&[0, 100, 200, 100], 4);
+        image.shift_subpixel(-1.25, 0.0, EdgeMode::Clamp);
+        assert_eq!(values(&image), vec![125, 175, 100, 100]);
+        // vertical shifts blend whole rows
+        let mut image = gray(&[0, 0, 200, 200], 2);
+        image.shift_subpixel(0.0, 0.5, EdgeMode::Fill(Color32::from_gray(0)));
+        assert_eq!(values(&image), vec![0, 0, 100, 100]);
+    }
+
+    #[test]
+    fn running_shift_doesnt_blur() {
+        let original = [0, 255, 0, 0, 0, 0, 0, 0];
+        let mut image = gray(&original, 8);
+        let mut running = RunningShift::default();
+        let mut generation = 0;
+        for _ in 0..16 {
+            running.step(&mut image, &mut generation, (0.5, 0.0), EdgeMode::Wrap);
+        }
+        assert_eq!(generation, 16);
+        // sixteen half pixel steps are a whole turn
+        assert_eq!(values(&image), original.to_vec());
+        running.step(&mut image, &mut generation, (0.5, 0.0), EdgeMode::Wrap);
+        assert_eq!(values(&image), vec![0, 128, 128, 0, 0, 0, 0, 0]);
+
+        // an edit starts over from the edited image
+        let mut edited = gray(&[0, 0, 0, 255, 0, 0, 0, 0], 8);
+        generation += 1;
+        running.step(&mut edited, &mut generation, (1.0, 0.0), EdgeMode::Wrap);
+        assert_eq!(values(&edited), vec![0, 0, 0, 0, 255, 0, 0, 0]);
+    }
+}
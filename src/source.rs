@@ -0,0 +1,242 @@
+/*
+ * Live data sources for the plotter.
+ *
+ * A source is given on the command line in place of a csv filename:
+ *
+ *   csv_plot_image udp://0.0.0.0:9000
+ *   csv_plot_image udp+f64://0.0.0.0:9000
+ *   csv_plot_image tcp://localhost:9000
+ *
+ * Each udp datagram or tcp line is parsed with the same row logic as load_csv,
+ * with udp+f64 each datagram is instead one row of packed little-endian f64 values.
+ * The rows are sent back to the ui thread over a channel.
+ *
+ * Test locally with a loopback sender like:
+ *
+ *   echo "1.0, 2.0, 3.0" | nc -u -w0 127.0.0.1 9000
+ */
+
+use crate::csv_plot::parse_row;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Max wait between retries after a udp receive error
+const MAX_BACKOFF: Duration = Duration::from_millis(1000);
+
+/// How the payload of a udp datagram is decoded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WireFormat {
+    /// Comma separated text, one row per line
+    Text,
+    /// Packed little-endian f64 values, one row per datagram
+    F64,
+}
+
+pub enum Source {
+    File(String),
+    Udp(String, WireFormat),
+    Tcp(String),
+}
+
+impl Source {
+    pub fn new(name: &str) -> Self {
+        if let Some(addr) = name.strip_prefix("udp://") {
+            Source::Udp(addr.to_string(), WireFormat::Text)
+        } else if let Some(addr) = name.strip_prefix("udp+f64://") {
+            Source::Udp(addr.to_string(), WireFormat::F64)
+        } else if let Some(addr) = name.strip_prefix("tcp://") {
+            Source::Tcp(addr.to_string())
+        } else {
+            Source::File(name.to_string())
+        }
+    }
+
+    /// Start a thread receiving rows from a network source, returns None for file sources
+    pub fn spawn(&self) -> Option<Receiver<Vec<f64>>> {
+        let (sender, receiver) = channel();
+        match self {
+            Source::File(_) => return None,
+            Source::Udp(addr, format) => {
+                let addr = addr.clone();
+                let format = *format;
+                thread::spawn(move || receive_udp(&addr, format, sender));
+            }
+            Source::Tcp(addr) => {
+                let addr = addr.clone();
+                thread::spawn(move || receive_tcp(&addr, sender));
+            }
+        }
+        Some(receiver)
+    }
+}
+
+/// Parse one line of text into a row, the first row received fixes the number of columns
+fn parse_line(line: &str, num_columns: &mut usize) -> Option<Vec<f64>> {
+    let fields: Vec<&str> = line.trim().split(',').collect();
+    if *num_columns == 0 {
+        *num_columns = fields.len();
+    }
+    match parse_row(&fields, *num_columns) {
+        Ok(row) => row,
+        Err(err) => {
+            // most likely a header line
+            println!("skipping '{}': {}", line.trim(), err);
+            None
+        }
+    }
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<f64> {
+    bytes
+        .chunks_exact(8)
+        .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .collect()
+}
+
+fn receive_udp(addr: &str, format: WireFormat, sender: Sender<Vec<f64>>) {
+    let socket = match UdpSocket::bind(addr) {
+        Ok(socket) => socket,
+        Err(why) => {
+            println!("couldn't bind udp {}: {}", addr, why);
+            return;
+        }
+    };
+    println!("listening on udp {}", addr);
+    receive_datagrams(&socket, format, sender);
+}
+
+/// Parse datagrams from the socket into rows until the receiver is dropped
+fn receive_datagrams(socket: &UdpSocket, format: WireFormat, sender: Sender<Vec<f64>>) {
+    let mut num_columns = 0;
+    let mut buf = [0; 65536];
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(why) => {
+                // wait longer each time so a persistent error doesn't spin
+                println!("udp receive error {}, retrying in {:?}", why, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        backoff = Duration::from_millis(10);
+        let rows = match format {
+            WireFormat::Text => String::from_utf8_lossy(&buf[..len])
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| parse_line(line, &mut num_columns))
+                .collect(),
+            WireFormat::F64 => {
+                // only a whole number of values can set the number of columns
+                let whole = len % 8 == 0 && len > 0;
+                if num_columns == 0 && whole {
+                    num_columns = len / 8;
+                }
+                if whole && len / 8 == num_columns {
+                    vec![parse_binary(&buf[..len])]
+                } else {
+                    println!("unexpected binary len {} {}", num_columns, len);
+                    Vec::new()
+                }
+            }
+        };
+        for row in rows {
+            if sender.send(row).is_err() {
+                // the ui has gone away
+                return;
+            }
+        }
+    }
+}
+
+fn receive_tcp(addr: &str, sender: Sender<Vec<f64>>) {
+    loop {
+        let stream = match TcpStream::connect(addr) {
+            Ok(stream) => stream,
+            Err(why) => {
+                println!("couldn't connect to tcp {}: {}, retrying", addr, why);
+                thread::sleep(Duration::from_millis(1000));
+                continue;
+            }
+        };
+        println!("connected to tcp {}", addr);
+
+        let mut num_columns = 0;
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(why) => {
+                    println!("tcp receive error {}", why);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(row) = parse_line(&line, &mut num_columns) {
+                if sender.send(row).is_err() {
+                    return;
+                }
+            }
+        }
+        println!("tcp {} disconnected", addr);
+        thread::sleep(Duration::from_millis(1000));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A receiving thread on an ephemeral loopback port and a socket connected to it
+    fn loopback(format: WireFormat) -> (UdpSocket, Receiver<Vec<f64>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (sender, receiver) = channel();
+        thread::spawn(move || receive_datagrams(&socket, format, sender));
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(addr).unwrap();
+        (client, receiver)
+    }
+
+    fn next_row(receiver: &Receiver<Vec<f64>>) -> Vec<f64> {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn udp_text_rows() {
+        let (client, receiver) = loopback(WireFormat::Text);
+        client.send(b"t, value\n0.5, 1.0\n1.5, -2.0\n").unwrap();
+        client.send(b"2.5, 3.0").unwrap();
+        assert_eq!(next_row(&receiver), vec![0.5, 1.0]);
+        assert_eq!(next_row(&receiver), vec![1.5, -2.0]);
+        assert_eq!(next_row(&receiver), vec![2.5, 3.0]);
+    }
+
+    #[test]
+    fn udp_binary_rows() {
+        let (client, receiver) = loopback(WireFormat::F64);
+        let pack = |row: &[f64]| -> Vec<u8> {
+            row.iter()
+                .flat_map(|val| val.to_le_bytes().to_vec())
+                .collect()
+        };
+        // malformed datagrams before the first row don't set the number of columns
+        client.send(&[0; 12]).unwrap();
+        client.send(&[]).unwrap();
+        // all zero bytes are also valid utf-8
+        client.send(&pack(&[0.0, 0.0])).unwrap();
+        // a short datagram is skipped
+        client.send(&[0; 12]).unwrap();
+        // as is a row of a different width
+        client.send(&pack(&[1.0, 2.0, 3.0])).unwrap();
+        client.send(&pack(&[1.5, -2.0])).unwrap();
+        assert_eq!(next_row(&receiver), vec![0.0, 0.0]);
+        assert_eq!(next_row(&receiver), vec![1.5, -2.0]);
+    }
+}
@@ -1,4 +1,6 @@
 use eframe::{egui, epi};
+use std::collections::HashMap;
+use std::hash::Hash;
 // use std::fs::File;
 
 // TODO(lucasw) create a library that is just for image pixels manipulation
@@ -34,41 +36,82 @@ impl TexMngr {
     }
 }
 
-pub struct Image {
-    pub size: (usize, usize),
-    pub pixels: Vec<egui::Color32>,
+/// Texture manager for many small images like thumbnails, holding a texture per key.
+/// The least recently used textures are freed once there are more than capacity.
+pub struct KeyedTexMngr<K> {
+    /// texture and the use count when it was last used
+    textures: HashMap<K, (egui::TextureId, u64)>,
+    uses: u64,
+    pub capacity: usize,
 }
 
-// TODO(lucasw) move this into library/module, and make it generic on any
-// vector with a width and height and default value supplied.
-impl Image {
-    pub fn shift(&mut self, mut shift_x: i32, mut shift_y: i32) {
-        let mut shifted = vec![egui::Color32::BLUE; self.pixels.len()];
-
-        let width = self.size.0;
-        while shift_x < 0 {
-            shift_x += width as i32;
+impl<K: Clone + Eq + Hash> KeyedTexMngr<K> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            textures: HashMap::new(),
+            uses: 0,
+            capacity,
         }
-        let shift_x = shift_x as usize;
+    }
 
-        let height = self.size.1;
-        while shift_y < 0 {
-            shift_y += height as i32;
+    pub fn contains(&self, key: &K) -> bool {
+        self.textures.contains_key(key)
+    }
+
+    /// The texture for the key if it has been allocated, marking it as used
+    pub fn get(&mut self, key: &K) -> Option<egui::TextureId> {
+        self.uses += 1;
+        let uses = self.uses;
+        self.textures.get_mut(key).map(|(texture_id, used)| {
+            *used = uses;
+            *texture_id
+        })
+    }
+
+    /// Allocate a texture for the image under the key, replacing any it had before
+    pub fn insert(
+        &mut self,
+        frame: &mut epi::Frame<'_>,
+        key: K,
+        image: &Image,
+    ) -> Option<egui::TextureId> {
+        let tex_allocator = frame.tex_allocator().as_mut()?;
+        let texture_id = tex_allocator.alloc_srgba_premultiplied(image.size, &image.pixels);
+        self.uses += 1;
+        if let Some((old, _)) = self.textures.insert(key, (texture_id, self.uses)) {
+            tex_allocator.free(old);
+        }
+        while self.textures.len() > self.capacity.max(1) {
+            let oldest = self
+                .textures
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some((old, _)) = oldest.and_then(|key| self.textures.remove(&key)) {
+                tex_allocator.free(old);
+            }
         }
-        let shift_y = shift_y as usize;
+        Some(texture_id)
+    }
 
-        for y in 0..height {
-            for x in 0..width {
-                let dst_x = (x + shift_x) % width;
-                let dst_y = (y + shift_y) % height;
-                let dst_ind = dst_y * width + dst_x;
-                let ind = y * width + x;
-                shifted[dst_ind] = self.pixels[ind];
+    pub fn clear(&mut self, frame: &mut epi::Frame<'_>) {
+        if let Some(tex_allocator) = frame.tex_allocator().as_mut() {
+            for (_, (texture_id, _)) in self.textures.drain() {
+                tex_allocator.free(texture_id);
             }
         }
-        self.pixels = shifted;
     }
+}
+
+#[derive(Clone)]
+pub struct Image {
+    pub size: (usize, usize),
+    pub pixels: Vec<egui::Color32>,
+}
 
+// TODO(lucasw) move this into library/module, and make it generic on any
+// vector with a width and height and default value supplied.
+impl Image {
     /*
     if false {
         for x in 0..255 {